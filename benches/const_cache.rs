@@ -0,0 +1,70 @@
+//! times the per-slab constants of allocator construction encrypted fresh against the same constants served by the Keys constant cache, single-threaded and from four threads sharing one Keys; run with `cargo bench --bench const_cache`.
+//! the threaded run starts from a cold cache, so it also shows concurrent misses encrypting side by side instead of queuing on the cache lock.
+//! it then times whole allocator builds with the table cache off, cold and warm; the warm build reuses every index and offset table the cold one encrypted.
+
+use cryptmalloc::{keys::DEFAULT_TABLE_CACHE_LIMIT, CryptMalloc, Keys};
+use std::time::{Duration, Instant};
+
+const SLABS: usize = 16;
+const THREADS: usize = 4;
+const ARENA: u64 = 4096;
+
+// the constants CryptMalloc::build_slab asks for per tier.
+fn slab_constants(keys: &Keys, cached: bool) {
+    if cached {
+        let _ = keys.enc_false_cached();
+        let _ = keys.enc_true_cached();
+        let _ = keys.enc_u32_cached(0);
+        let _ = keys.enc_u64_cached(0);
+    } else {
+        let _ = keys.enc_false();
+        let _ = keys.enc_true();
+        let _ = keys.enc_zero_u32();
+        let _ = keys.enc_zero_u64();
+    }
+}
+
+fn timed(body: impl FnOnce()) -> Duration {
+    let start = Instant::now();
+    body();
+    start.elapsed()
+}
+
+fn build(keys: &Keys) {
+    let _ = CryptMalloc::builder(ARENA)
+        .keys(keys.clone())
+        .build()
+        .unwrap();
+}
+
+fn main() {
+    let keys = Keys::new();
+
+    let fresh = timed(|| (0..SLABS).for_each(|_| slab_constants(&keys, false)));
+    let cached = timed(|| (0..SLABS).for_each(|_| slab_constants(&keys, true)));
+    assert_eq!(keys.const_cache_misses(), 4);
+
+    let shared = keys.clone();
+    let threaded = timed(|| {
+        std::thread::scope(|scope| {
+            for _ in 0..THREADS {
+                scope.spawn(|| (0..SLABS / THREADS).for_each(|_| slab_constants(&shared, true)));
+            }
+        })
+    });
+
+    println!("fresh constants:  {fresh:?} for {SLABS} slabs");
+    println!("cached constants: {cached:?} for {SLABS} slabs");
+    println!("cached, {THREADS} threads, cold cache: {threaded:?} for {SLABS} slabs");
+
+    let keys = Keys::new();
+    keys.set_table_cache_limit(0);
+    let uncached = timed(|| build(&keys));
+    keys.set_table_cache_limit(DEFAULT_TABLE_CACHE_LIMIT);
+    let cold = timed(|| build(&keys));
+    let warm = timed(|| build(&keys));
+
+    println!("CryptMalloc build, table cache off: {uncached:?}");
+    println!("CryptMalloc build, cold table cache: {cold:?}");
+    println!("CryptMalloc build, warm table cache: {warm:?}");
+}
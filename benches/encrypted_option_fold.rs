@@ -0,0 +1,76 @@
+//! compares a combine_with chain against EncryptedOption::fold over the six candidates CryptMalloc::allocate merges; run with `cargo bench --bench encrypted_option_fold`.
+//! a counting global allocator records the peak heap growth of each variant alongside its wall-clock time.
+
+use cryptmalloc::{EncryptedOption, EncryptedPtr, Keys};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
+};
+
+struct CountingAlloc;
+
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let live = LIVE.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK.fetch_max(live, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+// runs `f`, returning the wall-clock time and the peak heap growth above the starting level.
+fn measure(f: impl FnOnce()) -> (std::time::Duration, usize) {
+    let baseline = LIVE.load(Ordering::Relaxed);
+    PEAK.store(baseline, Ordering::Relaxed);
+    let start = Instant::now();
+    f();
+    let elapsed = start.elapsed();
+    (elapsed, PEAK.load(Ordering::Relaxed) - baseline)
+}
+
+fn main() {
+    let keys = Keys::new();
+    let candidates: Vec<EncryptedOption<EncryptedPtr>> = (0..6u64)
+        .map(|i| EncryptedOption {
+            value: EncryptedPtr::new(keys.enc_u64(i * 16)),
+            is_some: if i == 3 {
+                keys.enc_true()
+            } else {
+                keys.enc_false()
+            },
+        })
+        .collect();
+    let init = || EncryptedOption::none(EncryptedPtr::new(keys.enc_zero_u64()), keys.enc_false());
+
+    let chain_init = init();
+    let (chain_time, chain_peak) = measure(|| {
+        let mut result = chain_init;
+        for candidate in &candidates {
+            result = result.combine_with(candidate);
+        }
+        assert_eq!(keys.dec_u64(&result.value.0), 48);
+    });
+
+    let fold_init = init();
+    let (fold_time, fold_peak) = measure(|| {
+        let result = EncryptedOption::fold(&candidates, fold_init);
+        assert_eq!(keys.dec_u64(&result.value.0), 48);
+    });
+
+    println!("combine_with chain: {chain_time:?}, peak heap growth {chain_peak} bytes");
+    println!("fold:               {fold_time:?}, peak heap growth {fold_peak} bytes");
+}
@@ -0,0 +1,36 @@
+//! compares 64 looped CipherSelectable::select calls on FheUint32 pairs against one select_many batch; run with `cargo bench --bench select_many` (add `--features parallel` to spread the batch over rayon).
+//! both variants select under the same encrypted condition, so the difference is key installation and, with `parallel`, the worker split.
+
+use cryptmalloc::{
+    encrypted_option::{select_many, CipherSelectable},
+    Keys,
+};
+use std::time::Instant;
+use tfhe::FheUint32;
+
+const PAIRS: u32 = 64;
+
+fn main() {
+    let keys = Keys::new();
+    let when_true = keys.enc_u32_many(&(0..PAIRS).collect::<Vec<_>>());
+    let when_false = keys.enc_u32_many(&(0..PAIRS).map(|i| i + 1000).collect::<Vec<_>>());
+    let cond = keys.enc_true();
+
+    let start = Instant::now();
+    let looped: Vec<FheUint32> = when_true
+        .iter()
+        .zip(&when_false)
+        .map(|(t, f)| <FheUint32 as CipherSelectable>::select(&cond, t, f))
+        .collect();
+    let looped_time = start.elapsed();
+
+    let start = Instant::now();
+    let batched = select_many(&cond, &when_true, &when_false).unwrap();
+    let batched_time = start.elapsed();
+
+    for (looped, batched) in looped.iter().zip(&batched) {
+        assert_eq!(keys.dec_u32(looped), keys.dec_u32(batched));
+    }
+    println!("looped select: {looped_time:?} for {PAIRS} pairs");
+    println!("select_many:   {batched_time:?} for {PAIRS} pairs");
+}
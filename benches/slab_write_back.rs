@@ -0,0 +1,51 @@
+//! times one allocate_masked call on a 1024-block tier under each write-back variant; run with `cargo bench --bench slab_write_back`.
+//! every variant starts from an empty slab built from the same keys, so the timings differ only in the write-back strategy.
+
+use cryptmalloc::{IndexWidth, Keys, SlabClass, SlabOptions, WriteBack};
+use std::time::Instant;
+
+const NUM_BLOCKS: usize = 1024;
+const BLOCK_SIZE: usize = 16;
+
+fn main() {
+    let keys = Keys::new();
+    let variants = [
+        ("u32 index scan", SlabOptions::default()),
+        (
+            "narrow index scan",
+            SlabOptions {
+                index_width: IndexWidth::Narrow,
+                write_back: WriteBack::IndexScan,
+            },
+        ),
+        (
+            "retained flags",
+            SlabOptions {
+                index_width: IndexWidth::U32,
+                write_back: WriteBack::RetainedFlags,
+            },
+        ),
+    ];
+
+    for (label, options) in variants {
+        let mut slab = SlabClass::new(
+            BLOCK_SIZE,
+            NUM_BLOCKS,
+            keys.enc_u64(0),
+            keys.server_key(),
+            keys.enc_false_cached(),
+            keys.enc_true_cached(),
+            keys.enc_u32_cached(0),
+            keys.enc_u64_cached(0),
+            keys.build_enc_indices_u32(NUM_BLOCKS),
+            keys.build_enc_offsets_u64(NUM_BLOCKS, BLOCK_SIZE),
+        )
+        .with_options(&keys, options);
+
+        let start = Instant::now();
+        let granted = slab.allocate_masked(keys.enc_true());
+        let elapsed = start.elapsed();
+        assert!(keys.dec_bool(&granted.is_some));
+        println!("{label:>18}: {elapsed:?} for {NUM_BLOCKS} blocks");
+    }
+}
@@ -0,0 +1,3 @@
+//! cryptmalloc-ffi packages cryptmalloc's `ffi` module as a cdylib, so the C ABI is built only by callers who ask for it; every cm_* symbol is defined in cryptmalloc::ffi and re-exported here.
+
+pub use cryptmalloc::ffi::*;
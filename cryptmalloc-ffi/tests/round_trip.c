@@ -0,0 +1,99 @@
+/* client half of tests/ffi.rs: imports the keys at argv[1], writes one length-prefixed allocation
+ * request to stdout, reads the server's length-prefixed response from stdin and prints the decrypted
+ * result. Lengths are native-endian uint64_t. Exits nonzero on the first failed check. */
+
+#include <stdio.h>
+#include <string.h>
+
+#include "cryptmalloc.h"
+
+static int fail(const char *what) {
+    const char *err = cm_last_error();
+    fprintf(stderr, "%s: %s\n", what, err ? err : "(no error recorded)");
+    return 1;
+}
+
+static uint8_t *read_file(const char *path, size_t *len) {
+    FILE *f = fopen(path, "rb");
+    if (!f) {
+        return NULL;
+    }
+    fseek(f, 0, SEEK_END);
+    long size = ftell(f);
+    fseek(f, 0, SEEK_SET);
+    uint8_t *buf = malloc(size > 0 ? (size_t)size : 1);
+    *len = fread(buf, 1, (size_t)size, f);
+    fclose(f);
+    return buf;
+}
+
+int main(int argc, char **argv) {
+    if (argc != 2) {
+        fprintf(stderr, "usage: %s KEYS_FILE\n", argv[0]);
+        return 2;
+    }
+
+    size_t keys_len = 0;
+    uint8_t *keys_bytes = read_file(argv[1], &keys_len);
+    if (!keys_bytes) {
+        fprintf(stderr, "cannot read %s\n", argv[1]);
+        return 1;
+    }
+    CmKeys *keys = cm_keys_import(keys_bytes, keys_len);
+    free(keys_bytes);
+    if (!keys) {
+        return fail("cm_keys_import");
+    }
+
+    /* null arguments are rejected with a status and a message, not a crash. */
+    uint64_t offset = 0;
+    bool is_some = false;
+    if (cm_response_decrypt(keys, NULL, 8, &offset, &is_some) != CM_NULL_POINTER ||
+        cm_last_error() == NULL) {
+        fprintf(stderr, "null response was not rejected\n");
+        return 1;
+    }
+    if (cm_request_alloc(NULL, 24, &keys_len) != NULL) {
+        fprintf(stderr, "null handle was not rejected\n");
+        return 1;
+    }
+    uint8_t garbage[3] = {1, 0, 7};
+    if (cm_response_decrypt(keys, garbage, sizeof garbage, &offset, &is_some) != CM_PROTOCOL) {
+        fprintf(stderr, "garbage response was not rejected\n");
+        return 1;
+    }
+
+    size_t request_len = 0;
+    uint8_t *request = cm_request_alloc(keys, 24, &request_len);
+    if (!request) {
+        return fail("cm_request_alloc");
+    }
+    if (cm_last_error() != NULL) {
+        fprintf(stderr, "success left an error behind\n");
+        return 1;
+    }
+    uint64_t prefix = request_len;
+    fwrite(&prefix, sizeof prefix, 1, stdout);
+    fwrite(request, 1, request_len, stdout);
+    fflush(stdout);
+    cm_bytes_free(request, request_len);
+
+    if (fread(&prefix, sizeof prefix, 1, stdin) != 1) {
+        fprintf(stderr, "no response length\n");
+        return 1;
+    }
+    uint8_t *response = malloc(prefix > 0 ? (size_t)prefix : 1);
+    if (fread(response, 1, (size_t)prefix, stdin) != prefix) {
+        fprintf(stderr, "short response\n");
+        return 1;
+    }
+    int32_t status = cm_response_decrypt(keys, response, (size_t)prefix, &offset, &is_some);
+    free(response);
+    if (status != CM_OK) {
+        return fail("cm_response_decrypt");
+    }
+
+    fprintf(stderr, "offset=%llu is_some=%d\n", (unsigned long long)offset, is_some);
+    cm_keys_free(keys);
+    return 0;
+}
@@ -0,0 +1,83 @@
+//! Compiles tests/round_trip.c against the cdylib and runs a full allocate round trip: the C client encrypts a request through the C ABI, the Rust ServerEndpoint answers it, and the C client decrypts the response.
+#![cfg(target_os = "linux")]
+
+use cryptmalloc::{ffi::export_keys, CryptMalloc, Keys, ServerEndpoint};
+use std::{
+    env, fs,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+// the test binary lives in target/<profile>/deps, next to the libcryptmalloc_ffi.so cargo built for it.
+fn compile_client(out_dir: &Path) -> PathBuf {
+    let deps = env::current_exe().unwrap().parent().unwrap().to_path_buf();
+    let manifest = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let triple = format!("{}-unknown-linux-gnu", env::consts::ARCH);
+    let compiler = cc::Build::new()
+        .target(&triple)
+        .host(&triple)
+        .opt_level(0)
+        .cargo_metadata(false)
+        .get_compiler();
+    let exe = out_dir.join("round_trip");
+    let status = compiler
+        .to_command()
+        .arg(manifest.join("tests/round_trip.c"))
+        .arg("-I")
+        .arg(manifest.join("../include"))
+        .arg("-o")
+        .arg(&exe)
+        .arg("-L")
+        .arg(&deps)
+        .arg("-lcryptmalloc_ffi")
+        .arg(format!("-Wl,-rpath,{}", deps.display()))
+        .status()
+        .unwrap();
+    assert!(status.success(), "compiling round_trip.c failed");
+    exe
+}
+
+#[test]
+fn c_client_round_trips_an_allocation() {
+    let out_dir = env::temp_dir().join(format!("cryptmalloc-ffi-{}", std::process::id()));
+    fs::create_dir_all(&out_dir).unwrap();
+    let client = compile_client(&out_dir);
+
+    let keys = Keys::new();
+    let keys_path = out_dir.join("keys.bin");
+    fs::write(&keys_path, export_keys(&keys).unwrap()).unwrap();
+    let mut server = ServerEndpoint::new(
+        keys.server_key(),
+        CryptMalloc::builder(512).tiers(&[(16, 1), (32, 1), (64, 1), (128, 1), (256, 1)]),
+    )
+    .unwrap();
+
+    let mut child = Command::new(&client)
+        .arg(&keys_path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdout = child.stdout.take().unwrap();
+    let mut prefix = [0u8; 8];
+    stdout.read_exact(&mut prefix).unwrap();
+    let mut request = vec![0u8; u64::from_ne_bytes(prefix) as usize];
+    stdout.read_exact(&mut request).unwrap();
+
+    let response = server.handle(&request).unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    stdin
+        .write_all(&(response.len() as u64).to_ne_bytes())
+        .unwrap();
+    stdin.write_all(&response).unwrap();
+    drop(stdin);
+
+    let output = child.wait_with_output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{stderr}");
+    // 24 bytes lands in the 32-byte tier, which the planned layout puts at offset 32.
+    assert!(stderr.contains("offset=32 is_some=1"), "{stderr}");
+    fs::remove_dir_all(&out_dir).unwrap();
+}
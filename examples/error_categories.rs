@@ -0,0 +1,48 @@
+//! one handler for every subsystem: each failing call returns its own error, `?` lifts it into CryptmallocError, and the handler only looks at the category.
+//! run with `cargo run --example error_categories`; none of the failures get far enough to generate keys.
+
+use cryptmalloc::{
+    layout::{Layout, TierPlacement, DEFAULT_TIERS},
+    protocol::{decode, Request},
+    Category, CryptMalloc, CryptmallocError,
+};
+
+// two tiers placed on the same offsets; validation rejects it before any key exists.
+fn build_overlapping_allocator() -> Result<CryptMalloc, CryptmallocError> {
+    let mut layout = Layout::contiguous(&DEFAULT_TIERS, 4096);
+    layout.tiers[1] = TierPlacement {
+        base_offset: layout.tiers[0].base_offset,
+        ..layout.tiers[1]
+    };
+    Ok(CryptMalloc::builder(4096).layout(layout).build()?)
+}
+
+fn restore_layout(bytes: &[u8]) -> Result<Layout, CryptmallocError> {
+    Ok(Layout::from_bytes(bytes)?)
+}
+
+fn decode_request(bytes: &[u8]) -> Result<Request, CryptmallocError> {
+    Ok(decode(bytes)?)
+}
+
+fn handle(operation: &str, err: CryptmallocError) {
+    let action = match err.category() {
+        Category::Crypto | Category::Integrity => "alert and drop the input",
+        Category::Capacity => "shrink the request and retry",
+        Category::Concurrency => "retry",
+        Category::Config | Category::Usage => "fix the caller",
+    };
+    println!("{operation}: {err} -> {action}");
+}
+
+fn main() {
+    if let Err(err) = build_overlapping_allocator() {
+        handle("build", err);
+    }
+    if let Err(err) = restore_layout(b"not a layout") {
+        handle("restore layout", err);
+    }
+    if let Err(err) = decode_request(&[7]) {
+        handle("decode request", err);
+    }
+}
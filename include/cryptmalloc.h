@@ -0,0 +1,108 @@
+/* C ABI for the cryptmalloc client endpoint; link against libcryptmalloc_ffi, built by `cargo build -p cryptmalloc-ffi`.
+ * Regenerate with `cbindgen --config cbindgen.toml --output include/cryptmalloc.h` after changing src/ffi.rs.
+ *
+ * Ownership:
+ *  - CmKeys handles come from cm_keys_new or cm_keys_import and are released with cm_keys_free.
+ *  - Byte buffers returned by cm_keys_export and cm_request_* belong to the caller and are released with
+ *    cm_bytes_free, passing the length they were returned with. Never free them with free().
+ *  - Input buffers are only borrowed for the duration of the call.
+ *  - A handle may be used from any thread, but not from two threads at once.
+ *
+ * Errors: pointer-returning functions return NULL and status-returning functions a nonzero CM_* code;
+ * cm_last_error then describes the failure. No function unwinds into C.
+ */
+
+#ifndef CRYPTMALLOC_H
+#define CRYPTMALLOC_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define CM_OK 0
+
+/**
+ * a required pointer argument was null.
+ */
+#define CM_NULL_POINTER 1
+
+/**
+ * bytes did not decode as a message, or a message failed to encode.
+ */
+#define CM_PROTOCOL 2
+
+/**
+ * the response decoded but answers something other than an allocation.
+ */
+#define CM_UNEXPECTED_RESPONSE 3
+
+/**
+ * the call panicked; the handles it was given may be left in an unspecified state and should be destroyed.
+ */
+#define CM_PANIC 4
+
+/**
+ * opaque handle owning a keypair and the request id counter; created by cm_keys_new or cm_keys_import, destroyed by cm_keys_free.
+ */
+typedef struct CmKeys CmKeys;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * generates a fresh keypair; null on failure. Key generation takes seconds.
+ */
+CmKeys *cm_keys_new(void);
+
+/**
+ * destroys a handle from cm_keys_new or cm_keys_import; null is a no-op.
+ */
+void cm_keys_free(CmKeys *keys);
+
+/**
+ * seals the keypair, client key included, in a checksummed envelope in a new buffer of `*out_len` bytes; null on failure.
+ */
+uint8_t *cm_keys_export(const CmKeys *keys, size_t *out_len);
+
+/**
+ * rebuilds a handle from `len` bytes written by cm_keys_export; its request ids restart at zero. null on failure.
+ */
+CmKeys *cm_keys_import(const uint8_t *bytes, size_t len);
+
+/**
+ * encodes an encrypted request for `size` bytes into a new buffer of `*out_len` bytes; null on failure.
+ */
+uint8_t *cm_request_alloc(CmKeys *keys, uint64_t size, size_t *out_len);
+
+/**
+ * encodes an encrypted request to free the block at `offset` into a new buffer of `*out_len` bytes; null on failure.
+ */
+uint8_t *cm_request_free(CmKeys *keys, uint64_t offset, size_t *out_len);
+
+/**
+ * decrypts an allocation response of `len` bytes; on CM_OK `*out_is_some` says whether the server found room and `*out_offset` holds the offset, zero when it did not.
+ */
+int32_t cm_response_decrypt(const CmKeys *keys,
+                            const uint8_t *bytes,
+                            size_t len,
+                            uint64_t *out_offset,
+                            bool *out_is_some);
+
+/**
+ * releases a buffer returned by cm_keys_export or a cm_request_* function; `len` must be the length it was returned with. null is a no-op.
+ */
+void cm_bytes_free(uint8_t *bytes, size_t len);
+
+/**
+ * describes the calling thread's most recent failure, or null if its last call succeeded; the string stays valid until the thread's next cm_* call.
+ */
+const char *cm_last_error(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* CRYPTMALLOC_H */
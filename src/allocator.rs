@@ -1,26 +1,62 @@
 use core::fmt;
+#[cfg(feature = "failpoints")]
+use crate::failpoints::{FailPoint, FailPolicy, Failpoints};
+#[cfg(feature = "pool-registry")]
+use crate::registry::Registration;
 use crate::{
     arena::Arena,
     encrypted_option::EncryptedOption,
+    encrypted_prng::EncryptedPrng,
     encrypted_ptr::EncryptedPtr,
-    keys::Keys,
+    envelope::{EnvelopeError, Persistent},
+    keys::{reseat_server_key, Keys},
+    layout::{Layout, LayoutError, TierPlacement, DEFAULT_TIERS},
+    metrics::{AllocatorMetrics, MetricsRegistry, DEFAULT_ALLOCATOR_NAME},
+    nonce_window::{NonceWindow, DEFAULT_NONCE_WINDOW},
+    oblivious_result::ObliviousResult,
+    report::{AllocatorReport, ReportError},
+    ring_arena::RingArena,
+    size_blinder::SizeBlinder,
     slab::SlabClass,
+    tenancy::{reservation_caps, AllocationTicket, TenantTable},
+    tier_rounder::TierRounder,
+};
+use once_cell::sync::OnceCell;
+use std::{ops::Not, time::Instant};
+use tfhe::{
+    conformance::ParameterSetConformant, prelude::*, set_server_key, FheBool,
+    FheBoolConformanceParams, FheUint64,
 };
-use std::ops::Not;
-use tfhe::{prelude::*, set_server_key, FheBool, FheUint64};
 
 pub struct CryptMalloc {
     keys: Keys,
     slabs: Vec<SlabClass>,
     arena: Arena,
+    // when set, large requests go to the ring instead of the bump arena; both cover the same arena region.
+    ring: Option<RingArena>,
+    layout: Layout,
     enc_false: FheBool,
     enc_zero_u64: FheUint64,
-    size_bounds: [FheUint64; 5],
+    // tier block sizes that routing compares request sizes against, plus the size blinder if one is installed; exposed through rounder() so callers predict routing with the same comparisons.
+    rounder: TierRounder,
+    // per-tenant tier caps enforced by allocate_for; None when the builder reserved nothing.
+    tenants: Option<TenantTable>,
+    // results of recent free_with_nonce calls, replayed for duplicate nonces.
+    freed_nonces: NonceWindow<FheBool>,
+    // client-seeded randomness for oblivious tie-breaking; None unless the builder was given a seed.
+    rng: Option<EncryptedPrng>,
+    name: String,
+    metrics: AllocatorMetrics,
+    #[cfg(feature = "failpoints")]
+    failpoints: Failpoints,
+    #[cfg(feature = "pool-registry")]
+    registration: Option<Registration>,
 }
 
 impl fmt::Debug for CryptMalloc {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("CryptMalloc")
+            .field("name", &self.name)
             .field("slab_count", &self.slabs.len())
             .field("arena", &self.arena)
             .finish()
@@ -30,61 +66,122 @@ impl fmt::Debug for CryptMalloc {
 impl CryptMalloc {
     /// cryptmalloc wires together the strict top-level allocator: it alone owns the client key, manufactures the encrypted constants plus lookup tables, and lays out the slab tiers contiguously before the arena.
     pub fn new(arena_size: u64) -> Self {
-        let keys = Keys::new();
+        Self::with_keys(Keys::new(), arena_size)
+    }
+
+    /// builds the allocator under an existing keypair so ciphertexts encrypted elsewhere with the same keys (e.g. by a protocol client) can be routed directly.
+    pub fn with_keys(keys: Keys, arena_size: u64) -> Self {
+        Self::from_layout(keys, Layout::contiguous(&DEFAULT_TIERS, arena_size))
+    }
+
+    pub fn builder(arena_size: u64) -> CryptMallocBuilder {
+        CryptMallocBuilder::new(arena_size)
+    }
+
+    /// two-phase construction: generates keys and plans the default layout, deferring the per-tier index/offset tables to PreparedAllocator::finish (or per-tier ensure_tier calls) so the expensive encryption can run on a worker thread.
+    pub fn prepare(arena_size: u64) -> PreparedAllocator {
+        PreparedAllocator::new(Keys::new(), Layout::contiguous(&DEFAULT_TIERS, arena_size))
+    }
+
+    // materializes a validated layout: tier `i` of the layout is routing tier `i`, its base offset is wherever the layout placed it.
+    fn from_layout(keys: Keys, layout: Layout) -> Self {
+        let slabs = layout
+            .tiers
+            .iter()
+            .map(|placement| Self::build_slab(&keys, placement))
+            .collect();
+        Self::assemble(keys, layout, slabs)
+    }
+
+    // encrypts one tier's base offset plus index/offset tables; this is the bulk of construction time.
+    fn build_slab(keys: &Keys, placement: &TierPlacement) -> SlabClass {
         let server_key = keys.server_key();
         set_server_key(server_key.clone());
 
-        let enc_false = keys.enc_false();
-        let enc_true = keys.enc_true();
-        let enc_zero_u32 = keys.enc_zero_u32();
-        let enc_zero_u64 = keys.enc_zero_u64();
-        let size_bounds = [
-            keys.enc_u64(16),
-            keys.enc_u64(32),
-            keys.enc_u64(64),
-            keys.enc_u64(128),
-            keys.enc_u64(256),
-        ];
-
-        let slab_configs = [
-            (16usize, 1024usize),
-            (32usize, 512usize),
-            (64usize, 256usize),
-            (128usize, 128usize),
-            (256usize, 64usize),
-        ];
-
-        let mut slabs = Vec::with_capacity(slab_configs.len());
-        let mut running_offset = 0u64;
-
-        for (block_size, num_blocks) in slab_configs.iter() {
-            let base_offset_plain = running_offset;
-            running_offset += (*block_size as u64) * (*num_blocks as u64);
-
-            let base_offset = keys.enc_u64(base_offset_plain);
-            let enc_indices_u32 = keys.build_enc_indices_u32(*num_blocks);
-            let enc_offsets_u64 = keys.build_enc_offsets_u64(*num_blocks, *block_size);
-
-            let slab = SlabClass::new(
-                *block_size,
-                *num_blocks,
-                base_offset,
-                server_key.clone(),
-                enc_false.clone(),
-                enc_true.clone(),
-                enc_zero_u32.clone(),
-                enc_zero_u64.clone(),
-                enc_indices_u32,
-                enc_offsets_u64,
-            );
-
-            slabs.push(slab);
-        }
-
-        let arena_start_plain = running_offset;
-        let arena_end_plain = arena_start_plain + arena_size;
-        let arena_start = keys.enc_u64(arena_start_plain);
-        let arena_end = keys.enc_u64(arena_end_plain);
+        let base_offset = keys.constant_u64(placement.base_offset);
+        let enc_indices_u32 = keys.build_enc_indices_u32(placement.num_blocks);
+        let enc_offsets_u64 =
+            keys.build_enc_offsets_u64(placement.num_blocks, placement.block_size);
+
+        SlabClass::new(
+            placement.block_size,
+            placement.num_blocks,
+            base_offset,
+            server_key,
+            keys.enc_false_cached(),
+            keys.enc_true_cached(),
+            keys.enc_u32_cached(0),
+            keys.enc_u64_cached(0),
+            enc_indices_u32,
+            enc_offsets_u64,
+        )
+        .with_alignment(placement.alignment())
+    }
+
+    // wires already-built slabs to the routing constants and the arena.
+    fn assemble(keys: Keys, layout: Layout, slabs: Vec<SlabClass>) -> Self {
+        set_server_key(keys.server_key());
+        let block_sizes: [u64; 5] =
+            core::array::from_fn(|tier| layout.tiers[tier].block_size as u64);
+        let rounder = TierRounder::new(&keys, block_sizes);
+        let arena_start = keys.constant_u64(layout.arena_start);
+        let arena_end = keys.constant_u64(layout.arena_end);
+        Self::wire(keys, layout, slabs, rounder, (arena_start, arena_end))
+    }
+
+    /// confidential construction: the five slabs must come from SlabClass::new_confidential, ordered by ascending block size, and their encrypted block sizes become the routing bounds, so no tier geometry is ever plaintext. the order cannot be checked without decrypting, so the caller vouches for it.
+    /// the arena spans the encrypted `[arena_start, arena_end)`. layout() of the result is an empty placeholder, which keeps tier and arena sizes out of metrics and reports as well.
+    pub fn from_confidential_tiers(
+        keys: Keys,
+        slabs: Vec<SlabClass>,
+        arena_start: FheUint64,
+        arena_end: FheUint64,
+    ) -> Result<Self, LayoutError> {
+        set_server_key(keys.server_key());
+        if slabs.len() != DEFAULT_TIERS.len() {
+            return Err(LayoutError::TierCount {
+                found: slabs.len(),
+                expected: DEFAULT_TIERS.len(),
+            });
+        }
+        let mut enc_bounds = Vec::with_capacity(slabs.len());
+        for (tier, slab) in slabs.iter().enumerate() {
+            let enc_block_size = slab
+                .enc_block_size()
+                .ok_or(LayoutError::PlaintextTier { tier })?;
+            enc_bounds.push(enc_block_size.clone());
+        }
+        let enc_bounds: [FheUint64; 5] = enc_bounds
+            .try_into()
+            .unwrap_or_else(|_| unreachable!("tier count was checked above"));
+        let layout = Layout {
+            tiers: Vec::new(),
+            arena_start: 0,
+            arena_end: 0,
+            seed: None,
+        };
+        let rounder = TierRounder::from_encrypted(&keys, enc_bounds);
+        Ok(Self::wire(
+            keys,
+            layout,
+            slabs,
+            rounder,
+            (arena_start, arena_end),
+        ))
+    }
+
+    fn wire(
+        keys: Keys,
+        layout: Layout,
+        slabs: Vec<SlabClass>,
+        rounder: TierRounder,
+        (arena_start, arena_end): (FheUint64, FheUint64),
+    ) -> Self {
+        let server_key = keys.server_key();
+        set_server_key(server_key.clone());
+
+        let enc_false = keys.enc_false_cached();
+        let enc_zero_u64 = keys.enc_u64_cached(0);
         let arena_enc_false = enc_false.clone();
         let arena_enc_zero = enc_zero_u64.clone();
 
@@ -100,34 +197,186 @@ impl CryptMalloc {
             keys,
             slabs,
             arena,
+            ring: None,
+            layout,
             enc_false,
             enc_zero_u64,
-            size_bounds,
+            rounder,
+            tenants: None,
+            freed_nonces: NonceWindow::new(DEFAULT_NONCE_WINDOW),
+            rng: None,
+            name: DEFAULT_ALLOCATOR_NAME.to_string(),
+            metrics: AllocatorMetrics::default(),
+            #[cfg(feature = "failpoints")]
+            failpoints: Failpoints::default(),
+            #[cfg(feature = "pool-registry")]
+            registration: None,
+        }
+    }
+
+    /// the plaintext placement this allocator was built with; export it alongside the keys to rebuild an identical allocator via CryptMallocBuilder::layout. confidential allocators report an empty layout.
+    pub fn layout(&self) -> &Layout {
+        &self.layout
+    }
+
+    /// the tier comparisons routing uses, for predicting which tier a request lands in and how much padding it gets; queries go through the builder's size blinder first, as routing does.
+    pub fn rounder(&self) -> &TierRounder {
+        &self.rounder
+    }
+
+    /// label attached to this allocator's metrics.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// plaintext operation counters; reading them never touches ciphertexts.
+    pub fn metrics(&self) -> &AllocatorMetrics {
+        &self.metrics
+    }
+
+    /// trusted-side snapshot with every bitmap and cursor decrypted under `keys`; reads state only and fails only for server-only keys.
+    pub fn report(&self, keys: &Keys) -> Result<AllocatorReport, ReportError> {
+        AllocatorReport::collect(self, keys)
+    }
+
+    /// trusted-side: every tier's decrypted bitmap with its block size, decrypted under the allocator's own keys; test support only.
+    #[cfg(feature = "test-utils")]
+    pub fn decrypt_occupancy(&self) -> crate::test_utils::Occupancy {
+        self.slabs
+            .iter()
+            .map(|slab| {
+                (
+                    slab.trusted_block_size(&self.keys),
+                    slab.decrypt_bitmap(&self.keys),
+                )
+            })
+            .collect()
+    }
+
+    /// trusted-side: panics naming every tier whose bitmap differs from `expected`, with the differing indices and both states in `tier16: 1100` form.
+    #[cfg(feature = "test-utils")]
+    pub fn assert_occupancy(&self, expected: &[(usize, Vec<bool>)]) {
+        let actual = self.decrypt_occupancy();
+        assert_eq!(
+            actual.iter().map(|(size, _)| *size).collect::<Vec<_>>(),
+            expected.iter().map(|(size, _)| *size).collect::<Vec<_>>(),
+            "tier block sizes differ"
+        );
+        let reports: Vec<String> = actual
+            .iter()
+            .zip(expected)
+            .filter_map(|((block_size, actual), (_, expected))| {
+                crate::test_utils::tier_mismatch(*block_size, actual, expected)
+            })
+            .collect();
+        assert!(reports.is_empty(), "{}", reports.join("\n"));
+    }
+
+    /// lists this allocator in the process-wide registry under `name` until it is dropped; registering again replaces the earlier entry.
+    #[cfg(feature = "pool-registry")]
+    pub fn register(&mut self, name: &str) {
+        self.registration = Some(Registration::new(name, &self.layout, &self.metrics));
+    }
+
+    // pushes the counters to this allocator's registry entry, if it has one; called after every counter update.
+    fn publish_metrics(&self) {
+        #[cfg(feature = "pool-registry")]
+        if let Some(registration) = &self.registration {
+            registration.publish(&self.metrics);
         }
     }
 
-    /// routes encrypted size requests through every slab class plus the arena in constant time; sizes up to 256 bytes never spill into the arena, and zero length requests are coerced to 16 bytes before routing
+    /// this allocator's counters in Prometheus text format; use MetricsRegistry to combine several allocators.
+    pub fn metrics_text(&self) -> String {
+        MetricsRegistry::new().register(self).render()
+    }
+
+    /// routes encrypted size requests through every slab class plus the arena in constant time; sizes up to 256 bytes never spill into the arena, and zero length requests are coerced to 16 bytes before routing. a pointer served by tier `i` decrypts to a multiple of `slabs()[i].alignment()`, which is the block size's power-of-two factor for every layout the builder plans
     pub fn allocate(&mut self, size: FheUint64) -> EncryptedOption<EncryptedPtr> {
         set_server_key(self.keys.server_key());
+        let started = Instant::now();
+        let (result, _) = self.route(size, None, None);
+        self.metrics.allocations += 1;
+        self.metrics.allocate_time += started.elapsed();
+        self.publish_metrics();
+        result
+    }
+
+    /// allocate plus the encrypted routing decision: the audit carries clones of the exact tier masks and arena flag the request was routed with, so a key holder can check which region served it while the server learns nothing.
+    /// the audit costs six extra FheBool ciphertexts per request on the wire.
+    pub fn allocate_audited(
+        &mut self,
+        size: FheUint64,
+    ) -> (EncryptedOption<EncryptedPtr>, TierAudit) {
+        set_server_key(self.keys.server_key());
+        let started = Instant::now();
+        let routed = self.route(size, None, None);
+        self.metrics.allocations += 1;
+        self.metrics.allocate_time += started.elapsed();
+        self.publish_metrics();
+        routed
+    }
+
+    /// poison-style allocation for oblivious pipelines: zero-length requests are rejected by clearing `ok` rather than being coerced, exhaustion clears `ok` as well, and every tier plus the arena is scanned with the admission flag folded into the masks so success and failure do identical work
+    pub fn allocate_oblivious(&mut self, size: FheUint64) -> ObliviousResult<EncryptedPtr> {
+        set_server_key(self.keys.server_key());
+        let started = Instant::now();
+
+        let valid = size.ne(0u64);
+        let (routed, _) = self.route(size, Some(&valid), None);
+        self.metrics.oblivious_allocations += 1;
+        self.metrics.allocate_time += started.elapsed();
+        self.publish_metrics();
+        ObliviousResult::new(routed.value, routed.is_some)
+    }
+
+    /// allocate on behalf of `tenant`, holding it to the tier caps set with CryptMallocBuilder::reserve_tier_fraction: a tenant that has filled its reservation on a tier draws on that tier's unreserved blocks, and only when those are taken too is the request served by the arena instead. tenants without a reservation on a tier share that tier's unreserved blocks.
+    /// the ticket goes back to free_for so the block is returned to the tenant's share; without reservations this is allocate plus a ticket.
+    pub fn allocate_for(
+        &mut self,
+        tenant: u32,
+        size: FheUint64,
+    ) -> (EncryptedOption<EncryptedPtr>, AllocationTicket) {
+        set_server_key(self.keys.server_key());
+        let started = Instant::now();
+        let gates = self.tenants.as_ref().map(|tenants| tenants.gates(tenant));
+        let (result, audit) = self.route(size, None, gates.as_deref());
+        // masks are one-hot, so a tier served the request exactly when its mask is set and the result is some.
+        let granted = audit
+            .tiers
+            .iter()
+            .map(|mask| mask & &result.is_some)
+            .collect();
+        let ticket = match self.tenants.as_mut() {
+            Some(tenants) => tenants.charge(tenant, granted),
+            None => AllocationTicket::untracked(tenant, granted),
+        };
+        self.metrics.allocations += 1;
+        self.metrics.allocate_time += started.elapsed();
+        self.publish_metrics();
+        (result, ticket)
+    }
 
+    // shared routing core; sizes are bucketized first when a blinder is installed, after allocate_oblivious has judged the unpadded size. `admit` is an optional encrypted gate ANDed into every tier mask and the arena flag so rejected requests never mark a cell or bump the cursor.
+    // `gates` optionally closes individual tiers; a request routed to a closed tier is sent to the arena instead.
+    // the returned audit holds the very mask ciphertexts handed to the slabs and arena.
+    fn route(
+        &mut self,
+        size: FheUint64,
+        admit: Option<&FheBool>,
+        gates: Option<&[FheBool]>,
+    ) -> (EncryptedOption<EncryptedPtr>, TierAudit) {
         let enc_false = self.enc_false.clone();
         let enc_zero = self.enc_zero_u64.clone();
-        let enc_16 = self.size_bounds[0].clone();
-        let enc_32 = self.size_bounds[1].clone();
-        let enc_64 = self.size_bounds[2].clone();
-        let enc_128 = self.size_bounds[3].clone();
-        let enc_256 = self.size_bounds[4].clone();
-
-        let is_zero = size.eq(&enc_zero);
-        let lt_16 = size.lt(&enc_16);
-        let force_16 = (&is_zero) | (&lt_16);
-        let size_ct = force_16.if_then_else(&enc_16, &size);
-
-        let fits16 = size_ct.le(&enc_16);
-        let fits32 = size_ct.le(&enc_32);
-        let fits64 = size_ct.le(&enc_64);
-        let fits128 = size_ct.le(&enc_128);
-        let fits256 = size_ct.le(&enc_256);
+        let size = self.rounder.blind(size);
+
+        let size_ct = self.rounder.clamp_to_min(&size);
+
+        let fits16 = self.rounder.fits(&size_ct, 0);
+        let fits32 = self.rounder.fits(&size_ct, 1);
+        let fits64 = self.rounder.fits(&size_ct, 2);
+        let fits128 = self.rounder.fits(&size_ct, 3);
+        let fits256 = self.rounder.fits(&size_ct, 4);
 
         let mask0 = fits16.clone();
         let mask1 = fits32.clone() & fits16.clone().not();
@@ -138,27 +387,78 @@ impl CryptMalloc {
         let used0123 = used012.clone() | fits128.clone();
         let mask4 = fits256.clone() & used0123.clone().not();
 
-        let masks = [mask0, mask1, mask2, mask3, mask4];
+        let mut masks = [mask0, mask1, mask2, mask3, mask4];
+        let mut use_arena = fits256.clone().not();
+        if let Some(gates) = gates {
+            for (mask, gate) in masks.iter_mut().zip(gates) {
+                use_arena = &use_arena | &(&*mask & &!gate);
+                *mask = &*mask & gate;
+            }
+        }
+        if let Some(admit) = admit {
+            for mask in masks.iter_mut() {
+                *mask = &*mask & admit;
+            }
+        }
+        #[cfg(feature = "failpoints")]
+        for (tier, mask) in masks.iter_mut().enumerate() {
+            if self.failpoints.should_fail(FailPoint::SlabAllocate(tier)) {
+                *mask = &*mask & &enc_false;
+            }
+        }
 
-        let mut slab_results = Vec::with_capacity(self.slabs.len());
+        let mut candidates = Vec::with_capacity(self.slabs.len() + 1);
         for (slab, sel) in self.slabs.iter_mut().zip(masks.iter()) {
-            slab_results.push(slab.allocate_masked(sel.clone()));
+            candidates.push(slab.allocate_masked(sel.clone()));
         }
 
-        let use_arena = size_ct.gt(&enc_256);
+        if let Some(admit) = admit {
+            use_arena = &use_arena & admit;
+        }
+        #[cfg(feature = "failpoints")]
+        if self.failpoints.should_fail(FailPoint::ArenaAllocate) {
+            use_arena = &use_arena & &enc_false;
+        }
         let arena_size = use_arena.if_then_else(&size_ct, &enc_zero.clone());
-        let arena_raw = self.arena.allocate(arena_size);
+        let arena_raw = match self.ring.as_mut() {
+            Some(ring) => ring.allocate(arena_size).ptr,
+            None => self.arena.allocate(arena_size),
+        };
         let arena_masked = EncryptedOption {
             value: arena_raw.value,
             is_some: arena_raw.is_some & use_arena.clone(),
         };
 
-        let mut result = EncryptedOption::none(EncryptedPtr(enc_zero.clone()), enc_false.clone());
-        for slab_result in slab_results.iter() {
-            result = result.combine_with(slab_result);
-        }
-        result = result.combine_with(&arena_masked);
-        result
+        candidates.push(arena_masked);
+        let result = EncryptedOption::fold(
+            &candidates,
+            EncryptedOption::none(EncryptedPtr(enc_zero.clone()), enc_false.clone()),
+        );
+        let audit = TierAudit {
+            tiers: masks.to_vec(),
+            arena: use_arena,
+        };
+        (result, audit)
+    }
+
+    /// encrypted capacity answers for a client deciding whether to open a session: one `arena remaining >= threshold` flag per entry of `thresholds`, in order, followed by one any-free-block flag per slab tier in routing order. with a ring arena the arena answer is `threshold <= end - start`, since the ring wraps rather than running out. every threshold and every bitmap cell is processed whatever the occupancy.
+    pub fn capacity_proofs(&self, thresholds: &[FheUint64]) -> Vec<FheBool> {
+        set_server_key(self.keys.server_key());
+        let mut proofs: Vec<FheBool> = match &self.ring {
+            Some(ring) => {
+                let capacity = ring.end() - ring.start();
+                thresholds
+                    .iter()
+                    .map(|threshold| capacity.ge(threshold))
+                    .collect()
+            }
+            None => thresholds
+                .iter()
+                .map(|threshold| self.arena.capacity_proof(threshold.clone()))
+                .collect(),
+        };
+        proofs.extend(self.slabs.iter().map(SlabClass::has_free));
+        proofs
     }
 
     pub fn arena(&self) -> &Arena {
@@ -166,6 +466,12 @@ impl CryptMalloc {
         &self.arena
     }
 
+    /// the ring serving large requests when the allocator was built with `ring_arena(true)`; its epoch reveals (encrypted) how often earlier arena chunks may have been overwritten.
+    pub fn ring_arena(&self) -> Option<&RingArena> {
+        set_server_key(self.keys.server_key());
+        self.ring.as_ref()
+    }
+
     pub fn slabs(&self) -> &[SlabClass] {
         set_server_key(self.keys.server_key());
         &self.slabs
@@ -176,12 +482,464 @@ impl CryptMalloc {
         &self.keys
     }
 
-    // frees pointers by scanning every slab in constant time; arena chunks are not freed individually and null/invalid ciphertexts are harmless no-ops.
-    pub fn free(&mut self, ptr: &EncryptedPtr) {
+    /// a view for observers such as metrics exporters: every statistics accessor, but no allocation, free or reset, and no access to the keys.
+    pub fn read_only(&self) -> AllocatorReader<'_> {
+        AllocatorReader { inner: self }
+    }
+
+    /// arms a failpoint; an injected failure masks the tier's (or arena's) routing flag so the request reports `is_some = false` exactly as genuine exhaustion would, after the same full scans.
+    #[cfg(feature = "failpoints")]
+    pub fn inject_failure(&mut self, point: FailPoint, policy: FailPolicy) {
+        self.failpoints.inject(point, policy);
+    }
+
+    #[cfg(feature = "failpoints")]
+    pub fn clear_failure(&mut self, point: FailPoint) {
+        self.failpoints.clear(point);
+    }
+
+    #[cfg(feature = "failpoints")]
+    pub fn failpoints(&self) -> &Failpoints {
+        &self.failpoints
+    }
+
+    // returns every slab cell to free, rewinds the arena cursor and empties every tenant quota; outstanding pointers become dangling, exactly like Arena::reset, and AllocationTickets issued before the reset are invalid and must not be passed to free_for.
+    pub fn reset(&mut self) {
         set_server_key(self.keys.server_key());
 
         for slab in self.slabs.iter_mut() {
-            slab.free(ptr);
+            slab.reset();
+        }
+        self.arena.reset();
+        if let Some(ring) = self.ring.as_mut() {
+            ring.reset();
+        }
+        if let Some(tenants) = self.tenants.as_mut() {
+            tenants.reset();
+        }
+        self.metrics.resets += 1;
+        self.publish_metrics();
+    }
+
+    // frees pointers in constant time: an encrypted `ptr < arena_start || ptr >= arena_end` comparison classifies the region, so tiers may sit on either side of the arena, and every slab is scanned with its writes gated by that flag, so slab and arena pointers cost the same. only the first tier whose offsets match is modified, so a colliding pointer cannot clear cells in two tiers.
+    // returns the encrypted "reclaimed a slab cell" flag, false for a double free and for arena pointers, whose chunks are not freed individually.
+    pub fn free(&mut self, ptr: &EncryptedPtr) -> FheBool {
+        set_server_key(self.keys.server_key());
+        let started = Instant::now();
+
+        let in_slab_region = ptr.0.lt(self.arena.start()) | ptr.0.ge(self.arena.end());
+        let slab_reclaimed =
+            SlabClass::free_first_match_masked(&mut self.slabs, ptr, &in_slab_region)
+                .map_or_else(|| self.enc_false.clone(), |(_, reclaimed)| reclaimed);
+        let freed = &slab_reclaimed & &in_slab_region;
+        self.metrics.frees += 1;
+        self.metrics.free_time += started.elapsed();
+        self.publish_metrics();
+        freed
+    }
+
+    /// idempotent free for at-least-once delivery: the first call with a given nonce frees `ptr`, and a repeat while the nonce is still in the window returns that first result without touching the allocator. the same pointer can be freed again later under a new nonce.
+    pub fn free_with_nonce(&mut self, ptr: &EncryptedPtr, nonce: u128) -> FheBool {
+        if let Some(prior) = self.freed_nonces.get(nonce) {
+            return prior;
+        }
+        let freed = self.free(ptr);
+        self.freed_nonces.insert(nonce, freed.clone());
+        freed
+    }
+
+    /// the nonces free_with_nonce remembers; seal it with envelope::seal alongside the layout so a restarted server keeps rejecting duplicates.
+    pub fn nonce_window(&self) -> &NonceWindow<FheBool> {
+        &self.freed_nonces
+    }
+
+    /// the generator seeded with CryptMallocBuilder::oblivious_rng, for callers that need randomness the server cannot read; every draw steps the shared state.
+    pub fn oblivious_rng(&mut self) -> Option<&mut EncryptedPrng> {
+        self.rng.as_mut()
+    }
+
+    /// replaces the nonce window, e.g. with one saved before a restart. the window gets the same structural checks envelope::open runs, every cached result must conform to this allocator's server key, as ServerEndpoint requires of request ciphertexts, and the window is then re-bounded to this allocator's configured capacity, evicting its least recently seen nonces if it was saved with a larger one.
+    pub fn restore_nonce_window(
+        &mut self,
+        mut window: NonceWindow<FheBool>,
+    ) -> Result<(), EnvelopeError> {
+        window.validate()?;
+        let params = FheBoolConformanceParams::from(&self.keys.server_key());
+        if !window.values().all(|freed| freed.is_conformant(&params)) {
+            return Err(EnvelopeError::Invalid(
+                "nonce window holds a result that does not match the server key".to_string(),
+            ));
+        }
+        window.set_capacity(self.freed_nonces.capacity());
+        self.freed_nonces = window;
+        Ok(())
+    }
+
+    /// free for a block from allocate_for, returning it to the ticket's tenant share only when the free reclaims a cell, so repeating a free refunds nothing; the ticket must be the one issued with `ptr`.
+    pub fn free_for(&mut self, ticket: AllocationTicket, ptr: &EncryptedPtr) -> FheBool {
+        let freed = self.free(ptr);
+        if let Some(tenants) = self.tenants.as_mut() {
+            tenants.refund(ticket, &freed);
+        }
+        freed
+    }
+}
+
+/// per-request routing witness returned by CryptMalloc::allocate_audited; `tiers[i]` is the mask tier `i` was scanned with and `arena` the arena flag, after any admission or failpoint masking.
+#[derive(Clone)]
+pub struct TierAudit {
+    pub tiers: Vec<FheBool>,
+    pub arena: FheBool,
+}
+
+impl fmt::Debug for TierAudit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TierAudit")
+            .field("tiers", &self.tiers.len())
+            .field("arena", &"<ciphertext>")
+            .finish()
+    }
+}
+
+impl TierAudit {
+    /// encrypted "exactly one of the tier masks and the arena flag is set"; exactly one flag is set unless the request was rejected by admission or a failpoint, which leaves every flag clear.
+    pub fn assert_one_hot(&self) -> FheBool {
+        reseat_server_key();
+        let mut seen = self.arena.clone();
+        let mut repeated: Option<FheBool> = None;
+        for flag in self.tiers.iter() {
+            let clash = &seen & flag;
+            repeated = Some(match repeated {
+                Some(repeated) => &repeated | &clash,
+                None => clash,
+            });
+            seen = &seen | flag;
+        }
+        match repeated {
+            Some(repeated) => &seen & &repeated.not(),
+            None => seen,
+        }
+    }
+}
+
+/// AllocatorReader is the observation surface of a CryptMalloc handed out by read_only(); it holds a shared borrow, so the allocator cannot change underneath it and whatever it reports is current.
+/// It has no mutating methods and no keys() accessor, so it can be given to a component that must neither allocate nor decrypt:
+///
+/// ```compile_fail
+/// fn reader_cannot_allocate(reader: cryptmalloc::allocator::AllocatorReader<'_>, size: tfhe::FheUint64) {
+///     reader.allocate(size);
+/// }
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct AllocatorReader<'a> {
+    inner: &'a CryptMalloc,
+}
+
+impl<'a> AllocatorReader<'a> {
+    pub fn layout(&self) -> &'a Layout {
+        self.inner.layout()
+    }
+
+    pub fn rounder(&self) -> &'a TierRounder {
+        self.inner.rounder()
+    }
+
+    pub fn name(&self) -> &'a str {
+        self.inner.name()
+    }
+
+    pub fn metrics(&self) -> &'a AllocatorMetrics {
+        self.inner.metrics()
+    }
+
+    pub fn metrics_text(&self) -> String {
+        self.inner.metrics_text()
+    }
+
+    /// trusted-side snapshot; the caller supplies the keys, the reader never hands out the allocator's own.
+    pub fn report(&self, keys: &Keys) -> Result<AllocatorReport, ReportError> {
+        self.inner.report(keys)
+    }
+
+    pub fn capacity_proofs(&self, thresholds: &[FheUint64]) -> Vec<FheBool> {
+        self.inner.capacity_proofs(thresholds)
+    }
+
+    pub fn arena(&self) -> &'a Arena {
+        self.inner.arena()
+    }
+
+    pub fn ring_arena(&self) -> Option<&'a RingArena> {
+        self.inner.ring_arena()
+    }
+
+    pub fn slabs(&self) -> &'a [SlabClass] {
+        self.inner.slabs()
+    }
+}
+
+/// CryptMallocBuilder collects construction options; the plaintext layout is planned (and optionally randomized) before any key material is touched.
+#[derive(Debug)]
+pub struct CryptMallocBuilder {
+    arena_size: u64,
+    keys: Option<Keys>,
+    tiers: Vec<(usize, usize)>,
+    // Some(None) draws the seed when the layout is planned.
+    randomize: Option<Option<u64>>,
+    layout: Option<Layout>,
+    name: Option<String>,
+    ring: bool,
+    blinder: Option<SizeBlinder>,
+    reservations: Vec<(u32, usize, f32)>,
+    nonce_window: usize,
+    rng: Option<EncryptedPrng>,
+    #[cfg(feature = "pool-registry")]
+    registry_name: Option<String>,
+}
+
+impl CryptMallocBuilder {
+    pub fn new(arena_size: u64) -> Self {
+        Self {
+            arena_size,
+            keys: None,
+            tiers: DEFAULT_TIERS.to_vec(),
+            randomize: None,
+            layout: None,
+            name: None,
+            ring: false,
+            blinder: None,
+            reservations: Vec::new(),
+            nonce_window: DEFAULT_NONCE_WINDOW,
+            rng: None,
+            #[cfg(feature = "pool-registry")]
+            registry_name: None,
+        }
+    }
+
+    pub fn keys(mut self, keys: Keys) -> Self {
+        self.keys = Some(keys);
+        self
+    }
+
+    /// shuffles tier placement and inserts bounded random gaps; `None` draws a seed from the OS RNG each time the layout is planned, so plan_layout and build may disagree, while `Some(seed)` reproduces a layout exactly. the seed used is recorded in Layout::seed.
+    pub fn randomize_layout(mut self, seed: Option<u64>) -> Self {
+        self.randomize = Some(seed);
+        self
+    }
+
+    /// (block_size, num_blocks) per tier, smallest first, in place of DEFAULT_TIERS; the planned layout pads between tiers as needed so each stays aligned to its block size.
+    pub fn tiers(mut self, tiers: &[(usize, usize)]) -> Self {
+        self.tiers = tiers.to_vec();
+        self
+    }
+
+    /// reuses a previously exported layout verbatim, overriding tiers, arena_size and randomization; no padding is inserted, see Layout::misaligned_tiers.
+    pub fn layout(mut self, layout: Layout) -> Self {
+        self.layout = Some(layout);
+        self
+    }
+
+    /// label reported as `allocator="..."` in metrics output.
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    /// serves large requests from a RingArena over the arena region, so they wrap to the start instead of failing once the region is used up.
+    pub fn ring_arena(mut self, enabled: bool) -> Self {
+        self.ring = enabled;
+        self
+    }
+
+    /// pads every request size to the blinder's smallest fitting bucket before routing, so the allocator only ever serves bucket-sized requests.
+    pub fn size_blinder(mut self, blinder: SizeBlinder) -> Self {
+        self.blinder = Some(blinder);
+        self
+    }
+
+    /// reserves `fraction` of tier `tier_index`'s blocks, rounded down, for `tenant`'s allocate_for calls; past that the tenant competes for the tier's unreserved blocks like everyone else. reserving the same tenant and tier again replaces the fraction, and build fails if a tier's fractions sum past one.
+    /// the counts only see allocate_for and free_for: blocks taken through plain allocate are invisible to them, so reservations only hold if every caller uses allocate_for.
+    pub fn reserve_tier_fraction(mut self, tenant: u32, tier_index: usize, fraction: f32) -> Self {
+        self.reservations.push((tenant, tier_index, fraction));
+        self
+    }
+
+    /// how many nonces free_with_nonce remembers, DEFAULT_NONCE_WINDOW unless set; older nonces are evicted least recently seen first.
+    pub fn nonce_window(mut self, capacity: usize) -> Self {
+        self.nonce_window = capacity;
+        self
+    }
+
+    /// gives the allocator an EncryptedPrng seeded with the client's encrypted, non-zero `seed`, reachable through CryptMalloc::oblivious_rng.
+    pub fn oblivious_rng(mut self, seed: FheUint64) -> Self {
+        self.rng = Some(EncryptedPrng::new(seed));
+        self
+    }
+
+    /// registers the finished allocator in the process-wide registry under `name`; see CryptMalloc::register.
+    #[cfg(feature = "pool-registry")]
+    pub fn register(mut self, name: &str) -> Self {
+        self.registry_name = Some(name.to_string());
+        self
+    }
+
+    /// the layout build() would use, computed without generating keys. planned layouts are passed through Layout::aligned, so every tier is self-aligned and the arena starts on an ARENA_ALIGNMENT boundary.
+    /// panics if the tiers and arena do not fit below u64::MAX or the OS RNG fails; see try_plan_layout.
+    pub fn plan_layout(&self) -> Layout {
+        self.try_plan_layout().expect("layout planning failed")
+    }
+
+    /// plan_layout, reporting tiers or an arena that run past u64::MAX as OffsetOverflow or ArenaBounds, and an unavailable OS RNG as Rng, instead of panicking.
+    pub fn try_plan_layout(&self) -> Result<Layout, LayoutError> {
+        if let Some(layout) = &self.layout {
+            return Ok(layout.clone());
+        }
+        let planned = match self.randomize {
+            Some(seed) => {
+                let seed = match seed {
+                    Some(seed) => seed,
+                    None => Layout::fresh_seed()?,
+                };
+                Layout::try_randomized(&self.tiers, self.arena_size, seed)?
+            }
+            None => Layout::try_contiguous(&self.tiers, self.arena_size)?,
+        };
+        Ok(planned.aligned())
+    }
+
+    /// validates the layout and generates keys without encrypting any tier tables; see CryptMalloc::prepare.
+    pub fn prepare(self) -> Result<PreparedAllocator, LayoutError> {
+        let layout = self.try_plan_layout()?;
+        layout.validate()?;
+        let num_blocks: Vec<usize> = layout.tiers.iter().map(|tier| tier.num_blocks).collect();
+        let reservations = reservation_caps(&num_blocks, &self.reservations)?;
+        let mut prepared = PreparedAllocator::new(self.keys.unwrap_or_default(), layout);
+        prepared.name = self.name;
+        prepared.ring = self.ring;
+        prepared.blinder = self.blinder;
+        prepared.reservations = reservations;
+        prepared.nonce_window = self.nonce_window;
+        prepared.rng = self.rng;
+        #[cfg(feature = "pool-registry")]
+        {
+            prepared.registry_name = self.registry_name;
+        }
+        Ok(prepared)
+    }
+
+    pub fn build(self) -> Result<CryptMalloc, LayoutError> {
+        Ok(self.prepare()?.finish())
+    }
+}
+
+/// PreparedAllocator is the first phase of two-phase construction: keys exist and the layout is fixed, but each tier's encrypted tables are built on demand, exactly once, even under concurrent first use.
+/// Routing needs every tier, so allocation is only offered by the CryptMalloc that finish() returns; finish blocks on whichever tiers are still missing.
+pub struct PreparedAllocator {
+    keys: Keys,
+    layout: Layout,
+    tiers: Vec<OnceCell<SlabClass>>,
+    name: Option<String>,
+    ring: bool,
+    blinder: Option<SizeBlinder>,
+    // validated (tenant, tier, blocks) caps.
+    reservations: Vec<(u32, usize, u32)>,
+    nonce_window: usize,
+    rng: Option<EncryptedPrng>,
+    #[cfg(feature = "pool-registry")]
+    registry_name: Option<String>,
+}
+
+impl fmt::Debug for PreparedAllocator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PreparedAllocator")
+            .field("layout", &self.layout)
+            .field("progress", &self.construction_progress())
+            .finish()
+    }
+}
+
+impl PreparedAllocator {
+    fn new(keys: Keys, layout: Layout) -> Self {
+        let tiers = layout.tiers.iter().map(|_| OnceCell::new()).collect();
+        Self {
+            keys,
+            layout,
+            tiers,
+            name: None,
+            ring: false,
+            blinder: None,
+            reservations: Vec::new(),
+            nonce_window: DEFAULT_NONCE_WINDOW,
+            rng: None,
+            #[cfg(feature = "pool-registry")]
+            registry_name: None,
+        }
+    }
+
+    pub fn keys(&self) -> &Keys {
+        &self.keys
+    }
+
+    pub fn layout(&self) -> &Layout {
+        &self.layout
+    }
+
+    /// whether each tier's tables are ready, in routing order.
+    pub fn construction_progress(&self) -> Vec<bool> {
+        self.tiers.iter().map(|tier| tier.get().is_some()).collect()
+    }
+
+    /// builds `tier` on first use; concurrent callers block on the single initialization and all receive the same slab.
+    pub fn ensure_tier(&self, tier: usize) -> &SlabClass {
+        self.tiers[tier]
+            .get_or_init(|| CryptMalloc::build_slab(&self.keys, &self.layout.tiers[tier]))
+    }
+
+    /// builds any missing tiers and the arena, then applies the builder's name, ring, blinder, reservation, nonce window and rng options.
+    pub fn finish(self) -> CryptMalloc {
+        for tier in 0..self.tiers.len() {
+            self.ensure_tier(tier);
+        }
+        let slabs = self
+            .tiers
+            .into_iter()
+            .map(|tier| tier.into_inner().expect("every tier was just initialized"))
+            .collect();
+
+        let mut allocator = CryptMalloc::assemble(self.keys, self.layout, slabs);
+        if let Some(name) = self.name {
+            allocator.name = name;
+        }
+        allocator.rounder.set_blinder(self.blinder);
+        allocator.freed_nonces = NonceWindow::new(self.nonce_window);
+        allocator.rng = self.rng;
+        if !self.reservations.is_empty() {
+            let num_blocks: Vec<usize> = allocator
+                .layout
+                .tiers
+                .iter()
+                .map(|tier| tier.num_blocks)
+                .collect();
+            allocator.tenants = Some(TenantTable::new(
+                &allocator.keys,
+                &num_blocks,
+                &self.reservations,
+            ));
+        }
+        if self.ring {
+            let arena = &allocator.arena;
+            allocator.ring = Some(RingArena::new(
+                arena.start().clone(),
+                arena.end().clone(),
+                allocator.keys.server_key(),
+                arena.enc_false().clone(),
+                allocator.enc_zero_u64.clone(),
+            ));
+        }
+        #[cfg(feature = "pool-registry")]
+        if let Some(name) = self.registry_name {
+            allocator.register(&name);
         }
+        allocator
     }
 }
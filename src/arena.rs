@@ -12,6 +12,7 @@ pub struct Arena {
     server_key: ServerKey,
     enc_false: FheBool,
     enc_zero_u64: FheUint64,
+    size_rounding: bool,
 }
 
 impl core::fmt::Debug for Arena {
@@ -40,9 +41,19 @@ impl Arena {
             server_key,
             enc_false,
             enc_zero_u64,
+            size_rounding: false,
         }
     }
 
+    /// when enabled, allocate_aligned also rounds the requested size up to the alignment so consecutive same-alignment requests stay aligned without paying padding again.
+    pub fn set_size_rounding(&mut self, enabled: bool) {
+        self.size_rounding = enabled;
+    }
+
+    pub fn size_rounding(&self) -> bool {
+        self.size_rounding
+    }
+
     pub fn allocate(&mut self, size: FheUint64) -> EncryptedOption<EncryptedPtr> {
         set_server_key(self.server_key.clone());
 
@@ -60,11 +71,67 @@ impl Arena {
         }
     }
 
+    /// aligned bump allocation; `align` is a plaintext power of two. the cursor is rounded up homomorphically before the bounds check, and with size rounding on the size is rounded too, so the usable span of the returned pointer is `rounded_size(size, align)`. any wrap in the rounding or bump clears `is_some`.
+    pub fn allocate_aligned(
+        &mut self,
+        size: FheUint64,
+        align: u64,
+    ) -> EncryptedOption<EncryptedPtr> {
+        set_server_key(self.server_key.clone());
+        assert!(
+            align.is_power_of_two(),
+            "arena alignment must be a power of two"
+        );
+
+        let mask = align - 1;
+        let aligned_cursor = (&self.cursor + mask) & !mask;
+        let span = if self.size_rounding {
+            self.rounded_size(&size, align)
+        } else {
+            size.clone()
+        };
+        let new_cursor = &aligned_cursor + &span;
+
+        let has_space = new_cursor.le(&self.end);
+        let cursor_wrapped = aligned_cursor.lt(&self.cursor);
+        let bump_wrapped = new_cursor.lt(&aligned_cursor);
+        let span_wrapped = span.lt(&size);
+        let wrapped = (&cursor_wrapped | &bump_wrapped) | &span_wrapped;
+        let ok = (&has_space) & (&wrapped.not());
+
+        let ptr_val = ok.if_then_else(&aligned_cursor, &self.enc_zero_u64);
+        self.cursor = ok.if_then_else(&new_cursor, &self.cursor);
+
+        EncryptedOption {
+            value: EncryptedPtr::new(ptr_val),
+            is_some: ok,
+        }
+    }
+
+    /// `size` rounded up to the next multiple of `align` (power of two), computed with scalar add/and so no constant is encrypted.
+    pub fn rounded_size(&self, size: &FheUint64, align: u64) -> FheUint64 {
+        set_server_key(self.server_key.clone());
+        let mask = align - 1;
+        (size + mask) & !mask
+    }
+
     pub fn reset(&mut self) {
         set_server_key(self.server_key.clone());
         self.cursor = self.start.clone();
     }
 
+    /// encrypted bytes left between the cursor and `end`; allocation never moves the cursor past `end`, so the subtraction cannot wrap.
+    pub fn remaining(&self) -> FheUint64 {
+        set_server_key(self.server_key.clone());
+        &self.end - &self.cursor
+    }
+
+    /// encrypted `remaining() >= threshold`, so a client can learn whether a request of that size would fit without either side revealing the cursor or the threshold. one subtraction and one comparison whatever the occupancy.
+    pub fn capacity_proof(&self, threshold: FheUint64) -> FheBool {
+        set_server_key(self.server_key.clone());
+        self.remaining().ge(&threshold)
+    }
+
     pub fn start(&self) -> &FheUint64 {
         set_server_key(self.server_key.clone());
         &self.start
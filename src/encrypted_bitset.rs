@@ -0,0 +1,168 @@
+//! EncryptedBitset is a fixed-length vector of encrypted bits with oblivious indexed access; every operation scans all positions, so neither the index nor the bit values leak through timing.
+//! It backs SlabClass's allocation bitmap and is public so callers can keep their own encrypted occupancy sets with the same guarantees.
+
+use crate::keys::Keys;
+use core::fmt;
+use std::ops::Not;
+use tfhe::{prelude::*, set_server_key, FheBool, FheUint32, ServerKey};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BitsetOp {
+    And,
+    Or,
+    Xor,
+    AndNot,
+}
+
+#[derive(Clone)]
+pub struct EncryptedBitset {
+    bits: Vec<FheBool>,
+    enc_indices_u32: Vec<FheUint32>,
+    server_key: ServerKey,
+    enc_false: FheBool,
+    enc_zero_u32: FheUint32,
+}
+
+impl fmt::Debug for EncryptedBitset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncryptedBitset")
+            .field("len", &self.bits.len())
+            .field("bits", &"<ciphertext>")
+            .finish()
+    }
+}
+
+impl EncryptedBitset {
+    /// all-clear bitset of `len` bits; the clear bit and the zero index are encrypted fresh from `keys`, bypassing its constant cache, and the index table comes from its table cache.
+    pub fn new(keys: &Keys, len: usize) -> Self {
+        Self::from_parts(
+            keys.server_key(),
+            keys.enc_false(),
+            keys.enc_zero_u32(),
+            keys.build_enc_indices_u32(len),
+        )
+    }
+
+    /// all-clear bitset sized by `enc_indices_u32`, which must hold the encryptions of `0..len` in order.
+    pub fn from_parts(
+        server_key: ServerKey,
+        enc_false: FheBool,
+        enc_zero_u32: FheUint32,
+        enc_indices_u32: Vec<FheUint32>,
+    ) -> Self {
+        set_server_key(server_key.clone());
+        let bits = vec![enc_false.clone(); enc_indices_u32.len()];
+        Self {
+            bits,
+            enc_indices_u32,
+            server_key,
+            enc_false,
+            enc_zero_u32,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.bits.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bits.is_empty()
+    }
+
+    pub fn bits(&self) -> &[FheBool] {
+        set_server_key(self.server_key.clone());
+        &self.bits
+    }
+
+    pub fn enc_indices_u32(&self) -> &[FheUint32] {
+        set_server_key(self.server_key.clone());
+        &self.enc_indices_u32
+    }
+
+    /// sets the bit at `index_enc` when `condition` holds; out-of-range indices match nothing.
+    pub fn set_if(&mut self, index_enc: &FheUint32, condition: &FheBool) {
+        set_server_key(self.server_key.clone());
+        for i in 0..self.bits.len() {
+            let hit = &self.enc_indices_u32[i].eq(index_enc) & condition;
+            self.bits[i] = &self.bits[i] | &hit;
+        }
+    }
+
+    pub fn clear_if(&mut self, index_enc: &FheUint32, condition: &FheBool) {
+        set_server_key(self.server_key.clone());
+        for i in 0..self.bits.len() {
+            let hit = &self.enc_indices_u32[i].eq(index_enc) & condition;
+            self.bits[i] = &self.bits[i] & &hit.not();
+        }
+    }
+
+    /// sets the bit at a plaintext position when `condition` holds; the counterpart of clear_at_if.
+    pub fn set_at_if(&mut self, index: usize, condition: &FheBool) {
+        set_server_key(self.server_key.clone());
+        self.bits[index] = &self.bits[index] | condition;
+    }
+
+    /// clears the bit at a plaintext position when `condition` holds; for scans that already visit every position themselves.
+    pub fn clear_at_if(&mut self, index: usize, condition: &FheBool) {
+        set_server_key(self.server_key.clone());
+        self.bits[index] = &self.bits[index] & &condition.clone().not();
+    }
+
+    pub fn clear_all(&mut self) {
+        set_server_key(self.server_key.clone());
+        for bit in self.bits.iter_mut() {
+            *bit = self.enc_false.clone();
+        }
+    }
+
+    /// encrypted value of the bit at `index_enc`, false when the index is out of range.
+    pub fn test(&self, index_enc: &FheUint32) -> FheBool {
+        set_server_key(self.server_key.clone());
+        let mut result = self.enc_false.clone();
+        for i in 0..self.bits.len() {
+            let hit = &self.enc_indices_u32[i].eq(index_enc) & &self.bits[i];
+            result = &result | &hit;
+        }
+        result
+    }
+
+    pub fn popcount(&self) -> FheUint32 {
+        set_server_key(self.server_key.clone());
+        let mut count = self.enc_zero_u32.clone();
+        for bit in self.bits.iter() {
+            count = &count + &FheUint32::cast_from(bit.clone());
+        }
+        count
+    }
+
+    /// lowest clear position and whether one exists; when the bitset is full the index is zero and the flag is false.
+    pub fn first_clear(&self) -> (FheUint32, FheBool) {
+        set_server_key(self.server_key.clone());
+        let mut found = self.enc_false.clone();
+        let mut index = self.enc_zero_u32.clone();
+        for i in 0..self.bits.len() {
+            let take = &self.bits[i].clone().not() & &found.clone().not();
+            index = take.if_then_else(&self.enc_indices_u32[i], &index);
+            found = &found | &take;
+        }
+        (index, found)
+    }
+
+    /// combines `other` into `self` bit by bit; both bitsets must have the same length.
+    pub fn apply_mask(&mut self, other: &Self, op: BitsetOp) {
+        set_server_key(self.server_key.clone());
+        assert_eq!(
+            self.bits.len(),
+            other.bits.len(),
+            "bitset lengths must match"
+        );
+        for (bit, mask) in self.bits.iter_mut().zip(other.bits.iter()) {
+            *bit = match op {
+                BitsetOp::And => &*bit & mask,
+                BitsetOp::Or => &*bit | mask,
+                BitsetOp::Xor => &*bit ^ mask,
+                BitsetOp::AndNot => &*bit & &mask.clone().not(),
+            };
+        }
+    }
+}
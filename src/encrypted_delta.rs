@@ -0,0 +1,70 @@
+//! EncryptedDelta is a signed byte displacement kept as an encrypted magnitude plus an encrypted sign flag, enough to move a pointer by ±N bytes without signed FHE integers.
+//! Applying a delta always pays for both directions and keeps the one the sign selects, so the sign never shows up as a branch.
+
+use crate::{
+    encrypted_ptr::EncryptedPtr,
+    keys::{reseat_server_key, Keys},
+};
+use core::fmt;
+use serde::{Deserialize, Serialize};
+use tfhe::{FheBool, FheUint64};
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct EncryptedDelta {
+    pub magnitude: FheUint64,
+    /// true when the delta moves towards lower addresses.
+    pub negative: FheBool,
+}
+
+impl fmt::Debug for EncryptedDelta {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncryptedDelta")
+            .field("magnitude", &"<ciphertext>")
+            .field("negative", &"<ciphertext>")
+            .finish()
+    }
+}
+
+impl EncryptedDelta {
+    pub fn new(magnitude: FheUint64, negative: FheBool) -> Self {
+        reseat_server_key();
+        Self {
+            magnitude,
+            negative,
+        }
+    }
+
+    /// client-side constructor; the magnitude is |delta|, so i64::MIN encrypts as 2^63 with the sign set.
+    pub fn encrypt(keys: &Keys, delta: i64) -> Self {
+        let negative = if delta < 0 {
+            keys.enc_true()
+        } else {
+            keys.enc_false()
+        };
+        Self::new(keys.enc_u64(delta.unsigned_abs()), negative)
+    }
+
+    /// the same magnitude with the sign flipped.
+    pub fn negated(&self) -> Self {
+        reseat_server_key();
+        Self {
+            magnitude: self.magnitude.clone(),
+            negative: !&self.negative,
+        }
+    }
+
+    /// `ptr ± magnitude`, wrapping mod 2^64 like the rest of the crate's offset arithmetic.
+    pub fn apply_to_ptr(&self, ptr: &EncryptedPtr) -> EncryptedPtr {
+        ptr.wrapping_add_signed(&self.magnitude, &self.negative)
+    }
+
+    /// trusted-side decryption back to a signed value; magnitudes above i64::MAX wrap.
+    pub fn decrypt(&self, keys: &Keys) -> i64 {
+        let magnitude = keys.dec_u64(&self.magnitude) as i64;
+        if keys.dec_bool(&self.negative) {
+            magnitude.wrapping_neg()
+        } else {
+            magnitude
+        }
+    }
+}
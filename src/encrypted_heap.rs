@@ -0,0 +1,116 @@
+//! EncryptedHeap is a fixed-capacity priority queue over encrypted u32 priorities with u64 payloads; slot occupancy lives in an EncryptedBitset and every operation scans all slots, so neither priorities nor queue order leak.
+//! No heap property is maintained because the scans are full anyway: insert is one first-free scan, peek_min and extract_min each cost O(capacity) ciphertext comparisons.
+
+use crate::{encrypted_bitset::EncryptedBitset, encrypted_option::EncryptedOption, keys::Keys};
+use core::fmt;
+use std::ops::Not;
+use tfhe::{prelude::*, set_server_key, FheBool, FheUint32, FheUint64, ServerKey};
+
+#[derive(Clone)]
+pub struct EncryptedHeap {
+    priorities: Vec<FheUint32>,
+    payloads: Vec<FheUint64>,
+    occupied: EncryptedBitset,
+    server_key: ServerKey,
+    enc_false: FheBool,
+    enc_zero_u32: FheUint32,
+    enc_zero_u64: FheUint64,
+}
+
+impl fmt::Debug for EncryptedHeap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncryptedHeap")
+            .field("capacity", &self.priorities.len())
+            .field("slots", &"<ciphertext>")
+            .finish()
+    }
+}
+
+impl EncryptedHeap {
+    pub fn new(keys: &Keys, capacity: usize) -> Self {
+        let server_key = keys.server_key();
+        set_server_key(server_key.clone());
+        let enc_zero_u32 = keys.enc_u32_cached(0);
+        let enc_zero_u64 = keys.enc_u64_cached(0);
+        Self {
+            priorities: vec![enc_zero_u32.clone(); capacity],
+            payloads: vec![enc_zero_u64.clone(); capacity],
+            occupied: EncryptedBitset::new(keys, capacity),
+            server_key,
+            enc_false: keys.enc_false_cached(),
+            enc_zero_u32,
+            enc_zero_u64,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.priorities.len()
+    }
+
+    /// encrypted number of occupied slots.
+    pub fn len(&self) -> FheUint32 {
+        set_server_key(self.server_key.clone());
+        self.occupied.popcount()
+    }
+
+    /// stores the entry in the first free slot when `condition` holds; returns whether it was placed, which is false when the heap is full.
+    pub fn insert(
+        &mut self,
+        priority: &FheUint32,
+        payload: &FheUint64,
+        condition: &FheBool,
+    ) -> FheBool {
+        set_server_key(self.server_key.clone());
+
+        let (slot, has_room) = self.occupied.first_clear();
+        let place = &has_room & condition;
+        for i in 0..self.priorities.len() {
+            let hit = &self.occupied.enc_indices_u32()[i].eq(&slot) & &place;
+            self.priorities[i] = hit.if_then_else(priority, &self.priorities[i]);
+            self.payloads[i] = hit.if_then_else(payload, &self.payloads[i]);
+        }
+        self.occupied.set_if(&slot, &place);
+        place
+    }
+
+    /// smallest occupied priority and its payload without removing it; ties resolve to the lowest slot.
+    pub fn peek_min(&self) -> EncryptedOption<(FheUint32, FheUint64)> {
+        set_server_key(self.server_key.clone());
+        let (entry, _, found) = self.scan_min();
+        EncryptedOption {
+            value: entry,
+            is_some: found,
+        }
+    }
+
+    /// removes and returns the minimum entry when `condition` holds; with `condition` false the heap is unchanged and `is_some` is false, after the same scans.
+    pub fn extract_min(&mut self, condition: &FheBool) -> EncryptedOption<(FheUint32, FheUint64)> {
+        set_server_key(self.server_key.clone());
+        let (entry, slot, found) = self.scan_min();
+        let take = &found & condition;
+        self.occupied.clear_if(&slot, &take);
+        EncryptedOption {
+            value: entry,
+            is_some: take,
+        }
+    }
+
+    // (entry, slot index, any occupied) for the minimum occupied slot.
+    fn scan_min(&self) -> ((FheUint32, FheUint64), FheUint32, FheBool) {
+        let mut found = self.enc_false.clone();
+        let mut best_priority = self.enc_zero_u32.clone();
+        let mut best_payload = self.enc_zero_u64.clone();
+        let mut best_slot = self.enc_zero_u32.clone();
+
+        for i in 0..self.priorities.len() {
+            let occupied = &self.occupied.bits()[i];
+            let smaller = self.priorities[i].lt(&best_priority);
+            let better = occupied & &(&found.clone().not() | &smaller);
+            best_priority = better.if_then_else(&self.priorities[i], &best_priority);
+            best_payload = better.if_then_else(&self.payloads[i], &best_payload);
+            best_slot = better.if_then_else(&self.occupied.enc_indices_u32()[i], &best_slot);
+            found = &found | occupied;
+        }
+        ((best_priority, best_payload), best_slot, found)
+    }
+}
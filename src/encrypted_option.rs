@@ -2,17 +2,15 @@
 //! `combine_with` relies on `cond.if_then_else(&then, &else)` plus encrypted-or, so no plaintext control flow ever decides which branch wins.
 //! Callers feed it payloads that are cmux-able by value (FHE integers, booleans, EncryptedPtr) and let the selector move ciphertext handles without exposing them.
 
-use crate::{encrypted_ptr::EncryptedPtr, keys::clone_global_server_key};
+use crate::{encrypted_ptr::EncryptedPtr, keys::reseat_server_key};
 use core::fmt;
-use tfhe::{prelude::IfThenElse, set_server_key, FheBool, FheUint32, FheUint64};
+use serde::{Deserialize, Serialize};
+use tfhe::{
+    prelude::{FheEq, IfThenElse},
+    FheBool, FheUint32, FheUint64,
+};
 
-fn reseat_server_key() {
-    if let Some(server_key) = clone_global_server_key() {
-        set_server_key(server_key);
-    }
-}
-
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct EncryptedOption<T: Clone> {
     pub value: T,
     pub is_some: FheBool,
@@ -58,10 +56,69 @@ where
             is_some: combined_flag,
         }
     }
+
+    /// combine_with in place: the select result replaces `self.value` and the flag is OR-assigned, so neither of self's ciphertexts is cloned.
+    pub fn accumulate(&mut self, other: &Self) {
+        reseat_server_key();
+        self.value = T::select(&other.is_some, &other.value, &self.value);
+        self.is_some |= &other.is_some;
+    }
+
+    /// accumulates every option into `init` left to right; equivalent to chaining combine_with from `init`, so the last option with `is_some` wins.
+    pub fn fold(options: &[Self], init: Self) -> Self {
+        reseat_server_key();
+        let mut acc = init;
+        for option in options {
+            acc.accumulate(option);
+        }
+        acc
+    }
+
+    /// fold seeded with the first option, so `[a, b, c]` gives `a.combine_with(b).combine_with(c)`; returns None only for an empty slice.
+    pub fn combine_many(options: &[Self]) -> Option<Self> {
+        let (first, rest) = options.split_first()?;
+        Some(Self::fold(rest, first.clone()))
+    }
+}
+
+impl<T> EncryptedOption<T>
+where
+    T: Clone + CipherSelectable,
+{
+    /// oblivious gather: every slot is compared against the encrypted index (plaintext positions, scalar comparisons) and payload plus flag are selected together; an out-of-range index yields `is_some = false` with slot 0's payload as filler. returns None only for an empty slice.
+    pub fn gather(options: &[Self], index: &FheUint32) -> Option<Self> {
+        reseat_server_key();
+        let (first, rest) = options.split_first()?;
+
+        let first_hit = index.eq(0u32);
+        let mut value = first.value.clone();
+        let mut is_some = &first_hit & &first.is_some;
+        for (offset, slot) in rest.iter().enumerate() {
+            let hit = index.eq(offset as u32 + 1);
+            value = T::select(&hit, &slot.value, &value);
+            is_some = &is_some | &(&hit & &slot.is_some);
+        }
+        Some(Self { value, is_some })
+    }
+
+    /// oblivious scatter: every slot is rewritten through a select, and the targeted slot takes both the new payload and the new flag; out-of-range indices leave the slice unchanged.
+    pub fn scatter(options: &mut [Self], index: &FheUint32, replacement: &Self) {
+        reseat_server_key();
+        for (position, slot) in options.iter_mut().enumerate() {
+            let hit = index.eq(position as u32);
+            slot.value = T::select(&hit, &replacement.value, &slot.value);
+            slot.is_some = hit.if_then_else(&replacement.is_some, &slot.is_some);
+        }
+    }
 }
 
 pub trait CipherSelectable: Clone {
     fn select(cond: &FheBool, when_true: &Self, when_false: &Self) -> Self;
+
+    /// select for callers that already installed the server key on this thread; batch helpers use it to install once per batch instead of once per element.
+    fn select_installed(cond: &FheBool, when_true: &Self, when_false: &Self) -> Self {
+        Self::select(cond, when_true, when_false)
+    }
 }
 
 impl CipherSelectable for FheBool {
@@ -69,6 +126,10 @@ impl CipherSelectable for FheBool {
         reseat_server_key();
         cond.if_then_else(when_true, when_false)
     }
+
+    fn select_installed(cond: &FheBool, when_true: &Self, when_false: &Self) -> Self {
+        cond.if_then_else(when_true, when_false)
+    }
 }
 
 impl CipherSelectable for FheUint32 {
@@ -76,6 +137,10 @@ impl CipherSelectable for FheUint32 {
         reseat_server_key();
         cond.if_then_else(when_true, when_false)
     }
+
+    fn select_installed(cond: &FheBool, when_true: &Self, when_false: &Self) -> Self {
+        cond.if_then_else(when_true, when_false)
+    }
 }
 
 impl CipherSelectable for FheUint64 {
@@ -83,6 +148,10 @@ impl CipherSelectable for FheUint64 {
         reseat_server_key();
         cond.if_then_else(when_true, when_false)
     }
+
+    fn select_installed(cond: &FheBool, when_true: &Self, when_false: &Self) -> Self {
+        cond.if_then_else(when_true, when_false)
+    }
 }
 
 impl CipherSelectable for EncryptedPtr {
@@ -91,4 +160,61 @@ impl CipherSelectable for EncryptedPtr {
         let chosen_offset = cond.if_then_else(&when_true.0, &when_false.0);
         EncryptedPtr::new(chosen_offset)
     }
+
+    fn select_installed(cond: &FheBool, when_true: &Self, when_false: &Self) -> Self {
+        EncryptedPtr(cond.if_then_else(&when_true.0, &when_false.0))
+    }
+}
+
+/// element-wise `cond ? when_true[i] : when_false[i]` under one condition; the server key is installed once per batch (once per rayon worker with the `parallel` feature). returns None when the slices differ in length.
+pub fn select_many<T>(cond: &FheBool, when_true: &[T], when_false: &[T]) -> Option<Vec<T>>
+where
+    T: CipherSelectable + Send + Sync,
+{
+    if when_true.len() != when_false.len() {
+        return None;
+    }
+    reseat_server_key();
+    Some(select_zipped(cond, when_true.iter().zip(when_false)))
+}
+
+/// select_many over `(when_true, when_false)` pairs, which cannot have mismatched lengths.
+pub fn select_pairs<T>(cond: &FheBool, pairs: &[(T, T)]) -> Vec<T>
+where
+    T: CipherSelectable + Send + Sync,
+{
+    reseat_server_key();
+    select_zipped(
+        cond,
+        pairs
+            .iter()
+            .map(|(when_true, when_false)| (when_true, when_false)),
+    )
+}
+
+#[cfg(feature = "parallel")]
+fn select_zipped<'a, T, I>(cond: &FheBool, pairs: I) -> Vec<T>
+where
+    T: CipherSelectable + Send + Sync + 'a,
+    I: Iterator<Item = (&'a T, &'a T)>,
+{
+    use rayon::prelude::*;
+    let pairs: Vec<_> = pairs.collect();
+    pairs
+        .par_iter()
+        .map_init(reseat_server_key, |_, (when_true, when_false)| {
+            T::select_installed(cond, when_true, when_false)
+        })
+        .collect()
+}
+
+#[cfg(not(feature = "parallel"))]
+fn select_zipped<'a, T, I>(cond: &FheBool, pairs: I) -> Vec<T>
+where
+    T: CipherSelectable + 'a,
+    I: Iterator<Item = (&'a T, &'a T)>,
+{
+    pairs
+        .map(|(when_true, when_false)| T::select_installed(cond, when_true, when_false))
+        .collect()
 }
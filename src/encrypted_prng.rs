@@ -0,0 +1,78 @@
+//! EncryptedPrng is a xorshift64 generator whose state lives in an FheUint64 seeded by the client, so the server can draw tie-breaking randomness it never learns and no plaintext RNG output ever correlates with a decision.
+//! Each step costs three scalar shifts and three xors on the encrypted state; tfhe's operations bootstrap as they go, so the state can be stepped indefinitely without a manual refresh. The sequence is deterministic in the seed, which must be non-zero (zero is xorshift's fixed point).
+//! CryptMallocBuilder::oblivious_rng installs one on an allocator, where CryptMalloc::oblivious_rng hands it to whatever needs the draws.
+
+use crate::keys::reseat_server_key;
+use core::fmt;
+use serde::{Deserialize, Serialize};
+use tfhe::{prelude::*, FheBool, FheUint64};
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct EncryptedPrng {
+    state: FheUint64,
+    draws: u64,
+}
+
+impl fmt::Debug for EncryptedPrng {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncryptedPrng")
+            .field("state", &"<ciphertext>")
+            .field("draws", &self.draws)
+            .finish()
+    }
+}
+
+impl EncryptedPrng {
+    pub fn new(seed: FheUint64) -> Self {
+        reseat_server_key();
+        Self {
+            state: seed,
+            draws: 0,
+        }
+    }
+
+    /// how many times the state has been stepped; plaintext, it only counts calls.
+    pub fn draws(&self) -> u64 {
+        self.draws
+    }
+
+    /// `n` fresh bits in the low end of the result, taken from the high end of the state where xorshift mixes best; `n` is clamped to 1..=64.
+    pub fn next_bits(&mut self, n: u32) -> FheUint64 {
+        let n = n.clamp(1, 64);
+        let state = self.step();
+        if n == 64 {
+            state
+        } else {
+            state >> (64 - n as u64)
+        }
+    }
+
+    /// a value below `bound`, which must be a power of two so keeping the top log2(bound) bits stays uniform.
+    pub fn next_below(&mut self, bound: u64) -> FheUint64 {
+        assert!(
+            bound.is_power_of_two(),
+            "bound {bound} is not a power of two"
+        );
+        match bound.trailing_zeros() {
+            // bound 1 only admits zero: the drawn bit is shifted out, and the state still steps so the call costs what any other does.
+            0 => self.next_bits(1) >> 1u64,
+            bits => self.next_bits(bits),
+        }
+    }
+
+    /// the top bit of the next state.
+    pub fn next_bool(&mut self) -> FheBool {
+        self.step().ge(1u64 << 63)
+    }
+
+    fn step(&mut self) -> FheUint64 {
+        reseat_server_key();
+        let mut x = self.state.clone();
+        x ^= &x << 13u64;
+        x ^= &x >> 7u64;
+        x ^= &x << 17u64;
+        self.state = x.clone();
+        self.draws += 1;
+        x
+    }
+}
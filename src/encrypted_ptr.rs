@@ -1,19 +1,26 @@
 /// EncryptedPtr carries a single `FheUint64` byte offset; null is `EncryptedPtr(enc_zero_u64)` and no plaintext address math ever happens.
 /// Downstream slabs treat the wrapped ciphertext as the full pointer payload and reseat the global server key before constructing one.
-use crate::keys::clone_global_server_key;
+use crate::keys::reseat_server_key;
 use core::fmt;
-use tfhe::{set_server_key, FheUint64};
+use serde::{Deserialize, Serialize};
+use tfhe::{prelude::IfThenElse, FheBool, FheUint64};
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct EncryptedPtr(pub FheUint64);
 
 impl EncryptedPtr {
     pub fn new(offset: FheUint64) -> Self {
-        if let Some(server_key) = clone_global_server_key() {
-            set_server_key(server_key);
-        }
+        reseat_server_key();
         Self(offset)
     }
+
+    /// adds `magnitude` when `negative` is false and subtracts it when true, wrapping mod 2^64; costs one negation, one select and one add whatever the sign.
+    pub fn wrapping_add_signed(&self, magnitude: &FheUint64, negative: &FheBool) -> Self {
+        reseat_server_key();
+        let negated = -magnitude;
+        let step = negative.if_then_else(&negated, magnitude);
+        Self(&self.0 + &step)
+    }
 }
 
 impl fmt::Debug for EncryptedPtr {
@@ -0,0 +1,146 @@
+//! envelope frames every persistent artifact as `magic | format version | payload length | payload | checksum` so blobs from other crate versions either migrate explicitly or fail with both versions named.
+//! The checksum is FNV-1a over everything before it; it catches truncation and bit rot, it is not an authenticity guarantee.
+
+use bincode::Options;
+use core::fmt;
+use serde::{de::DeserializeOwned, Serialize};
+
+/// the default Persistent::MAX_PAYLOAD; decoded payloads larger than their type's limit are rejected before bincode allocates for them.
+pub const MAX_ENVELOPE_PAYLOAD: u64 = 64 * 1024 * 1024;
+
+const HEADER_LEN: usize = 4 + 2 + 8;
+const CHECKSUM_LEN: usize = 8;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EnvelopeError {
+    Truncated,
+    BadMagic { found: [u8; 4], expected: [u8; 4] },
+    UnsupportedVersion { found: u16, current: u16 },
+    LengthMismatch { declared: u64, available: u64 },
+    ChecksumMismatch,
+    Encode(String),
+    Decode(String),
+    Invalid(String),
+}
+
+impl fmt::Display for EnvelopeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Truncated => write!(f, "envelope is shorter than its fixed header"),
+            Self::BadMagic { found, expected } => {
+                write!(f, "envelope magic {found:?} does not match {expected:?}")
+            }
+            Self::UnsupportedVersion { found, current } => write!(
+                f,
+                "format version {found} cannot be migrated to current version {current}"
+            ),
+            Self::LengthMismatch {
+                declared,
+                available,
+            } => write!(
+                f,
+                "envelope declares {declared} payload bytes but {available} are present"
+            ),
+            Self::ChecksumMismatch => write!(f, "envelope checksum does not match its contents"),
+            Self::Encode(msg) => write!(f, "failed to encode payload: {msg}"),
+            Self::Decode(msg) => write!(f, "failed to decode payload: {msg}"),
+            Self::Invalid(msg) => write!(f, "decoded payload is invalid: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for EnvelopeError {}
+
+/// implemented by every type that is written to durable storage; bump FORMAT_VERSION on any layout change and teach `migrate` how to lift the previous version's payload.
+pub trait Persistent: Serialize + DeserializeOwned {
+    const MAGIC: [u8; 4];
+    const FORMAT_VERSION: u16;
+    /// decode limit for this type's payload; only types that are legitimately larger, such as exported keys, raise it.
+    const MAX_PAYLOAD: u64 = MAX_ENVELOPE_PAYLOAD;
+
+    /// rewrites a payload produced at `from_version` into the current version's payload; the default accepts no older versions.
+    fn migrate(from_version: u16, payload: Vec<u8>) -> Result<Vec<u8>, EnvelopeError> {
+        let _ = payload;
+        Err(EnvelopeError::UnsupportedVersion {
+            found: from_version,
+            current: Self::FORMAT_VERSION,
+        })
+    }
+
+    /// structural checks open runs on every decoded value, so a blob that passes the checksum but was crafted or migrated into an inconsistent state is rejected before use; the default accepts everything.
+    fn validate(&self) -> Result<(), EnvelopeError> {
+        Ok(())
+    }
+}
+
+fn payload_options(limit: u64) -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .with_little_endian()
+        .with_limit(limit)
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
+pub fn seal<T: Persistent>(value: &T) -> Result<Vec<u8>, EnvelopeError> {
+    let payload = payload_options(T::MAX_PAYLOAD)
+        .serialize(value)
+        .map_err(|err| EnvelopeError::Encode(err.to_string()))?;
+    let mut bytes = Vec::with_capacity(HEADER_LEN + payload.len() + CHECKSUM_LEN);
+    bytes.extend_from_slice(&T::MAGIC);
+    bytes.extend_from_slice(&T::FORMAT_VERSION.to_le_bytes());
+    bytes.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(&payload);
+    let checksum = fnv1a(&bytes);
+    bytes.extend_from_slice(&checksum.to_le_bytes());
+    Ok(bytes)
+}
+
+pub fn open<T: Persistent>(bytes: &[u8]) -> Result<T, EnvelopeError> {
+    if bytes.len() < HEADER_LEN + CHECKSUM_LEN {
+        return Err(EnvelopeError::Truncated);
+    }
+    let mut magic = [0u8; 4];
+    magic.copy_from_slice(&bytes[..4]);
+    if magic != T::MAGIC {
+        return Err(EnvelopeError::BadMagic {
+            found: magic,
+            expected: T::MAGIC,
+        });
+    }
+    let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+    let mut len_bytes = [0u8; 8];
+    len_bytes.copy_from_slice(&bytes[6..HEADER_LEN]);
+    let declared = u64::from_le_bytes(len_bytes);
+    let available = (bytes.len() - HEADER_LEN - CHECKSUM_LEN) as u64;
+    if declared != available {
+        return Err(EnvelopeError::LengthMismatch {
+            declared,
+            available,
+        });
+    }
+
+    let body_end = bytes.len() - CHECKSUM_LEN;
+    let mut checksum_bytes = [0u8; 8];
+    checksum_bytes.copy_from_slice(&bytes[body_end..]);
+    if fnv1a(&bytes[..body_end]) != u64::from_le_bytes(checksum_bytes) {
+        return Err(EnvelopeError::ChecksumMismatch);
+    }
+
+    let mut payload = bytes[HEADER_LEN..body_end].to_vec();
+    if version != T::FORMAT_VERSION {
+        payload = T::migrate(version, payload)?;
+    }
+    let value: T = payload_options(T::MAX_PAYLOAD)
+        .deserialize(&payload)
+        .map_err(|err| EnvelopeError::Decode(err.to_string()))?;
+    value.validate()?;
+    Ok(value)
+}
@@ -0,0 +1,148 @@
+//! CryptmallocError gathers every subsystem error behind one type so an application can run a single error-handling layer; subsystem APIs keep returning their own errors and `?` converts them.
+//! Category buckets errors by what the caller should do about them (retry, alert, fix configuration) rather than by the subsystem that raised them.
+
+use crate::{
+    envelope::EnvelopeError, evm::EvmError, layout::LayoutError, protocol::ProtocolError,
+    report::ReportError,
+};
+use core::fmt;
+
+#[cfg(feature = "simulation")]
+use crate::simulation::SimulationError;
+#[cfg(feature = "test-utils")]
+use crate::test_utils::OccupancyParseError;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Category {
+    /// key, randomness or ciphertext failures: the wrong kind of key set, or the OS RNG being unavailable.
+    Crypto,
+    /// a request or message exceeds a fixed limit.
+    Capacity,
+    /// bytes were truncated, corrupted or otherwise fail to decode.
+    Integrity,
+    /// contention or ordering failures; like Crypto, currently unused.
+    Concurrency,
+    /// a layout, version or other setup choice is invalid; retrying cannot help.
+    Config,
+    /// the caller passed arguments the API does not accept.
+    Usage,
+}
+
+/// non_exhaustive because the Simulation and Occupancy variants only exist with the `simulation` and `test-utils` features, and a feature turned on anywhere in the dependency graph must not break another crate's match.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum CryptmallocError {
+    Layout(LayoutError),
+    Envelope(EnvelopeError),
+    Protocol(ProtocolError),
+    Evm(EvmError),
+    Report(ReportError),
+    #[cfg(feature = "simulation")]
+    Simulation(SimulationError),
+    #[cfg(feature = "test-utils")]
+    Occupancy(OccupancyParseError),
+}
+
+impl CryptmallocError {
+    pub fn category(&self) -> Category {
+        match self {
+            Self::Layout(LayoutError::PlaintextTier { .. }) => Category::Usage,
+            Self::Layout(LayoutError::Rng(_)) => Category::Crypto,
+            Self::Layout(_) => Category::Config,
+            Self::Envelope(EnvelopeError::UnsupportedVersion { .. }) => Category::Config,
+            Self::Envelope(EnvelopeError::Encode(_)) => Category::Usage,
+            Self::Envelope(_) => Category::Integrity,
+            Self::Protocol(ProtocolError::UnsupportedVersion { .. }) => Category::Config,
+            Self::Protocol(ProtocolError::ServerOnlyKeys | ProtocolError::Rng(_)) => {
+                Category::Crypto
+            }
+            // in practice encoding only fails once a message passes MAX_MESSAGE_BYTES.
+            Self::Protocol(ProtocolError::Encode(_)) => Category::Capacity,
+            Self::Protocol(_) => Category::Integrity,
+            Self::Evm(EvmError::MemoryTooLarge { .. }) => Category::Capacity,
+            Self::Evm(EvmError::ProgramTooLong { .. }) => Category::Capacity,
+            Self::Evm(_) => Category::Usage,
+            Self::Report(_) => Category::Usage,
+            #[cfg(feature = "simulation")]
+            Self::Simulation(_) => Category::Usage,
+            #[cfg(feature = "test-utils")]
+            Self::Occupancy(_) => Category::Usage,
+        }
+    }
+}
+
+impl fmt::Display for CryptmallocError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Layout(err) => write!(f, "layout: {err}"),
+            Self::Envelope(err) => write!(f, "envelope: {err}"),
+            Self::Protocol(err) => write!(f, "protocol: {err}"),
+            Self::Evm(err) => write!(f, "evm: {err}"),
+            Self::Report(err) => write!(f, "report: {err}"),
+            #[cfg(feature = "simulation")]
+            Self::Simulation(err) => write!(f, "simulation: {err}"),
+            #[cfg(feature = "test-utils")]
+            Self::Occupancy(err) => write!(f, "occupancy: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for CryptmallocError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Layout(err) => Some(err),
+            Self::Envelope(err) => Some(err),
+            Self::Protocol(err) => Some(err),
+            Self::Evm(err) => Some(err),
+            Self::Report(err) => Some(err),
+            #[cfg(feature = "simulation")]
+            Self::Simulation(err) => Some(err),
+            #[cfg(feature = "test-utils")]
+            Self::Occupancy(err) => Some(err),
+        }
+    }
+}
+
+impl From<LayoutError> for CryptmallocError {
+    fn from(err: LayoutError) -> Self {
+        Self::Layout(err)
+    }
+}
+
+impl From<EnvelopeError> for CryptmallocError {
+    fn from(err: EnvelopeError) -> Self {
+        Self::Envelope(err)
+    }
+}
+
+impl From<ProtocolError> for CryptmallocError {
+    fn from(err: ProtocolError) -> Self {
+        Self::Protocol(err)
+    }
+}
+
+impl From<EvmError> for CryptmallocError {
+    fn from(err: EvmError) -> Self {
+        Self::Evm(err)
+    }
+}
+
+impl From<ReportError> for CryptmallocError {
+    fn from(err: ReportError) -> Self {
+        Self::Report(err)
+    }
+}
+
+#[cfg(feature = "simulation")]
+impl From<SimulationError> for CryptmallocError {
+    fn from(err: SimulationError) -> Self {
+        Self::Simulation(err)
+    }
+}
+
+#[cfg(feature = "test-utils")]
+impl From<OccupancyParseError> for CryptmallocError {
+    fn from(err: OccupancyParseError) -> Self {
+        Self::Occupancy(err)
+    }
+}
@@ -1,7 +1,76 @@
 // evm maintains encrypted pc/halt plus fully encrypted stack and memory, runs plaintext opcodes, and never owns a client key; pre-encrypted pc values are injected so execution avoids runtime encryption.
+// plaintext bounds (stack capacity, slot indices) are compared with tfhe's scalar ops, so no constant is ever encrypted and a missing key cannot silently turn one into zero.
+use crate::{evm_program::EvmProgram, keys::Keys};
 use core::fmt;
 use tfhe::{prelude::*, set_server_key, FheBool, FheUint32, FheUint64, ServerKey};
 
+/// logical stack depth; pushes beyond it are masked off, and the physical stack never grows past it.
+pub const STACK_CAPACITY: usize = 1024;
+
+/// largest memory_size accepted by EVM::try_new; every slot is a resident FheUint64.
+pub const MAX_MEMORY_SLOTS: usize = 4096;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EvmError {
+    PcTableTooShort {
+        program_len: usize,
+        pc_values: usize,
+    },
+    MemoryTooLarge {
+        requested: usize,
+        max: usize,
+    },
+    DanglingLabel {
+        label: usize,
+    },
+    LabelRebound {
+        label: usize,
+    },
+    ImmediateMissing {
+        pc: usize,
+    },
+    BadJumpTarget {
+        pc: usize,
+        target: usize,
+    },
+    ProgramTooLong {
+        len: usize,
+    },
+}
+
+impl fmt::Display for EvmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::PcTableTooShort {
+                program_len,
+                pc_values,
+            } => write!(
+                f,
+                "program has {program_len} bytes but only {pc_values} encrypted pc values"
+            ),
+            Self::MemoryTooLarge { requested, max } => {
+                write!(f, "memory of {requested} slots exceeds the limit of {max}")
+            }
+            Self::DanglingLabel { label } => {
+                write!(f, "label {label} is never bound by this builder")
+            }
+            Self::LabelRebound { label } => write!(f, "label {label} is bound more than once"),
+            Self::ImmediateMissing { pc } => write!(f, "PUSH1 at pc {pc} has no immediate byte"),
+            Self::BadJumpTarget { pc, target } => {
+                write!(
+                    f,
+                    "jump at pc {pc} targets {target}, which is not a PUSH1-reachable JUMPDEST"
+                )
+            }
+            Self::ProgramTooLong { len } => {
+                write!(f, "program of {len} bytes does not fit a u32 pc")
+            }
+        }
+    }
+}
+
+impl std::error::Error for EvmError {}
+
 #[allow(dead_code)]
 pub struct EVM {
     pc: FheUint32,
@@ -24,13 +93,14 @@ impl fmt::Debug for EVM {
         f.debug_struct("EVM")
             .field("program_len", &self.program.len())
             .field("memory_len", &self.memory.len())
-            .field("stack_cap", &1024)
+            .field("stack_cap", &STACK_CAPACITY)
             .finish()
     }
 }
 
 #[allow(dead_code)]
 impl EVM {
+    /// panicking wrapper around try_new.
     #[allow(clippy::too_many_arguments)]
     pub fn new(
         program: Vec<u8>,
@@ -43,15 +113,54 @@ impl EVM {
         enc_one_u32: FheUint32,
         enc_pc_values: Vec<FheUint32>,
     ) -> Self {
+        Self::try_new(
+            program,
+            memory_size,
+            server_key,
+            enc_false,
+            enc_true,
+            enc_zero_u32,
+            enc_zero_u64,
+            enc_one_u32,
+            enc_pc_values,
+        )
+        .unwrap_or_else(|err| panic!("invalid EVM configuration: {err}"))
+    }
+
+    /// validates the configuration before installing `server_key`: `enc_pc_values` needs an entry for every program byte and `memory_size` may not exceed MAX_MEMORY_SLOTS.
+    #[allow(clippy::too_many_arguments)]
+    pub fn try_new(
+        program: Vec<u8>,
+        memory_size: usize,
+        server_key: ServerKey,
+        enc_false: FheBool,
+        enc_true: FheBool,
+        enc_zero_u32: FheUint32,
+        enc_zero_u64: FheUint64,
+        enc_one_u32: FheUint32,
+        enc_pc_values: Vec<FheUint32>,
+    ) -> Result<Self, EvmError> {
+        if enc_pc_values.len() < program.len() {
+            return Err(EvmError::PcTableTooShort {
+                program_len: program.len(),
+                pc_values: enc_pc_values.len(),
+            });
+        }
+        if memory_size > MAX_MEMORY_SLOTS {
+            return Err(EvmError::MemoryTooLarge {
+                requested: memory_size,
+                max: MAX_MEMORY_SLOTS,
+            });
+        }
         set_server_key(server_key.clone());
 
         let pc = enc_zero_u32.clone();
         let halt = enc_false.clone();
         let stack_len = enc_zero_u32.clone();
-        let stack = Vec::with_capacity(1024);
+        let stack = Vec::with_capacity(STACK_CAPACITY);
         let memory = vec![enc_zero_u64.clone(); memory_size];
 
-        Self {
+        Ok(Self {
             pc,
             stack,
             stack_len,
@@ -65,27 +174,101 @@ impl EVM {
             enc_zero_u64,
             enc_one_u32,
             enc_pc_values,
+        })
+    }
+
+    /// builds an EVM sized for `program`: one encrypted pc value per byte and the program's memory slots. keys are only used to encrypt the constants and pc table; the EVM keeps just the server key.
+    pub fn from_program(program: EvmProgram, keys: &Keys) -> Result<Self, EvmError> {
+        let enc_pc_values = keys.build_enc_indices_u32(program.pc_table_len());
+        let memory_size = program.memory_slots();
+        Self::try_new(
+            program.bytecode().to_vec(),
+            memory_size,
+            keys.server_key(),
+            keys.enc_false_cached(),
+            keys.enc_true_cached(),
+            keys.enc_u32_cached(0),
+            keys.enc_u64_cached(0),
+            keys.enc_u32_cached(1),
+            enc_pc_values,
+        )
+    }
+
+    // host-facing seeding: unconditional push of a caller-provided argument, still bounded by the encrypted capacity guard.
+    pub fn push_input(&mut self, value: FheUint64) {
+        set_server_key(self.server_key.clone());
+        let always = self.enc_true.clone();
+        self.stack_push(value, always);
+    }
+
+    pub fn stack_len(&self) -> &FheUint32 {
+        set_server_key(self.server_key.clone());
+        &self.stack_len
+    }
+
+    // oblivious peek: scans every physical slot and selects the one at stack_len-1-depth; a depth past the logical bottom wraps and matches nothing, yielding zero, as does a depth too large for a u32.
+    pub fn stack_get(&self, depth_from_top: usize) -> FheUint64 {
+        set_server_key(self.server_key.clone());
+
+        let Some(offset) = u32::try_from(depth_from_top)
+            .ok()
+            .and_then(|depth| depth.checked_add(1))
+        else {
+            return self.enc_zero_u64.clone();
+        };
+        let target_index = &self.stack_len - offset;
+
+        let mut value = self.enc_zero_u64.clone();
+        for (idx, slot) in self.stack.iter().enumerate() {
+            let is_target = target_index.eq(idx as u32);
+            value = is_target.if_then_else(slot, &value);
+        }
+        value
+    }
+
+    // pops n results for the host; each pop is underflow-masked, so draining more than the logical depth returns zeros for the missing entries.
+    pub fn drain_outputs(&mut self, n: usize) -> Vec<FheUint64> {
+        set_server_key(self.server_key.clone());
+
+        let mut outputs = Vec::with_capacity(n);
+        for _ in 0..n {
+            let always = self.enc_true.clone();
+            outputs.push(self.stack_pop(always));
         }
+        outputs
+    }
+
+    // restores pc/halt/stack_len to their initial encrypted constants and drops the physical stack; memory and the pc lookup table are kept so a program can be re-run with fresh inputs.
+    pub fn reset(&mut self) {
+        set_server_key(self.server_key.clone());
+        self.pc = self.enc_zero_u32.clone();
+        self.halt = self.enc_false.clone();
+        self.stack_len = self.enc_zero_u32.clone();
+        self.stack.clear();
     }
 
     // stack helpers use encrypted guards for overflow/underflow, run fixed-length scans, and never branch on ciphertexts.
-    // encrypted push: always appends a ciphertext payload, guards logical growth with can_push, and bumps stack_len conditionally; physical growth is ignored by consumers beyond stack_len.
+    // encrypted push: grows the physical stack by one zero slot per call up to STACK_CAPACITY (the call count is public anyway), then overwrites the slot at stack_len under can_push and bumps stack_len conditionally. stack_len never exceeds the number of pushes, so its slot always exists, and pops never shrink the stack, so a slot freed by a pop is reused by the next push.
     fn stack_push(&mut self, value: FheUint64, condition: FheBool) {
         set_server_key(self.server_key.clone());
 
-        let enc_cap =
-            FheUint32::try_encrypt_trivial(1024u32).unwrap_or_else(|_| self.enc_zero_u32.clone());
-        let has_space = self.stack_len.lt(&enc_cap);
+        let has_space = self.stack_len.lt(STACK_CAPACITY as u32);
         let can_push = has_space & condition;
 
-        let stored = can_push.if_then_else(&value, &self.enc_zero_u64);
-        self.stack.push(stored);
+        if self.stack.len() < STACK_CAPACITY {
+            self.stack.push(self.enc_zero_u64.clone());
+        }
+
+        for (idx, slot) in self.stack.iter_mut().enumerate() {
+            let is_target = can_push.clone() & self.stack_len.eq(idx as u32);
+            *slot = is_target.if_then_else(&value, slot);
+        }
 
         let bumped = &self.stack_len + &self.enc_one_u32;
         self.stack_len = can_push.if_then_else(&bumped, &self.stack_len);
     }
 
-    // encrypted pop: scans the fixed logical depth, selects the top element under can_pop, and conditionally decrements stack_len.
+    // encrypted pop: scans every physical slot, selects the top element under can_pop, and conditionally decrements stack_len.
     fn stack_pop(&mut self, condition: FheBool) -> FheUint64 {
         set_server_key(self.server_key.clone());
 
@@ -94,16 +277,9 @@ impl EVM {
         let target_index = &self.stack_len - &self.enc_one_u32;
 
         let mut value = self.enc_zero_u64.clone();
-        for idx in 0..1024 {
-            let enc_idx =
-                FheUint32::try_encrypt_trivial(idx as u32).unwrap_or_else(|_| self.enc_zero_u32.clone());
-            let slot = self
-                .stack
-                .get(idx)
-                .cloned()
-                .unwrap_or_else(|| self.enc_zero_u64.clone());
-            let is_target = can_pop.clone() & enc_idx.eq(&target_index);
-            value = is_target.if_then_else(&slot, &value);
+        for (idx, slot) in self.stack.iter().enumerate() {
+            let is_target = can_pop.clone() & target_index.eq(idx as u32);
+            value = is_target.if_then_else(slot, &value);
         }
 
         let decremented = &self.stack_len - &self.enc_one_u32;
@@ -115,9 +291,7 @@ impl EVM {
     fn stack_pop2(&mut self, condition: FheBool) -> (FheUint64, FheUint64) {
         set_server_key(self.server_key.clone());
 
-        let enc_two =
-            FheUint32::try_encrypt_trivial(2u32).unwrap_or_else(|_| self.enc_zero_u32.clone());
-        let has_two = self.stack_len.ge(&enc_two);
+        let has_two = self.stack_len.ge(2u32);
         let can_pop = has_two & condition;
 
         let first = self.stack_pop(can_pop.clone());
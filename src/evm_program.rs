@@ -0,0 +1,254 @@
+//! EvmProgramBuilder assembles EVM bytecode from typed instructions, resolving jump labels and checking the result before any ciphertext exists; EvmProgram is the validated output that EVM::from_program sizes the pc table and memory from.
+//! Jumps assemble to `PUSH1 target; JUMP`, so every jump target must sit within the first 256 bytes. Immediates are plaintext bytes; the EVM has no instruction loop yet that could consume encrypted ones.
+
+use crate::evm::EvmError;
+use core::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+pub const STOP: u8 = 0x00;
+pub const ADD: u8 = 0x01;
+pub const SUB: u8 = 0x03;
+pub const MLOAD: u8 = 0x51;
+pub const MSTORE: u8 = 0x52;
+pub const JUMP: u8 = 0x56;
+pub const JUMPDEST: u8 = 0x5b;
+pub const PUSH1: u8 = 0x60;
+
+// hands every EvmProgramBuilder its own id, so labels from another builder are told apart even when their indices coincide.
+static NEXT_BUILDER_ID: AtomicU64 = AtomicU64::new(0);
+
+/// a jump target handed out by EvmProgramBuilder::label and bound by jumpdest; it only resolves in the builder that issued it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Label {
+    builder: u64,
+    index: usize,
+}
+
+pub struct EvmProgramBuilder {
+    id: u64,
+    code: Vec<u8>,
+    // bound pc per label, in allocation order.
+    labels: Vec<Option<usize>>,
+    // (pc of the PUSH1 immediate, label it must hold).
+    fixups: Vec<(usize, Label)>,
+    // first label bound twice, reported by build.
+    rebound: Option<Label>,
+    // first label from another builder passed to jumpdest, reported by build.
+    foreign: Option<Label>,
+    memory_slots: usize,
+    // the immediate of the instruction just emitted when it was a plaintext PUSH1, i.e. a statically known memory slot.
+    pushed: Option<u8>,
+}
+
+impl fmt::Debug for EvmProgramBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EvmProgramBuilder")
+            .field("code", &self.code)
+            .field("labels", &self.labels)
+            .field("memory_slots", &self.memory_slots)
+            .finish()
+    }
+}
+
+impl Default for EvmProgramBuilder {
+    fn default() -> Self {
+        Self {
+            id: NEXT_BUILDER_ID.fetch_add(1, Ordering::Relaxed),
+            code: Vec::new(),
+            labels: Vec::new(),
+            fixups: Vec::new(),
+            rebound: None,
+            foreign: None,
+            memory_slots: 0,
+            pushed: None,
+        }
+    }
+}
+
+impl EvmProgramBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// a fresh label for jump and jumpdest.
+    pub fn label(&mut self) -> Label {
+        self.labels.push(None);
+        Label {
+            builder: self.id,
+            index: self.labels.len() - 1,
+        }
+    }
+
+    pub fn push1(&mut self, value: u8) -> &mut Self {
+        self.code.extend([PUSH1, value]);
+        self.pushed = Some(value);
+        self
+    }
+
+    pub fn add(&mut self) -> &mut Self {
+        self.op(ADD)
+    }
+
+    pub fn sub(&mut self) -> &mut Self {
+        self.op(SUB)
+    }
+
+    /// stores the second stack entry at the slot on top; a slot pushed just before counts towards the program's memory size.
+    pub fn mstore(&mut self) -> &mut Self {
+        self.note_slot();
+        self.op(MSTORE)
+    }
+
+    /// loads the slot on top of the stack; a slot pushed just before counts towards the program's memory size.
+    pub fn mload(&mut self) -> &mut Self {
+        self.note_slot();
+        self.op(MLOAD)
+    }
+
+    pub fn stop(&mut self) -> &mut Self {
+        self.op(STOP)
+    }
+
+    /// binds `label` to a JUMPDEST emitted here; a label from another builder is reported by build as dangling.
+    pub fn jumpdest(&mut self, label: Label) -> &mut Self {
+        if label.builder != self.id {
+            self.foreign.get_or_insert(label);
+            return self.op(JUMPDEST);
+        }
+        match self.labels.get_mut(label.index) {
+            Some(Some(_)) => {
+                self.rebound.get_or_insert(label);
+            }
+            Some(bound) => *bound = Some(self.code.len()),
+            None => {
+                self.foreign.get_or_insert(label);
+            }
+        }
+        self.op(JUMPDEST)
+    }
+
+    /// jumps to `label`, which may be bound before or after this point.
+    pub fn jump(&mut self, label: Label) -> &mut Self {
+        self.code.push(PUSH1);
+        self.fixups.push((self.code.len(), label));
+        self.code.extend([0, JUMP]);
+        self.pushed = None;
+        self
+    }
+
+    /// raises the memory size to at least `slots`, for addresses computed at run time that the builder cannot see.
+    pub fn memory_slots(&mut self, slots: usize) -> &mut Self {
+        self.memory_slots = self.memory_slots.max(slots);
+        self
+    }
+
+    /// resolves every label and validates the bytecode.
+    pub fn build(self) -> Result<EvmProgram, EvmError> {
+        if let Some(label) = self.rebound {
+            return Err(EvmError::LabelRebound { label: label.index });
+        }
+        if let Some(label) = self.foreign {
+            return Err(EvmError::DanglingLabel { label: label.index });
+        }
+        let mut code = self.code;
+        for (pc, label) in self.fixups {
+            let target = (label.builder == self.id)
+                .then(|| self.labels.get(label.index).copied().flatten())
+                .flatten()
+                .ok_or(EvmError::DanglingLabel { label: label.index })?;
+            code[pc] =
+                u8::try_from(target).map_err(|_| EvmError::BadJumpTarget { pc: pc + 1, target })?;
+        }
+        let mut program = EvmProgram::from_bytecode(code)?;
+        program.memory_slots = program.memory_slots.max(self.memory_slots);
+        Ok(program)
+    }
+
+    fn op(&mut self, opcode: u8) -> &mut Self {
+        self.code.push(opcode);
+        self.pushed = None;
+        self
+    }
+
+    fn note_slot(&mut self) {
+        if let Some(slot) = self.pushed {
+            self.memory_slots = self.memory_slots.max(slot as usize + 1);
+        }
+    }
+}
+
+/// validated bytecode plus what an EVM needs to run it: one pc-table entry per byte and a memory size.
+#[derive(Clone)]
+pub struct EvmProgram {
+    bytecode: Vec<u8>,
+    memory_slots: usize,
+}
+
+impl fmt::Debug for EvmProgram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EvmProgram")
+            .field("bytecode", &self.bytecode)
+            .field("memory_slots", &self.memory_slots)
+            .finish()
+    }
+}
+
+impl EvmProgram {
+    /// checks hand-assembled bytecode: every PUSH1 has its immediate, every `PUSH1 target; JUMP` lands on a JUMPDEST, and the length fits a u32 pc. the memory size is one past the highest slot a PUSH1 feeds straight into MLOAD or MSTORE.
+    pub fn from_bytecode(bytecode: Vec<u8>) -> Result<Self, EvmError> {
+        if u32::try_from(bytecode.len()).is_err() {
+            return Err(EvmError::ProgramTooLong {
+                len: bytecode.len(),
+            });
+        }
+        let mut jumpdests = vec![false; bytecode.len()];
+        let mut jumps = Vec::new();
+        let mut memory_slots = 0;
+        let mut pc = 0;
+        while pc < bytecode.len() {
+            match bytecode[pc] {
+                PUSH1 => {
+                    let immediate = *bytecode
+                        .get(pc + 1)
+                        .ok_or(EvmError::ImmediateMissing { pc })?;
+                    match bytecode.get(pc + 2) {
+                        Some(&JUMP) => jumps.push((pc + 2, immediate as usize)),
+                        Some(&MLOAD | &MSTORE) => {
+                            memory_slots = memory_slots.max(immediate as usize + 1)
+                        }
+                        _ => {}
+                    }
+                    pc += 2;
+                }
+                JUMPDEST => {
+                    jumpdests[pc] = true;
+                    pc += 1;
+                }
+                _ => pc += 1,
+            }
+        }
+        if let Some(&(pc, target)) = jumps
+            .iter()
+            .find(|&&(_, target)| jumpdests.get(target) != Some(&true))
+        {
+            return Err(EvmError::BadJumpTarget { pc, target });
+        }
+        Ok(Self {
+            bytecode,
+            memory_slots,
+        })
+    }
+
+    pub fn bytecode(&self) -> &[u8] {
+        &self.bytecode
+    }
+
+    /// encrypted pc values the EVM needs, one per bytecode byte.
+    pub fn pc_table_len(&self) -> usize {
+        self.bytecode.len()
+    }
+
+    pub fn memory_slots(&self) -> usize {
+        self.memory_slots
+    }
+}
@@ -0,0 +1,94 @@
+//! failpoints lets resilience tests force allocator failures without exhausting a tier; an armed point masks that tier's (or the arena's) encrypted routing flag so the request fails exactly like real exhaustion while the scan work stays identical.
+//! Decisions are plaintext and deterministic (seeded xorshift for probabilistic policies), which is acceptable because the feature only exists in test builds.
+
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FailPoint {
+    SlabAllocate(usize),
+    ArenaAllocate,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FailPolicy {
+    Always,
+    EveryNth(u64),
+    Probability { p: f64, seed: u64 },
+}
+
+#[derive(Clone, Debug)]
+struct ArmedPolicy {
+    policy: FailPolicy,
+    evaluations: u64,
+    triggered: u64,
+    rng_state: u64,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Failpoints {
+    armed: HashMap<FailPoint, ArmedPolicy>,
+}
+
+impl Failpoints {
+    pub fn inject(&mut self, point: FailPoint, policy: FailPolicy) {
+        let rng_state = match policy {
+            // xorshift must never be seeded with zero or it sticks there.
+            FailPolicy::Probability { seed, .. } => seed.max(1),
+            _ => 1,
+        };
+        self.armed.insert(
+            point,
+            ArmedPolicy {
+                policy,
+                evaluations: 0,
+                triggered: 0,
+                rng_state,
+            },
+        );
+    }
+
+    pub fn clear(&mut self, point: FailPoint) {
+        self.armed.remove(&point);
+    }
+
+    pub fn clear_all(&mut self) {
+        self.armed.clear();
+    }
+
+    pub fn is_armed(&self, point: FailPoint) -> bool {
+        self.armed.contains_key(&point)
+    }
+
+    pub fn policy(&self, point: FailPoint) -> Option<FailPolicy> {
+        self.armed.get(&point).map(|armed| armed.policy)
+    }
+
+    /// number of times `point` actually injected a failure since it was armed.
+    pub fn triggered(&self, point: FailPoint) -> u64 {
+        self.armed.get(&point).map_or(0, |armed| armed.triggered)
+    }
+
+    /// evaluates the policy for one pass through `point`; unarmed points never fail.
+    pub fn should_fail(&mut self, point: FailPoint) -> bool {
+        let Some(armed) = self.armed.get_mut(&point) else {
+            return false;
+        };
+        armed.evaluations += 1;
+        let fail = match armed.policy {
+            FailPolicy::Always => true,
+            FailPolicy::EveryNth(n) => n > 0 && armed.evaluations.is_multiple_of(n),
+            FailPolicy::Probability { p, .. } => {
+                let mut x = armed.rng_state;
+                x ^= x << 13;
+                x ^= x >> 7;
+                x ^= x << 17;
+                armed.rng_state = x;
+                ((x >> 11) as f64 / (1u64 << 53) as f64) < p
+            }
+        };
+        if fail {
+            armed.triggered += 1;
+        }
+        fail
+    }
+}
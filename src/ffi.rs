@@ -0,0 +1,277 @@
+//! ffi is the opt-in (`ffi` feature) C ABI over the client side of the protocol, for callers that cannot link Rust; the cryptmalloc-ffi crate builds it into a cdylib, and include/cryptmalloc.h declares it and documents the ownership rules.
+//! Every function catches panics and reports failure through its return value, never unwinding into C: pointer-returning functions return null and status-returning ones a nonzero CM_* code, and cm_last_error then describes the failure. Byte buffers handed to C are owned by the caller until passed back to cm_bytes_free.
+
+use crate::{
+    envelope::{self, Persistent},
+    keys::Keys,
+    protocol::{ClientEndpoint, ClientReply, ProtocolError},
+};
+use core::{fmt, ptr, slice};
+use serde::{Deserialize, Serialize};
+use std::{
+    cell::RefCell,
+    ffi::{c_char, CString},
+    panic::{catch_unwind, AssertUnwindSafe},
+};
+use tfhe::{ClientKey, ServerKey};
+
+pub const CM_OK: i32 = 0;
+/// a required pointer argument was null.
+pub const CM_NULL_POINTER: i32 = 1;
+/// bytes did not decode as a message, or a message failed to encode.
+pub const CM_PROTOCOL: i32 = 2;
+/// the response decoded but answers something other than an allocation.
+pub const CM_UNEXPECTED_RESPONSE: i32 = 3;
+/// the call panicked; the handles it was given may be left in an unspecified state and should be destroyed.
+pub const CM_PANIC: i32 = 4;
+
+/// opaque handle owning a keypair and the request id counter; created by cm_keys_new or cm_keys_import, destroyed by cm_keys_free.
+pub struct CmKeys {
+    client: ClientEndpoint,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+enum FfiError {
+    Null(&'static str),
+    Protocol(ProtocolError),
+    UnexpectedResponse,
+    Panic,
+}
+
+impl FfiError {
+    fn status(&self) -> i32 {
+        match self {
+            Self::Null(_) => CM_NULL_POINTER,
+            Self::Protocol(_) => CM_PROTOCOL,
+            Self::UnexpectedResponse => CM_UNEXPECTED_RESPONSE,
+            Self::Panic => CM_PANIC,
+        }
+    }
+}
+
+impl fmt::Display for FfiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Null(arg) => write!(f, "`{arg}` is null"),
+            Self::Protocol(err) => write!(f, "{err}"),
+            Self::UnexpectedResponse => write!(f, "response does not answer an allocation"),
+            Self::Panic => write!(f, "cryptmalloc panicked"),
+        }
+    }
+}
+
+impl From<ProtocolError> for FfiError {
+    fn from(err: ProtocolError) -> Self {
+        Self::Protocol(err)
+    }
+}
+
+// decode limit for an exported keypair; default parameters serialize to roughly 120 MiB, nearly all of it server key.
+const MAX_EXPORTED_KEYS_BYTES: u64 = 256 * 1024 * 1024;
+
+// the keypair cm_keys_export writes, sealed in a CMKY envelope.
+#[derive(Serialize, Deserialize)]
+struct ExportedKeys {
+    client_key: ClientKey,
+    server_key: ServerKey,
+}
+
+impl Persistent for ExportedKeys {
+    const MAGIC: [u8; 4] = *b"CMKY";
+    const FORMAT_VERSION: u16 = 1;
+    const MAX_PAYLOAD: u64 = MAX_EXPORTED_KEYS_BYTES;
+}
+
+/// seals both halves of a keypair in the envelope format cm_keys_export writes, so a Rust server can load keys a C client generated, or the reverse.
+pub fn export_keys(keys: &Keys) -> Result<Vec<u8>, ProtocolError> {
+    let client_key = keys
+        .client_key()
+        .ok_or_else(|| ProtocolError::Encode("server-only keys have no client key".to_string()))?;
+    let exported = ExportedKeys {
+        client_key: client_key.clone(),
+        server_key: keys.server_key(),
+    };
+    envelope::seal(&exported).map_err(|err| ProtocolError::Encode(err.to_string()))
+}
+
+/// the inverse of export_keys; rejects blobs with the wrong magic, version or checksum, and payloads past 256 MiB, then installs the server key like Keys::new does.
+pub fn import_keys(bytes: &[u8]) -> Result<Keys, ProtocolError> {
+    let ExportedKeys {
+        client_key,
+        server_key,
+    } = envelope::open(bytes).map_err(|err| ProtocolError::Decode(err.to_string()))?;
+    Ok(Keys::from_parts(client_key, server_key))
+}
+
+/// generates a fresh keypair; null on failure. Key generation takes seconds.
+#[no_mangle]
+pub extern "C" fn cm_keys_new() -> *mut CmKeys {
+    guard_ptr(|| into_handle(Keys::new()))
+}
+
+/// destroys a handle from cm_keys_new or cm_keys_import; null is a no-op.
+///
+/// # Safety
+/// `keys` must be null or a handle this library returned that has not been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn cm_keys_free(keys: *mut CmKeys) {
+    if !keys.is_null() {
+        let _ = catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(keys))));
+    }
+}
+
+/// seals the keypair, client key included, in a checksummed envelope in a new buffer of `*out_len` bytes; null on failure.
+///
+/// # Safety
+/// `keys` must be a live handle and `out_len` valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn cm_keys_export(keys: *const CmKeys, out_len: *mut usize) -> *mut u8 {
+    guard_ptr(|| {
+        let keys = keys.as_ref().ok_or(FfiError::Null("keys"))?;
+        let out_len = out_len.as_mut().ok_or(FfiError::Null("out_len"))?;
+        Ok(into_buffer(export_keys(keys.client.keys())?, out_len))
+    })
+}
+
+/// rebuilds a handle from `len` bytes written by cm_keys_export; its request ids restart at zero. null on failure.
+///
+/// # Safety
+/// `bytes` must be valid for `len` reads.
+#[no_mangle]
+pub unsafe extern "C" fn cm_keys_import(bytes: *const u8, len: usize) -> *mut CmKeys {
+    guard_ptr(|| {
+        let bytes = borrow_bytes(bytes, len, "bytes")?;
+        into_handle(import_keys(bytes)?)
+    })
+}
+
+/// encodes an encrypted request for `size` bytes into a new buffer of `*out_len` bytes; null on failure.
+///
+/// # Safety
+/// `keys` must be a live handle not used concurrently from another thread, and `out_len` valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn cm_request_alloc(
+    keys: *mut CmKeys,
+    size: u64,
+    out_len: *mut usize,
+) -> *mut u8 {
+    guard_ptr(|| {
+        let keys = keys.as_mut().ok_or(FfiError::Null("keys"))?;
+        let out_len = out_len.as_mut().ok_or(FfiError::Null("out_len"))?;
+        Ok(into_buffer(keys.client.alloc_request(size)?, out_len))
+    })
+}
+
+/// encodes an encrypted request to free the block at `offset` into a new buffer of `*out_len` bytes; null on failure.
+///
+/// # Safety
+/// `keys` must be a live handle not used concurrently from another thread, and `out_len` valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn cm_request_free(
+    keys: *mut CmKeys,
+    offset: u64,
+    out_len: *mut usize,
+) -> *mut u8 {
+    guard_ptr(|| {
+        let keys = keys.as_mut().ok_or(FfiError::Null("keys"))?;
+        let out_len = out_len.as_mut().ok_or(FfiError::Null("out_len"))?;
+        Ok(into_buffer(keys.client.free_request(offset)?, out_len))
+    })
+}
+
+/// decrypts an allocation response of `len` bytes; on CM_OK `*out_is_some` says whether the server found room and `*out_offset` holds the offset, zero when it did not.
+///
+/// # Safety
+/// `keys` must be a live handle, `bytes` valid for `len` reads, and both out pointers valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn cm_response_decrypt(
+    keys: *const CmKeys,
+    bytes: *const u8,
+    len: usize,
+    out_offset: *mut u64,
+    out_is_some: *mut bool,
+) -> i32 {
+    guard_status(|| {
+        let keys = keys.as_ref().ok_or(FfiError::Null("keys"))?;
+        let bytes = borrow_bytes(bytes, len, "bytes")?;
+        let out_offset = out_offset.as_mut().ok_or(FfiError::Null("out_offset"))?;
+        let out_is_some = out_is_some.as_mut().ok_or(FfiError::Null("out_is_some"))?;
+        match keys.client.decrypt_response(bytes)? {
+            ClientReply::Allocated { offset, .. } => {
+                *out_offset = offset.unwrap_or(0);
+                *out_is_some = offset.is_some();
+                Ok(())
+            }
+            _ => Err(FfiError::UnexpectedResponse),
+        }
+    })
+}
+
+/// releases a buffer returned by cm_keys_export or a cm_request_* function; `len` must be the length it was returned with. null is a no-op.
+///
+/// # Safety
+/// `bytes` must be null or a buffer this library returned that has not been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn cm_bytes_free(bytes: *mut u8, len: usize) {
+    if !bytes.is_null() {
+        let _ = catch_unwind(AssertUnwindSafe(|| {
+            drop(Box::from_raw(ptr::slice_from_raw_parts_mut(bytes, len)))
+        }));
+    }
+}
+
+/// describes the calling thread's most recent failure, or null if its last call succeeded; the string stays valid until the thread's next cm_* call.
+#[no_mangle]
+pub extern "C" fn cm_last_error() -> *const c_char {
+    LAST_ERROR.with(|slot| {
+        slot.borrow()
+            .as_ref()
+            .map_or(ptr::null(), |message| message.as_ptr())
+    })
+}
+
+fn into_handle(keys: Keys) -> Result<*mut CmKeys, FfiError> {
+    Ok(Box::into_raw(Box::new(CmKeys {
+        client: ClientEndpoint::new(keys)?,
+    })))
+}
+
+fn into_buffer(bytes: Vec<u8>, out_len: &mut usize) -> *mut u8 {
+    let bytes = bytes.into_boxed_slice();
+    *out_len = bytes.len();
+    Box::into_raw(bytes).cast()
+}
+
+// a zero-length buffer may come with any pointer, null included.
+unsafe fn borrow_bytes<'a>(
+    bytes: *const u8,
+    len: usize,
+    arg: &'static str,
+) -> Result<&'a [u8], FfiError> {
+    match (bytes.is_null(), len) {
+        (_, 0) => Ok(&[]),
+        (true, _) => Err(FfiError::Null(arg)),
+        (false, _) => Ok(slice::from_raw_parts(bytes, len)),
+    }
+}
+
+// runs `body`, recording its failure or panic as the thread's last error.
+fn guard<T>(body: impl FnOnce() -> Result<T, FfiError>) -> Result<T, FfiError> {
+    let result = catch_unwind(AssertUnwindSafe(body)).unwrap_or(Err(FfiError::Panic));
+    let message = result.as_ref().err().map(|err| {
+        CString::new(err.to_string()).unwrap_or_else(|_| c"cryptmalloc error".to_owned())
+    });
+    LAST_ERROR.with(|slot| *slot.borrow_mut() = message);
+    result
+}
+
+fn guard_ptr<T>(body: impl FnOnce() -> Result<*mut T, FfiError>) -> *mut T {
+    guard(body).unwrap_or(ptr::null_mut())
+}
+
+fn guard_status(body: impl FnOnce() -> Result<(), FfiError>) -> i32 {
+    guard(body).map_or_else(|err| err.status(), |()| CM_OK)
+}
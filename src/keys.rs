@@ -1,18 +1,89 @@
 //! Keys owns the client key, exposes encrypted constants, and reseats tfhe's global server key before every ciphertext operation so downstream modules never touch plaintext secrets.
+//! A server-only key set (Keys::server_only) has no client key: the cached constants and tables it builds are trivial encryptions, which are fine for the public values allocator construction encrypts, while the enc_* and dec_* helpers panic; trivial_* encrypts other public constants explicitly.
 
 use core::fmt;
 use once_cell::sync::Lazy;
-use std::sync::RwLock;
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::{Arc, Mutex, PoisonError, RwLock},
+};
 use tfhe::{
-    generate_keys, prelude::FheEncrypt, set_server_key, ClientKey, ConfigBuilder, FheBool,
-    FheUint32, FheUint64, ServerKey,
+    generate_keys,
+    prelude::{FheDecrypt, FheEncrypt, FheTrivialEncrypt},
+    set_server_key, ClientKey, ConfigBuilder, FheBool, FheUint16, FheUint32, FheUint64, FheUint8,
+    ServerKey,
 };
 
 static GLOBAL_SERVER_KEY: Lazy<RwLock<Option<ServerKey>>> = Lazy::new(|| RwLock::new(None));
 
+/// table ciphertexts a key set keeps for reuse unless Keys::set_table_cache_limit picks another bound; enough for every index and offset table of the default layout.
+pub const DEFAULT_TABLE_CACHE_LIMIT: usize = 4096;
+
 pub struct Keys {
-    client_key: ClientKey,
+    // None for server-only key sets.
+    client_key: Option<ClientKey>,
     server_key: ServerKey,
+    const_cache: Mutex<ConstCache>,
+    tables: Arc<Mutex<TableCache>>,
+}
+
+// memoized encryptions of small public constants keyed by (type, value); reusing one ciphertext per constant is a deliberate trade-off, so `randomize` re-encrypts every `rerandomize_every`-th hit for callers who want fresher ciphertexts. index/offset tables live in TableCache instead, so it stays a few ciphertexts large.
+#[derive(Default)]
+struct ConstCache {
+    bools: HashMap<bool, FheBool>,
+    u32s: HashMap<u32, FheUint32>,
+    u64s: HashMap<u64, FheUint64>,
+    hits: u64,
+    misses: u64,
+    randomize: bool,
+    rerandomize_every: u64,
+}
+
+impl ConstCache {
+    // counts one lookup and returns the cached ciphertext, or None on a miss or when this hit is due for re-randomization.
+    fn lookup<K, V>(&mut self, map: fn(&mut Self) -> &mut HashMap<K, V>, key: &K) -> Option<V>
+    where
+        K: Eq + Hash,
+        V: Clone,
+    {
+        match map(self).get(key).cloned() {
+            Some(cached) => {
+                self.hits += 1;
+                let every = self.rerandomize_every;
+                let due = self.randomize && every > 0 && self.hits.is_multiple_of(every);
+                (!due).then_some(cached)
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+}
+
+// the u32 index table and the u64 offset tables of slab construction, kept as prefixes so a table of n entries reuses every shorter one built before; tiers share the index prefix, and allocators built from one keygen share all of it. holds at most `limit` ciphertexts in total, and entries past the bound are encrypted fresh on every build.
+struct TableCache {
+    indices_u32: Vec<FheUint32>,
+    // block size -> encryptions of 0, block_size, 2 * block_size, ...
+    offsets_u64: HashMap<usize, Vec<FheUint64>>,
+    limit: usize,
+}
+
+impl Default for TableCache {
+    fn default() -> Self {
+        Self {
+            indices_u32: Vec::new(),
+            offsets_u64: HashMap::new(),
+            limit: DEFAULT_TABLE_CACHE_LIMIT,
+        }
+    }
+}
+
+impl TableCache {
+    fn len(&self) -> usize {
+        self.indices_u32.len() + self.offsets_u64.values().map(Vec::len).sum::<usize>()
+    }
 }
 
 impl fmt::Debug for Keys {
@@ -24,65 +95,330 @@ impl fmt::Debug for Keys {
     }
 }
 
+// clones share the keypair and the table cache but start with an empty constant cache, so a client endpoint and an allocator built from one keygen never contend on the same constant lock, while allocators built from clones reuse each other's tables.
+impl Clone for Keys {
+    fn clone(&self) -> Self {
+        Self {
+            client_key: self.client_key.clone(),
+            server_key: self.server_key.clone(),
+            const_cache: Mutex::new(ConstCache::default()),
+            tables: Arc::clone(&self.tables),
+        }
+    }
+}
+
 impl Keys {
     pub fn new() -> Self {
         let config = ConfigBuilder::default().build();
         let (client_key, server_key) = generate_keys(config);
+        Self::from_parts(client_key, server_key)
+    }
+
+    // installs the server key globally and on this thread; also reassembles keypairs deserialized by ffi::import_keys.
+    pub(crate) fn from_parts(client_key: ClientKey, server_key: ServerKey) -> Self {
+        Self::install(Some(client_key), server_key)
+    }
+
+    /// a key set holding only `server_key`, for a server that must never see the client key; cached constants and tables become trivial encryptions, and every enc_* and dec_* helper panics.
+    pub fn server_only(server_key: ServerKey) -> Self {
+        Self::install(None, server_key)
+    }
+
+    fn install(client_key: Option<ClientKey>, server_key: ServerKey) -> Self {
         set_server_key(server_key.clone());
         install_global_server_key(&server_key);
         Self {
             client_key,
             server_key,
+            const_cache: Mutex::new(ConstCache::default()),
+            tables: Arc::default(),
+        }
+    }
+
+    /// false for server-only key sets.
+    pub fn has_client_key(&self) -> bool {
+        self.client_key.is_some()
+    }
+
+    #[cfg(feature = "ffi")]
+    pub(crate) fn client_key(&self) -> Option<&ClientKey> {
+        self.client_key.as_ref()
+    }
+
+    // encrypts under the client key; callers reseat the server key first.
+    fn encrypt<T, C: FheEncrypt<T, ClientKey>>(&self, val: T) -> C {
+        let client_key = self.client_key.as_ref().expect(
+            "server-only keys cannot encrypt; use the trivial_* helpers for public constants",
+        );
+        C::encrypt(val, client_key)
+    }
+
+    // the cached constants and tables carry public values only, so server-only key sets fall back to trivial encryptions there.
+    fn encrypt_constant<T, C>(&self, val: T) -> C
+    where
+        C: FheEncrypt<T, ClientKey> + FheTrivialEncrypt<T>,
+    {
+        match &self.client_key {
+            Some(client_key) => C::encrypt(val, client_key),
+            None => C::encrypt_trivial(val),
         }
     }
 
+    fn decryption_key(&self) -> &ClientKey {
+        self.client_key
+            .as_ref()
+            .expect("server-only keys cannot decrypt")
+    }
+
+    /// trivial (noiseless, unencrypted) ciphertexts for constants the server may know anyway; they need no client key, and anyone can read them off the wire, so never pass a secret.
+    pub fn trivial_bool(&self, val: bool) -> FheBool {
+        set_server_key(self.server_key.clone());
+        FheBool::encrypt_trivial(val)
+    }
+
+    pub fn trivial_u32(&self, val: u32) -> FheUint32 {
+        set_server_key(self.server_key.clone());
+        FheUint32::encrypt_trivial(val)
+    }
+
+    pub fn trivial_u64(&self, val: u64) -> FheUint64 {
+        set_server_key(self.server_key.clone());
+        FheUint64::encrypt_trivial(val)
+    }
+
+    // a public constant outside the caches, such as a layout offset: encrypted under the client key when there is one, trivial otherwise.
+    pub(crate) fn constant_u64(&self, val: u64) -> FheUint64 {
+        set_server_key(self.server_key.clone());
+        self.encrypt_constant::<_, FheUint64>(val)
+    }
+
     pub fn enc_false(&self) -> FheBool {
         set_server_key(self.server_key.clone());
-        FheBool::encrypt(false, &self.client_key)
+        self.encrypt::<_, FheBool>(false)
     }
 
     pub fn enc_true(&self) -> FheBool {
         set_server_key(self.server_key.clone());
-        FheBool::encrypt(true, &self.client_key)
+        self.encrypt::<_, FheBool>(true)
     }
 
     pub fn enc_u32(&self, val: u32) -> FheUint32 {
         set_server_key(self.server_key.clone());
-        FheUint32::encrypt(val, &self.client_key)
+        self.encrypt::<_, FheUint32>(val)
     }
 
     pub fn enc_u64(&self, val: u64) -> FheUint64 {
         set_server_key(self.server_key.clone());
-        FheUint64::encrypt(val, &self.client_key)
+        self.encrypt::<_, FheUint64>(val)
     }
 
     pub fn enc_zero_u32(&self) -> FheUint32 {
         set_server_key(self.server_key.clone());
-        FheUint32::encrypt(0u32, &self.client_key)
+        self.encrypt::<_, FheUint32>(0u32)
     }
 
     pub fn enc_zero_u64(&self) -> FheUint64 {
         set_server_key(self.server_key.clone());
-        FheUint64::encrypt(0u64, &self.client_key)
+        self.encrypt::<_, FheUint64>(0u64)
     }
 
-    pub fn build_enc_indices_u32(&self, count: usize) -> Vec<FheUint32> {
+    pub fn enc_false_cached(&self) -> FheBool {
+        self.enc_bool_cached(false)
+    }
+
+    pub fn enc_true_cached(&self) -> FheBool {
+        self.enc_bool_cached(true)
+    }
+
+    pub fn enc_u32_cached(&self, val: u32) -> FheUint32 {
+        self.fetch_cached(|cache| &mut cache.u32s, val, || {
+            self.encrypt_constant::<_, FheUint32>(val)
+        })
+    }
+
+    pub fn enc_u64_cached(&self, val: u64) -> FheUint64 {
+        self.fetch_cached(|cache| &mut cache.u64s, val, || {
+            self.encrypt_constant::<_, FheUint64>(val)
+        })
+    }
+
+    fn enc_bool_cached(&self, val: bool) -> FheBool {
+        self.fetch_cached(|cache| &mut cache.bools, val, || {
+            self.encrypt_constant::<_, FheBool>(val)
+        })
+    }
+
+    // the lock only covers the lookup and the insert; encryption runs unlocked, so concurrent constant builds do not queue behind each other. two threads missing on the same value both encrypt and the later insert wins.
+    fn fetch_cached<K, V>(
+        &self,
+        map: fn(&mut ConstCache) -> &mut HashMap<K, V>,
+        key: K,
+        encrypt: impl FnOnce() -> V,
+    ) -> V
+    where
+        K: Eq + Hash,
+        V: Clone,
+    {
         set_server_key(self.server_key.clone());
-        let mut table = Vec::with_capacity(count);
-        for idx in 0..count {
-            table.push(self.enc_u32(idx as u32));
+        let cached = self
+            .const_cache
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .lookup(map, &key);
+        if let Some(cached) = cached {
+            return cached;
         }
-        table
+        let fresh = encrypt();
+        let mut cache = self.const_cache.lock().unwrap_or_else(PoisonError::into_inner);
+        map(&mut cache).insert(key, fresh.clone());
+        fresh
     }
 
+    /// warms the constant cache so later constant lookups only pay clones; warming counts as misses for values not yet cached.
+    pub fn precompute(&self, values_u64: &[u64], values_u32: &[u32]) {
+        set_server_key(self.server_key.clone());
+        for &val in values_u64 {
+            let _ = self.enc_u64_cached(val);
+        }
+        for &val in values_u32 {
+            let _ = self.enc_u32_cached(val);
+        }
+    }
+
+    /// `randomize` swaps the cached ciphertext for a fresh encryption on every `every_nth_hit`-th cache hit; an interval of zero disables re-randomization.
+    pub fn set_const_cache_randomize(&self, randomize: bool, every_nth_hit: u64) {
+        let mut cache = self.const_cache.lock().unwrap_or_else(PoisonError::into_inner);
+        cache.randomize = randomize;
+        cache.rerandomize_every = every_nth_hit;
+    }
+
+    pub fn const_cache_hits(&self) -> u64 {
+        self.const_cache
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .hits
+    }
+
+    pub fn const_cache_misses(&self) -> u64 {
+        self.const_cache
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .misses
+    }
+
+    /// encrypts a batch of values in one call; with the `parallel` feature the encryptions are spread across rayon's pool. element `i` of the result encrypts `values[i]`.
+    pub fn enc_bool_many(&self, values: &[bool]) -> Vec<FheBool> {
+        set_server_key(self.server_key.clone());
+        encrypt_many(values, |&val| self.encrypt::<_, FheBool>(val))
+    }
+
+    pub fn enc_u32_many(&self, values: &[u32]) -> Vec<FheUint32> {
+        set_server_key(self.server_key.clone());
+        encrypt_many(values, |&val| self.encrypt::<_, FheUint32>(val))
+    }
+
+    pub fn enc_u64_many(&self, values: &[u64]) -> Vec<FheUint64> {
+        set_server_key(self.server_key.clone());
+        encrypt_many(values, |&val| self.encrypt::<_, FheUint64>(val))
+    }
+
+    /// the encryptions of `0..count`, served from the table cache as far as it reaches; the rest is encrypted in one bulk batch and cached while the cache has room. tables leave the constant cache counters alone.
+    pub fn build_enc_indices_u32(&self, count: usize) -> Vec<FheUint32> {
+        self.fetch_table(
+            |tables| &mut tables.indices_u32,
+            count,
+            |indices| {
+                let values: Vec<u32> = indices.map(|idx| idx as u32).collect();
+                encrypt_many(&values, |&val| self.encrypt_constant::<_, FheUint32>(val))
+            },
+        )
+    }
+
+    /// narrow index tables for slabs built with `IndexWidth::Narrow`; unlike the u32 table they are not cached.
+    pub fn build_enc_indices_u8(&self, count: usize) -> Vec<FheUint8> {
+        set_server_key(self.server_key.clone());
+        assert!(count <= 1 << 8, "u8 indices cover at most 256 blocks");
+        let values: Vec<u8> = (0..count).map(|idx| idx as u8).collect();
+        encrypt_many(&values, |&val| self.encrypt_constant::<_, FheUint8>(val))
+    }
+
+    pub fn build_enc_indices_u16(&self, count: usize) -> Vec<FheUint16> {
+        set_server_key(self.server_key.clone());
+        assert!(count <= 1 << 16, "u16 indices cover at most 65536 blocks");
+        let values: Vec<u16> = (0..count).map(|idx| idx as u16).collect();
+        encrypt_many(&values, |&val| self.encrypt_constant::<_, FheUint16>(val))
+    }
+
+    /// the encryptions of `idx * block_size` for `idx` in `0..count`, cached per block size like build_enc_indices_u32.
     pub fn build_enc_offsets_u64(&self, count: usize, block_size: usize) -> Vec<FheUint64> {
+        self.fetch_table(
+            |tables| tables.offsets_u64.entry(block_size).or_default(),
+            count,
+            |indices| {
+                let values: Vec<u64> = indices.map(|idx| (idx * block_size) as u64).collect();
+                encrypt_many(&values, |&val| self.encrypt_constant::<_, FheUint64>(val))
+            },
+        )
+    }
+
+    /// bounds the table cache at `ciphertexts` entries across every table; a bound below what it holds drops the cached tables, and zero turns table caching off. the bound is shared by every clone of these keys.
+    pub fn set_table_cache_limit(&self, ciphertexts: usize) {
+        let mut tables = self.tables.lock().unwrap_or_else(PoisonError::into_inner);
+        tables.limit = ciphertexts;
+        if tables.len() > ciphertexts {
+            tables.indices_u32.clear();
+            tables.offsets_u64.clear();
+        }
+    }
+
+    /// table ciphertexts currently cached.
+    pub fn table_cache_len(&self) -> usize {
+        self.tables
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .len()
+    }
+
+    // clones the cached prefix of a table under the lock, encrypts the missing tail unlocked, then caches as much of the tail as the bound allows, unless another thread extended the table meanwhile.
+    fn fetch_table<V: Clone>(
+        &self,
+        table: impl Fn(&mut TableCache) -> &mut Vec<V>,
+        count: usize,
+        encrypt: impl FnOnce(core::ops::Range<usize>) -> Vec<V>,
+    ) -> Vec<V> {
         set_server_key(self.server_key.clone());
-        let mut table = Vec::with_capacity(count);
-        for idx in 0..count {
-            let offset = (idx * block_size) as u64;
-            table.push(self.enc_u64(offset));
+        let mut values: Vec<V> = {
+            let mut tables = self.tables.lock().unwrap_or_else(PoisonError::into_inner);
+            let cached = table(&mut tables);
+            cached[..count.min(cached.len())].to_vec()
+        };
+        let hit = values.len();
+        if hit == count {
+            return values;
         }
-        table
+        let fresh = encrypt(hit..count);
+        let mut tables = self.tables.lock().unwrap_or_else(PoisonError::into_inner);
+        let room = tables.limit.saturating_sub(tables.len());
+        let cached = table(&mut tables);
+        if cached.len() == hit {
+            cached.extend(fresh.iter().take(room).cloned());
+        }
+        drop(tables);
+        values.extend(fresh);
+        values
+    }
+
+    // trusted-side decryption helpers; only the key holder (tests, clients) ever calls these, allocator internals stay purely encrypted.
+    pub fn dec_bool(&self, ct: &FheBool) -> bool {
+        ct.decrypt(self.decryption_key())
+    }
+
+    pub fn dec_u32(&self, ct: &FheUint32) -> u32 {
+        ct.decrypt(self.decryption_key())
+    }
+
+    pub fn dec_u64(&self, ct: &FheUint64) -> u64 {
+        ct.decrypt(self.decryption_key())
     }
 
     pub fn server_key(&self) -> ServerKey {
@@ -97,18 +433,31 @@ impl Default for Keys {
     }
 }
 
+#[cfg(feature = "parallel")]
+fn encrypt_many<T, C>(values: &[T], encrypt: impl Fn(&T) -> C + Send + Sync) -> Vec<C>
+where
+    T: Sync,
+    C: Send,
+{
+    use rayon::prelude::*;
+    values.par_iter().map(encrypt).collect()
+}
+
+#[cfg(not(feature = "parallel"))]
+fn encrypt_many<T, C>(values: &[T], encrypt: impl Fn(&T) -> C) -> Vec<C> {
+    values.iter().map(encrypt).collect()
+}
+
 fn install_global_server_key(server_key: &ServerKey) {
     if let Ok(mut slot) = GLOBAL_SERVER_KEY.write() {
         *slot = Some(server_key.clone());
     }
 }
 
-#[allow(dead_code)]
-pub(crate) fn refresh_global_server_key() {
-    if let Ok(slot) = GLOBAL_SERVER_KEY.read() {
-        if let Some(server_key) = slot.as_ref() {
-            set_server_key(server_key.clone());
-        }
+// installs the last keygen's server key on this thread; for types that hold no server key of their own.
+pub(crate) fn reseat_server_key() {
+    if let Some(server_key) = clone_global_server_key() {
+        set_server_key(server_key);
     }
 }
 
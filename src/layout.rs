@@ -0,0 +1,339 @@
+//! Layout is the plaintext placement plan for the slab tiers and arena; it is fixed at build time, serializable so it can travel with exported keys, and optionally randomized so offsets stop being predictable from the crate's defaults alone.
+//! Randomization shuffles tier placement order and inserts bounded, block-aligned gaps; the routing order of tiers (by block size) never changes.
+
+use crate::envelope::{self, EnvelopeError, Persistent};
+use core::fmt;
+use serde::{Deserialize, Serialize};
+
+/// (block_size, num_blocks) per tier, smallest first; routing assumes exactly this ordering.
+pub const DEFAULT_TIERS: [(usize, usize); 5] =
+    [(16, 1024), (32, 512), (64, 256), (128, 128), (256, 64)];
+
+/// inter-tier gaps are drawn as multiples of this granule so every tier stays aligned to the largest block size.
+pub const LAYOUT_GAP_GRANULE: u64 = 256;
+
+/// upper bound (in granules) on a single randomized gap.
+pub const MAX_LAYOUT_GAP_GRANULES: u64 = 16;
+
+/// arena start alignment guaranteed by Layout::aligned, one page.
+pub const ARENA_ALIGNMENT: u64 = 4096;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LayoutError {
+    TierCount { found: usize, expected: usize },
+    EmptyTier { tier: usize },
+    TierOrder { tier: usize },
+    Overlap { first: usize, second: usize },
+    ArenaOverlap { tier: usize },
+    ArenaBounds { start: u64, end: u64 },
+    PlaintextTier { tier: usize },
+    Reservation { tenant: u32, tier: usize },
+    OffsetOverflow { tier: usize },
+    Rng(String),
+}
+
+impl fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TierCount { found, expected } => {
+                write!(f, "layout has {found} tiers, routing expects {expected}")
+            }
+            Self::EmptyTier { tier } => write!(f, "tier {tier} has a zero block size or count"),
+            Self::TierOrder { tier } => {
+                write!(f, "tier {tier} block size is not larger than the previous tier's")
+            }
+            Self::Overlap { first, second } => write!(f, "tiers {first} and {second} overlap"),
+            Self::ArenaOverlap { tier } => write!(f, "tier {tier} overlaps the arena"),
+            Self::ArenaBounds { start, end } => {
+                write!(f, "arena start {start} is past arena end {end}")
+            }
+            Self::PlaintextTier { tier } => {
+                write!(f, "confidential allocator got plaintext-sized tier {tier}")
+            }
+            Self::Reservation { tenant, tier } => write!(
+                f,
+                "tenant {tenant}'s reservation on tier {tier} is out of range or over-commits the tier"
+            ),
+            Self::OffsetOverflow { tier } => write!(f, "tier {tier} extends past u64::MAX"),
+            Self::Rng(msg) => write!(f, "OS RNG unavailable: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for LayoutError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TierPlacement {
+    pub block_size: usize,
+    pub num_blocks: usize,
+    pub base_offset: u64,
+}
+
+impl TierPlacement {
+    /// saturates at u64::MAX; validated layouts never get that far.
+    pub fn extent(&self) -> u64 {
+        (self.block_size as u64).saturating_mul(self.num_blocks as u64)
+    }
+
+    /// one past the tier's last byte, or None when that does not fit a u64.
+    pub fn end(&self) -> Option<u64> {
+        (self.block_size as u64)
+            .checked_mul(self.num_blocks as u64)
+            .and_then(|extent| self.base_offset.checked_add(extent))
+    }
+
+    /// largest power of two dividing every block offset in the tier; equals the block size's own power-of-two factor once the base offset is a multiple of the block size.
+    pub fn alignment(&self) -> u64 {
+        let block_align = 1u64 << (self.block_size as u64).trailing_zeros().min(63);
+        match self.base_offset {
+            0 => block_align,
+            base => block_align.min(1 << base.trailing_zeros()),
+        }
+    }
+
+    /// whether the base offset is a multiple of the block size, so every block in the tier starts at a multiple of its size.
+    pub fn is_self_aligned(&self) -> bool {
+        self.block_size != 0 && self.base_offset.is_multiple_of(self.block_size as u64)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Layout {
+    /// tiers in routing order (ascending block size); base offsets reflect placement order.
+    pub tiers: Vec<TierPlacement>,
+    pub arena_start: u64,
+    pub arena_end: u64,
+    /// seed the layout was drawn from, `None` for the deterministic contiguous layout.
+    pub seed: Option<u64>,
+}
+
+impl Layout {
+    /// tiers packed back to back from offset 0 in routing order, arena immediately after; this is the historical CryptMalloc layout.
+    /// panics if the tiers and arena do not fit below u64::MAX; see try_contiguous.
+    pub fn contiguous(tiers: &[(usize, usize)], arena_size: u64) -> Self {
+        Self::try_contiguous(tiers, arena_size).expect("layout runs past u64::MAX")
+    }
+
+    /// contiguous, reporting OffsetOverflow for the first tier that ends past u64::MAX and ArenaBounds for an arena that does.
+    pub fn try_contiguous(tiers: &[(usize, usize)], arena_size: u64) -> Result<Self, LayoutError> {
+        let mut placements = Vec::with_capacity(tiers.len());
+        let mut running_offset = 0u64;
+        for (tier, &(block_size, num_blocks)) in tiers.iter().enumerate() {
+            let placement = TierPlacement {
+                block_size,
+                num_blocks,
+                base_offset: running_offset,
+            };
+            running_offset = placement
+                .end()
+                .ok_or(LayoutError::OffsetOverflow { tier })?;
+            placements.push(placement);
+        }
+        Ok(Self {
+            tiers: placements,
+            arena_start: running_offset,
+            arena_end: arena_end(running_offset, arena_size)?,
+            seed: None,
+        })
+    }
+
+    /// shuffles the placement order of the tiers and inserts a random gap before each tier and before the arena; identical seeds give identical layouts.
+    /// panics if the tiers, gaps and arena do not fit below u64::MAX; see try_randomized.
+    pub fn randomized(tiers: &[(usize, usize)], arena_size: u64, seed: u64) -> Self {
+        Self::try_randomized(tiers, arena_size, seed).expect("layout runs past u64::MAX")
+    }
+
+    /// randomized, with the same errors as try_contiguous; tier indices in OffsetOverflow are routing indices.
+    pub fn try_randomized(
+        tiers: &[(usize, usize)],
+        arena_size: u64,
+        seed: u64,
+    ) -> Result<Self, LayoutError> {
+        let mut rng = SplitMix64(seed);
+
+        let mut order: Vec<usize> = (0..tiers.len()).collect();
+        for i in (1..order.len()).rev() {
+            let j = (rng.next() % (i as u64 + 1)) as usize;
+            order.swap(i, j);
+        }
+
+        let mut placements: Vec<Option<TierPlacement>> = vec![None; tiers.len()];
+        let mut running_offset = 0u64;
+        for &tier in order.iter() {
+            running_offset = running_offset
+                .checked_add(rng.gap())
+                .ok_or(LayoutError::OffsetOverflow { tier })?;
+            let (block_size, num_blocks) = tiers[tier];
+            let placement = TierPlacement {
+                block_size,
+                num_blocks,
+                base_offset: running_offset,
+            };
+            running_offset = placement
+                .end()
+                .ok_or(LayoutError::OffsetOverflow { tier })?;
+            placements[tier] = Some(placement);
+        }
+        let gap = rng.gap();
+        let arena_start = running_offset
+            .checked_add(gap)
+            .ok_or(LayoutError::ArenaBounds {
+                start: running_offset.wrapping_add(gap),
+                end: running_offset,
+            })?;
+
+        Ok(Self {
+            tiers: placements.into_iter().flatten().collect(),
+            arena_start,
+            arena_end: arena_end(arena_start, arena_size)?,
+            seed: Some(seed),
+        })
+    }
+
+    /// draws a seed from the operating system's RNG; used when randomization is requested without an explicit seed.
+    pub fn fresh_seed() -> Result<u64, LayoutError> {
+        let mut seed = [0u8; 8];
+        getrandom::getrandom(&mut seed).map_err(|err| LayoutError::Rng(err.to_string()))?;
+        Ok(u64::from_le_bytes(seed))
+    }
+
+    /// checks the invariants routing relies on: five tiers in ascending block-size order, every tier ending within u64, no tier or arena overlaps.
+    pub fn validate(&self) -> Result<(), LayoutError> {
+        if self.tiers.len() != DEFAULT_TIERS.len() {
+            return Err(LayoutError::TierCount {
+                found: self.tiers.len(),
+                expected: DEFAULT_TIERS.len(),
+            });
+        }
+        if self.arena_start > self.arena_end {
+            return Err(LayoutError::ArenaBounds {
+                start: self.arena_start,
+                end: self.arena_end,
+            });
+        }
+        for (tier, placement) in self.tiers.iter().enumerate() {
+            if placement.block_size == 0 || placement.num_blocks == 0 {
+                return Err(LayoutError::EmptyTier { tier });
+            }
+            if tier > 0 && placement.block_size <= self.tiers[tier - 1].block_size {
+                return Err(LayoutError::TierOrder { tier });
+            }
+            let end = placement
+                .end()
+                .ok_or(LayoutError::OffsetOverflow { tier })?;
+            if placement.base_offset < self.arena_end && self.arena_start < end {
+                return Err(LayoutError::ArenaOverlap { tier });
+            }
+            for (other, earlier) in self.tiers[..tier].iter().enumerate() {
+                // earlier tiers passed this check already.
+                let earlier_end = earlier.end().unwrap_or(u64::MAX);
+                if placement.base_offset < earlier_end && earlier.base_offset < end {
+                    return Err(LayoutError::Overlap {
+                        first: other,
+                        second: tier,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// pads the layout so every tier starts at a multiple of its block size and the arena at a multiple of ARENA_ALIGNMENT. padding goes in front of the misaligned region and shifts everything placed after it, so placement order, extents and the arena size are unchanged and the gaps show up in the base offsets.
+    /// a layout that fails validate, or whose padding would run past u64::MAX, comes back unchanged, so validate still reports what is wrong with it.
+    pub fn aligned(self) -> Self {
+        if self.validate().is_err() {
+            return self;
+        }
+        self.try_align().unwrap_or(self)
+    }
+
+    fn try_align(&self) -> Option<Self> {
+        let mut aligned = self.clone();
+        // regions in placement order; None stands for the arena.
+        let mut order: Vec<Option<usize>> = (0..self.tiers.len()).map(Some).collect();
+        order.push(None);
+        order.sort_by_key(|region| match region {
+            Some(tier) => self.tiers[*tier].base_offset,
+            None => self.arena_start,
+        });
+
+        let mut shift = 0u64;
+        for region in order {
+            let (start, alignment) = match region {
+                Some(tier) => {
+                    let placement = &mut aligned.tiers[tier];
+                    (
+                        &mut placement.base_offset,
+                        placement.block_size.max(1) as u64,
+                    )
+                }
+                None => (&mut aligned.arena_start, ARENA_ALIGNMENT),
+            };
+            let padded = start
+                .checked_add(shift)?
+                .checked_next_multiple_of(alignment)?;
+            shift = padded - *start;
+            *start = padded;
+        }
+        aligned.arena_end = aligned.arena_start.checked_add(self.arena_size())?;
+        aligned.validate().ok()?;
+        Some(aligned)
+    }
+
+    /// tiers whose base offset is not a multiple of their block size, in routing order.
+    pub fn misaligned_tiers(&self) -> Vec<usize> {
+        (0..self.tiers.len())
+            .filter(|&tier| !self.tiers[tier].is_self_aligned())
+            .collect()
+    }
+
+    /// zero for a layout whose arena start is past its end; validate rejects those.
+    pub fn arena_size(&self) -> u64 {
+        self.arena_end.saturating_sub(self.arena_start)
+    }
+
+    /// seals the layout in a versioned envelope for storage next to exported keys.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, EnvelopeError> {
+        envelope::seal(self)
+    }
+
+    /// opens a sealed layout; one that decodes but fails validate is rejected with EnvelopeError::Invalid.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, EnvelopeError> {
+        envelope::open(bytes)
+    }
+}
+
+impl Persistent for Layout {
+    const MAGIC: [u8; 4] = *b"CMLY";
+    const FORMAT_VERSION: u16 = 1;
+
+    fn validate(&self) -> Result<(), EnvelopeError> {
+        Layout::validate(self).map_err(|err| EnvelopeError::Invalid(err.to_string()))
+    }
+}
+
+// an arena that would run past u64::MAX is reported with the end it wraps to, which lands before its start.
+fn arena_end(arena_start: u64, arena_size: u64) -> Result<u64, LayoutError> {
+    arena_start
+        .checked_add(arena_size)
+        .ok_or(LayoutError::ArenaBounds {
+            start: arena_start,
+            end: arena_start.wrapping_add(arena_size),
+        })
+}
+
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn gap(&mut self) -> u64 {
+        (self.next() % (MAX_LAYOUT_GAP_GRANULES + 1)) * LAYOUT_GAP_GRANULE
+    }
+}
@@ -1,18 +1,65 @@
 //! Cryptmalloc is a fully homomorphic allocator; tfhe-rs 1.4 drives every ciphertext operation end-to-end.
 //! Constant-time, oblivious memory management keeps access patterns hidden and never emits plaintext metadata or ciphertext branches.
+//! Every public type is Send + Sync (pinned in tests/send_sync.rs), and allocators or ciphertexts can move between threads freely: tfhe's server key is thread-local, so types that own a server key (CryptMalloc and its slabs, arenas and rounder, EVM, EncryptedBitset, EncryptedHeap) reinstall it before touching ciphertexts.
+//! EncryptedOption, ObliviousResult, EncryptedPtr, EncryptedDelta, EncryptedPrng, SizeBlinder::blind_cipher and TierAudit hold no key and install the one from the most recent Keys::new, Keys::server_only or key import in the process, which the allocator also reaches through them; using several keypairs in one process is therefore unsupported.
 
 pub mod allocator;
 pub mod arena;
+pub mod encrypted_bitset;
+pub mod encrypted_delta;
+pub mod encrypted_heap;
 pub mod encrypted_option;
+pub mod encrypted_prng;
 pub mod encrypted_ptr;
+pub mod envelope;
+pub mod error;
 pub mod evm;
+pub mod evm_program;
+#[cfg(feature = "failpoints")]
+pub mod failpoints;
+#[cfg(feature = "ffi")]
+pub mod ffi;
 pub mod keys;
+pub mod layout;
+pub mod metrics;
+pub mod nonce_window;
+pub mod oblivious_result;
+pub mod protocol;
+#[cfg(feature = "pool-registry")]
+pub mod registry;
+pub mod report;
+pub mod ring_arena;
+#[cfg(feature = "simulation")]
+pub mod simulation;
+pub mod size_blinder;
 pub mod slab;
+pub mod tenancy;
+#[cfg(feature = "test-utils")]
+pub mod test_utils;
+pub mod tier_rounder;
 
-pub use allocator::CryptMalloc;
+pub use allocator::{
+    AllocatorReader, CryptMalloc, CryptMallocBuilder, PreparedAllocator, TierAudit,
+};
 pub use arena::Arena;
+pub use encrypted_bitset::{BitsetOp, EncryptedBitset};
+pub use encrypted_delta::EncryptedDelta;
+pub use encrypted_heap::EncryptedHeap;
 pub use encrypted_option::EncryptedOption;
+pub use encrypted_prng::EncryptedPrng;
 pub use encrypted_ptr::EncryptedPtr;
+pub use error::{Category, CryptmallocError};
 pub use evm::EVM;
+pub use evm_program::{EvmProgram, EvmProgramBuilder};
 pub use keys::Keys;
-pub use slab::SlabClass;
+pub use layout::Layout;
+pub use metrics::MetricsRegistry;
+pub use nonce_window::NonceWindow;
+pub use oblivious_result::ObliviousResult;
+pub use protocol::{ClientEndpoint, ServerEndpoint};
+pub use report::AllocatorReport;
+pub use ring_arena::RingArena;
+pub use size_blinder::SizeBlinder;
+pub use slab::{IndexWidth, SlabClass, SlabOptions, WriteBack};
+pub use tenancy::AllocationTicket;
+pub use tier_rounder::TierRounder;
@@ -0,0 +1,130 @@
+//! metrics renders plaintext-safe allocator counters in the Prometheus text exposition format; only operation counts, wall-clock time and the plaintext layout are reported, never anything derived from ciphertexts.
+//! Collecting a sample reads plain integers off the allocator, so scraping never triggers homomorphic work.
+
+use crate::allocator::CryptMalloc;
+use core::fmt::Write;
+use serde::Serialize;
+use std::time::Duration;
+
+/// label value used when an allocator was built without a name.
+pub const DEFAULT_ALLOCATOR_NAME: &str = "default";
+
+/// plaintext operation counters kept by CryptMalloc.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct AllocatorMetrics {
+    pub allocations: u64,
+    pub oblivious_allocations: u64,
+    pub frees: u64,
+    pub resets: u64,
+    pub allocate_time: Duration,
+    pub free_time: Duration,
+}
+
+// (metric name, help text, sample value) for every per-allocator counter family.
+type CounterFamily = (&'static str, &'static str, fn(&AllocatorMetrics) -> String);
+
+const COUNTERS: [CounterFamily; 6] = [
+    (
+        "cryptmalloc_allocations_total",
+        "Encrypted allocate calls.",
+        |m| m.allocations.to_string(),
+    ),
+    (
+        "cryptmalloc_oblivious_allocations_total",
+        "Encrypted allocate_oblivious calls.",
+        |m| m.oblivious_allocations.to_string(),
+    ),
+    ("cryptmalloc_frees_total", "Encrypted free calls.", |m| {
+        m.frees.to_string()
+    }),
+    ("cryptmalloc_resets_total", "Allocator resets.", |m| {
+        m.resets.to_string()
+    }),
+    (
+        "cryptmalloc_allocate_seconds_total",
+        "Wall-clock time spent in allocation calls.",
+        |m| m.allocate_time.as_secs_f64().to_string(),
+    ),
+    (
+        "cryptmalloc_free_seconds_total",
+        "Wall-clock time spent in free calls.",
+        |m| m.free_time.as_secs_f64().to_string(),
+    ),
+];
+
+/// aggregates several allocators into one exposition; each metric family is emitted once with one sample per allocator, distinguished by the `allocator` label.
+#[derive(Debug, Default)]
+pub struct MetricsRegistry<'a> {
+    allocators: Vec<&'a CryptMalloc>,
+}
+
+impl<'a> MetricsRegistry<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&mut self, allocator: &'a CryptMalloc) -> &mut Self {
+        self.allocators.push(allocator);
+        self
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for (name, help, value) in COUNTERS {
+            header(&mut out, name, help, "counter");
+            for allocator in &self.allocators {
+                let label = escape(allocator.name());
+                let _ = writeln!(
+                    out,
+                    "{name}{{allocator=\"{label}\"}} {}",
+                    value(allocator.metrics())
+                );
+            }
+        }
+
+        header(
+            &mut out,
+            "cryptmalloc_slab_blocks",
+            "Block capacity of each slab tier.",
+            "gauge",
+        );
+        for allocator in &self.allocators {
+            let label = escape(allocator.name());
+            for (tier, placement) in allocator.layout().tiers.iter().enumerate() {
+                let _ = writeln!(
+                    out,
+                    "cryptmalloc_slab_blocks{{allocator=\"{label}\",tier=\"{tier}\",block_size=\"{}\"}} {}",
+                    placement.block_size, placement.num_blocks
+                );
+            }
+        }
+
+        header(
+            &mut out,
+            "cryptmalloc_arena_bytes",
+            "Size of the arena region.",
+            "gauge",
+        );
+        for allocator in &self.allocators {
+            let label = escape(allocator.name());
+            let _ = writeln!(
+                out,
+                "cryptmalloc_arena_bytes{{allocator=\"{label}\"}} {}",
+                allocator.layout().arena_size()
+            );
+        }
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
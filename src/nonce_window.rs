@@ -0,0 +1,153 @@
+//! NonceWindow remembers the results of the most recent nonce-tagged operations, so a request delivered twice gets its first answer back instead of being applied again; CryptMalloc::free_with_nonce keeps one for frees.
+//! The window is bounded: once full, the least recently seen nonce is evicted, and a duplicate arriving after its nonce was evicted is applied like a fresh request. Nonces are plaintext request tags chosen by the client, not secrets.
+
+use crate::envelope::{EnvelopeError, Persistent};
+use core::fmt;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use tfhe::FheBool;
+
+/// nonces CryptMalloc remembers unless the builder picks another size.
+pub const DEFAULT_NONCE_WINDOW: usize = 1024;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct NonceWindow<V> {
+    capacity: usize,
+    // nonce -> (recency stamp, recorded result).
+    entries: HashMap<u128, (u64, V)>,
+    // recency stamp -> nonce; the smallest stamp is the next eviction.
+    order: BTreeMap<u64, u128>,
+    next_stamp: u64,
+}
+
+impl<V> fmt::Debug for NonceWindow<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NonceWindow")
+            .field("capacity", &self.capacity)
+            .field("len", &self.entries.len())
+            .finish()
+    }
+}
+
+impl<V: Clone> NonceWindow<V> {
+    /// a window holding up to `capacity` nonces; zero remembers nothing, so every request is applied.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            next_stamp: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// changes the capacity, evicting the least recently seen nonces until the window fits.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.entries.len() > capacity {
+            let Some((_, oldest)) = self.order.pop_first() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn contains(&self, nonce: u128) -> bool {
+        self.entries.contains_key(&nonce)
+    }
+
+    /// the result recorded for `nonce`, marking it most recently seen; a miss leaves the window untouched.
+    pub fn get(&mut self, nonce: u128) -> Option<V> {
+        if !self.entries.contains_key(&nonce) {
+            return None;
+        }
+        let stamp = self.take_stamp();
+        let (seen, value) = self.entries.get_mut(&nonce)?;
+        self.order.remove(seen);
+        self.order.insert(stamp, nonce);
+        *seen = stamp;
+        Some(value.clone())
+    }
+
+    /// records `value` for `nonce`, evicting the least recently seen nonce when the window is full.
+    pub fn insert(&mut self, nonce: u128, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+        let stamp = self.take_stamp();
+        if let Some((seen, _)) = self.entries.insert(nonce, (stamp, value)) {
+            self.order.remove(&seen);
+        } else if self.entries.len() > self.capacity {
+            if let Some((_, oldest)) = self.order.pop_first() {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.order.insert(stamp, nonce);
+    }
+
+    fn take_stamp(&mut self) -> u64 {
+        if self.next_stamp == u64::MAX {
+            self.renumber();
+        }
+        let stamp = self.next_stamp;
+        self.next_stamp += 1;
+        stamp
+    }
+}
+
+impl<V> NonceWindow<V> {
+    /// every recorded result, in no particular order.
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.entries.values().map(|(_, value)| value)
+    }
+
+    // every remembered nonce has exactly one stamp below next_stamp, and the window is within its capacity.
+    fn is_consistent(&self) -> bool {
+        self.entries.len() == self.order.len()
+            && self.entries.len() <= self.capacity
+            && self.order.iter().all(|(&stamp, nonce)| {
+                stamp < self.next_stamp
+                    && self.entries.get(nonce).map(|(seen, _)| *seen) == Some(stamp)
+            })
+    }
+
+    // compacts the stamps to 0..len, keeping their order, once they run out.
+    fn renumber(&mut self) {
+        let order = core::mem::take(&mut self.order);
+        for (stamp, (_, nonce)) in order.into_iter().enumerate() {
+            let stamp = stamp as u64;
+            if let Some((seen, _)) = self.entries.get_mut(&nonce) {
+                *seen = stamp;
+            }
+            self.order.insert(stamp, nonce);
+        }
+        self.next_stamp = self.order.len() as u64;
+    }
+}
+
+impl Persistent for NonceWindow<FheBool> {
+    const MAGIC: [u8; 4] = *b"CMNW";
+    const FORMAT_VERSION: u16 = 1;
+
+    fn validate(&self) -> Result<(), EnvelopeError> {
+        if self.is_consistent() {
+            Ok(())
+        } else {
+            Err(EnvelopeError::Invalid(
+                "nonce window entries disagree with their recency order or exceed its capacity"
+                    .to_string(),
+            ))
+        }
+    }
+}
@@ -0,0 +1,85 @@
+//! ObliviousResult is the poison-propagating result used by oblivious pipelines; `ok` is an encrypted flag and `value` is always a well-formed ciphertext, so failures clear the flag instead of taking a plaintext `Err` branch.
+//! Combinators keep computing on garbage-but-valid payloads when `ok` is false, which keeps the work identical across success and failure paths.
+
+use crate::{encrypted_option::EncryptedOption, keys::reseat_server_key};
+use core::fmt;
+use tfhe::FheBool;
+
+#[derive(Clone)]
+pub struct ObliviousResult<T: Clone> {
+    pub value: T,
+    pub ok: FheBool,
+}
+
+impl<T: Clone> fmt::Debug for ObliviousResult<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ObliviousResult")
+            .field("value", &"<ciphertext>")
+            .field("ok", &"<ciphertext>")
+            .finish()
+    }
+}
+
+impl<T: Clone> ObliviousResult<T> {
+    pub fn new(value: T, ok: FheBool) -> Self {
+        reseat_server_key();
+        Self { value, ok }
+    }
+
+    /// applies `f` to the payload unconditionally; the ok flag is carried through untouched, so a poisoned input yields a poisoned (but well-formed) output
+    pub fn map<U, F>(self, f: F) -> ObliviousResult<U>
+    where
+        U: Clone,
+        F: FnOnce(T) -> U,
+    {
+        reseat_server_key();
+        ObliviousResult {
+            value: f(self.value),
+            ok: self.ok,
+        }
+    }
+
+    /// chains a fallible oblivious step; the step always runs and the resulting flag is the encrypted AND of both flags
+    pub fn and_then<U, F>(self, f: F) -> ObliviousResult<U>
+    where
+        U: Clone,
+        F: FnOnce(T) -> ObliviousResult<U>,
+    {
+        reseat_server_key();
+        let next = f(self.value);
+        ObliviousResult {
+            value: next.value,
+            ok: self.ok & next.ok,
+        }
+    }
+
+    /// clears the ok flag wherever `failed` is true; used to fold additional failure causes into an in-flight result
+    pub fn poison_if(self, failed: &FheBool) -> Self {
+        reseat_server_key();
+        let ok = &self.ok & &!failed;
+        Self {
+            value: self.value,
+            ok,
+        }
+    }
+
+    pub fn into_option(self) -> EncryptedOption<T> {
+        reseat_server_key();
+        EncryptedOption {
+            value: self.value,
+            is_some: self.ok,
+        }
+    }
+}
+
+impl<T: Clone> From<EncryptedOption<T>> for ObliviousResult<T> {
+    fn from(option: EncryptedOption<T>) -> Self {
+        Self::new(option.value, option.is_some)
+    }
+}
+
+impl<T: Clone> From<ObliviousResult<T>> for EncryptedOption<T> {
+    fn from(result: ObliviousResult<T>) -> Self {
+        result.into_option()
+    }
+}
@@ -0,0 +1,341 @@
+//! protocol splits the allocator into a key-holding client and a homomorphic server; requests and responses travel as versioned byte buffers carrying serialized ciphertexts only.
+//! The server side holds nothing but the server key: it checks every deserialized ciphertext against that key's parameters and drives a CryptMalloc built from a server-only key set, while decryption happens exclusively in ClientEndpoint.
+
+use crate::{
+    allocator::{CryptMalloc, CryptMallocBuilder},
+    encrypted_option::EncryptedOption,
+    encrypted_ptr::EncryptedPtr,
+    envelope::EnvelopeError,
+    keys::Keys,
+    layout::LayoutError,
+    nonce_window::NonceWindow,
+};
+use bincode::Options;
+use core::fmt;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tfhe::{
+    conformance::ParameterSetConformant, FheBool, FheBoolConformanceParams, FheUint64,
+    FheUint64ConformanceParams, ServerKey,
+};
+
+/// bumped whenever the layout of any message changes; it is written as a little-endian u16 ahead of the bincode payload.
+pub const WIRE_VERSION: u16 = 2;
+
+/// upper bound on a decoded message; a handful of ciphertexts fits comfortably, and hostile length prefixes fail instead of allocating.
+pub const MAX_MESSAGE_BYTES: u64 = 16 * 1024 * 1024;
+
+#[derive(Debug)]
+pub enum ProtocolError {
+    Truncated,
+    UnsupportedVersion { found: u16, expected: u16 },
+    Encode(String),
+    Decode(String),
+    Nonconformant,
+    // ClientEndpoint::new was handed a server-only key set.
+    ServerOnlyKeys,
+    Rng(String),
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Truncated => write!(f, "message shorter than the wire version header"),
+            Self::UnsupportedVersion { found, expected } => {
+                write!(f, "unsupported wire version {found}, expected {expected}")
+            }
+            Self::Encode(msg) => write!(f, "failed to encode message: {msg}"),
+            Self::Decode(msg) => write!(f, "failed to decode message: {msg}"),
+            Self::Nonconformant => write!(f, "ciphertext does not match the server key"),
+            Self::ServerOnlyKeys => write!(f, "client endpoints need a client key"),
+            Self::Rng(msg) => write!(f, "OS RNG unavailable: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+#[derive(Serialize, Deserialize)]
+pub struct AllocRequest {
+    pub request_id: u64,
+    pub size: FheUint64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FreeRequest {
+    pub request_id: u64,
+    pub ptr: EncryptedPtr,
+    /// client-chosen tag that makes the free idempotent; see CryptMalloc::free_with_nonce.
+    pub nonce: u128,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ResetRequest {
+    pub request_id: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum Request {
+    Alloc(AllocRequest),
+    Free(FreeRequest),
+    Reset(ResetRequest),
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AllocResponse {
+    pub request_id: u64,
+    pub result: EncryptedOption<EncryptedPtr>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FreeResponse {
+    pub request_id: u64,
+    // set when the free reclaimed an allocated slab cell; repeated frees of one pointer report false.
+    pub reclaimed: FheBool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ResetResponse {
+    pub request_id: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum Response {
+    Alloc(AllocResponse),
+    Free(FreeResponse),
+    Reset(ResetResponse),
+}
+
+impl Response {
+    pub fn request_id(&self) -> u64 {
+        match self {
+            Self::Alloc(resp) => resp.request_id,
+            Self::Free(resp) => resp.request_id,
+            Self::Reset(resp) => resp.request_id,
+        }
+    }
+}
+
+fn wire_options() -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .with_little_endian()
+        .with_limit(MAX_MESSAGE_BYTES)
+}
+
+pub fn encode<T: Serialize>(message: &T) -> Result<Vec<u8>, ProtocolError> {
+    let payload = wire_options()
+        .serialize(message)
+        .map_err(|err| ProtocolError::Encode(err.to_string()))?;
+    let mut bytes = Vec::with_capacity(payload.len() + 2);
+    bytes.extend_from_slice(&WIRE_VERSION.to_le_bytes());
+    bytes.extend_from_slice(&payload);
+    Ok(bytes)
+}
+
+pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, ProtocolError> {
+    if bytes.len() < 2 {
+        return Err(ProtocolError::Truncated);
+    }
+    let found = u16::from_le_bytes([bytes[0], bytes[1]]);
+    if found != WIRE_VERSION {
+        return Err(ProtocolError::UnsupportedVersion {
+            found,
+            expected: WIRE_VERSION,
+        });
+    }
+    wire_options()
+        .deserialize(&bytes[2..])
+        .map_err(|err| ProtocolError::Decode(err.to_string()))
+}
+
+/// ServerEndpoint owns the allocator and answers encoded requests; it is built from a server key alone, so it cannot decrypt and every response is a ciphertext the client alone can open.
+/// every ciphertext in a request is checked against the server key's parameters before the allocator sees it, so a malformed one is answered with ProtocolError::Nonconformant instead of panicking deep inside tfhe.
+/// frees go through CryptMalloc::free_with_nonce, so a redelivered free request gets the first response back; the window size is set with CryptMallocBuilder::nonce_window. a restarted server restores its saved window with restore_nonce_window before handling any request.
+pub struct ServerEndpoint {
+    allocator: CryptMalloc,
+    // requests carry FheUint64s only.
+    uint64_params: FheUint64ConformanceParams,
+}
+
+impl fmt::Debug for ServerEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServerEndpoint")
+            .field("allocator", &self.allocator)
+            .finish()
+    }
+}
+
+impl ServerEndpoint {
+    /// builds the allocator `builder` describes under a server-only key set made from `server_key`; keys set on the builder are replaced, so no client key reaches the server.
+    pub fn new(server_key: ServerKey, builder: CryptMallocBuilder) -> Result<Self, LayoutError> {
+        let uint64_params = FheUint64ConformanceParams::from(&server_key);
+        let allocator = builder.keys(Keys::server_only(server_key)).build()?;
+        Ok(Self {
+            allocator,
+            uint64_params,
+        })
+    }
+
+    pub fn allocator(&self) -> &CryptMalloc {
+        &self.allocator
+    }
+
+    /// restores a nonce window saved from allocator().nonce_window(); see CryptMalloc::restore_nonce_window.
+    pub fn restore_nonce_window(
+        &mut self,
+        window: NonceWindow<FheBool>,
+    ) -> Result<(), EnvelopeError> {
+        self.allocator.restore_nonce_window(window)
+    }
+
+    pub fn handle(&mut self, request: &[u8]) -> Result<Vec<u8>, ProtocolError> {
+        let request = decode::<Request>(request)?;
+        self.check_conformance(&request)?;
+        let response = match request {
+            Request::Alloc(req) => Response::Alloc(AllocResponse {
+                request_id: req.request_id,
+                result: self.allocator.allocate(req.size),
+            }),
+            Request::Free(req) => Response::Free(FreeResponse {
+                request_id: req.request_id,
+                reclaimed: self.allocator.free_with_nonce(&req.ptr, req.nonce),
+            }),
+            Request::Reset(req) => {
+                self.allocator.reset();
+                Response::Reset(ResetResponse {
+                    request_id: req.request_id,
+                })
+            }
+        };
+        encode(&response)
+    }
+
+    fn check_conformance(&self, request: &Request) -> Result<(), ProtocolError> {
+        let conformant = match request {
+            Request::Alloc(req) => req.size.is_conformant(&self.uint64_params),
+            Request::Free(req) => req.ptr.0.is_conformant(&self.uint64_params),
+            Request::Reset(_) => true,
+        };
+        conformant.then_some(()).ok_or(ProtocolError::Nonconformant)
+    }
+}
+
+/// plaintext view of a decrypted response.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClientReply {
+    Allocated { request_id: u64, offset: Option<u64> },
+    Freed { request_id: u64, reclaimed: bool },
+    Reset { request_id: u64 },
+}
+
+/// ClientEndpoint holds the keys, encrypts plaintext requests and decrypts responses; request ids are assigned sequentially.
+/// every ciphertext in a response is checked against the keys' parameters before it is decrypted, so a malformed or hostile response is answered with ProtocolError::Decode instead of a panic inside tfhe.
+pub struct ClientEndpoint {
+    keys: Keys,
+    next_request_id: u64,
+    // random high half of every free nonce; the low half is the request id, so nonces never repeat within an endpoint.
+    nonce_prefix: u64,
+    bool_params: FheBoolConformanceParams,
+    uint64_params: FheUint64ConformanceParams,
+}
+
+impl fmt::Debug for ClientEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClientEndpoint")
+            .field("keys", &self.keys)
+            .field("next_request_id", &self.next_request_id)
+            .field("nonce_prefix", &self.nonce_prefix)
+            .finish()
+    }
+}
+
+impl ClientEndpoint {
+    /// rejects server-only key sets, which would put every request on the wire as a trivial ciphertext; draws the nonce prefix from the operating system's RNG.
+    pub fn new(keys: Keys) -> Result<Self, ProtocolError> {
+        if !keys.has_client_key() {
+            return Err(ProtocolError::ServerOnlyKeys);
+        }
+        let mut prefix = [0u8; 8];
+        getrandom::getrandom(&mut prefix).map_err(|err| ProtocolError::Rng(err.to_string()))?;
+        let server_key = keys.server_key();
+        Ok(Self {
+            bool_params: FheBoolConformanceParams::from(&server_key),
+            uint64_params: FheUint64ConformanceParams::from(&server_key),
+            keys,
+            next_request_id: 0,
+            nonce_prefix: u64::from_le_bytes(prefix),
+        })
+    }
+
+    pub fn keys(&self) -> &Keys {
+        &self.keys
+    }
+
+    fn take_request_id(&mut self) -> u64 {
+        let id = self.next_request_id;
+        self.next_request_id += 1;
+        id
+    }
+
+    pub fn alloc_request(&mut self, size: u64) -> Result<Vec<u8>, ProtocolError> {
+        let request_id = self.take_request_id();
+        encode(&Request::Alloc(AllocRequest {
+            request_id,
+            size: self.keys.enc_u64(size),
+        }))
+    }
+
+    /// every call gets a fresh nonce, so resending the returned bytes frees once while a new free_request for the same offset frees again.
+    pub fn free_request(&mut self, offset: u64) -> Result<Vec<u8>, ProtocolError> {
+        let request_id = self.take_request_id();
+        encode(&Request::Free(FreeRequest {
+            request_id,
+            ptr: EncryptedPtr::new(self.keys.enc_u64(offset)),
+            nonce: (u128::from(self.nonce_prefix) << 64) | u128::from(request_id),
+        }))
+    }
+
+    pub fn reset_request(&mut self) -> Result<Vec<u8>, ProtocolError> {
+        let request_id = self.take_request_id();
+        encode(&Request::Reset(ResetRequest { request_id }))
+    }
+
+    pub fn decrypt_response(&self, bytes: &[u8]) -> Result<ClientReply, ProtocolError> {
+        let response = decode::<Response>(bytes)?;
+        self.check_conformance(&response)?;
+        let reply = match response {
+            Response::Alloc(resp) => {
+                let is_some = self.keys.dec_bool(&resp.result.is_some);
+                let offset = self.keys.dec_u64(&resp.result.value.0);
+                ClientReply::Allocated {
+                    request_id: resp.request_id,
+                    offset: is_some.then_some(offset),
+                }
+            }
+            Response::Free(resp) => ClientReply::Freed {
+                request_id: resp.request_id,
+                reclaimed: self.keys.dec_bool(&resp.reclaimed),
+            },
+            Response::Reset(resp) => ClientReply::Reset {
+                request_id: resp.request_id,
+            },
+        };
+        Ok(reply)
+    }
+
+    fn check_conformance(&self, response: &Response) -> Result<(), ProtocolError> {
+        let conformant = match response {
+            Response::Alloc(resp) => {
+                resp.result.is_some.is_conformant(&self.bool_params)
+                    && resp.result.value.0.is_conformant(&self.uint64_params)
+            }
+            Response::Free(resp) => resp.reclaimed.is_conformant(&self.bool_params),
+            Response::Reset(_) => true,
+        };
+        conformant.then_some(()).ok_or_else(|| {
+            ProtocolError::Decode(
+                "response ciphertext does not match the client's keys".to_string(),
+            )
+        })
+    }
+}
@@ -0,0 +1,106 @@
+//! registry is the opt-in (`pool-registry` feature) process-wide list of live allocators, for finding leaked allocators at shutdown: each registered CryptMalloc contributes its name, creation time, a plaintext configuration summary and its operation counters.
+//! The registry only holds weak references, so it never extends an allocator's lifetime; an entry vanishes from live_pools once the allocator that owns it is dropped, and dead entries are pruned on every registration and listing.
+
+use crate::{layout::Layout, metrics::AllocatorMetrics};
+use core::fmt::Write;
+use once_cell::sync::Lazy;
+use std::{
+    sync::{Arc, Mutex, PoisonError, Weak},
+    time::SystemTime,
+};
+
+static REGISTRY: Lazy<Mutex<Vec<Weak<Entry>>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+struct Entry {
+    name: String,
+    created_at: SystemTime,
+    config: String,
+    metrics: Mutex<AllocatorMetrics>,
+}
+
+/// snapshot of one live registered allocator; every field is plaintext operational metadata.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PoolInfo {
+    pub name: String,
+    pub created_at: SystemTime,
+    /// tier geometry and arena size, e.g. `tiers=16x1024,32x512 arena=4096`; confidential allocators report `confidential`.
+    pub config: String,
+    /// counters as of the allocator's most recent operation.
+    pub metrics: AllocatorMetrics,
+}
+
+// owned by the registered allocator; the registry's Weak dies with it.
+pub(crate) struct Registration(Arc<Entry>);
+
+impl Registration {
+    pub(crate) fn new(name: &str, layout: &Layout, metrics: &AllocatorMetrics) -> Self {
+        let entry = Arc::new(Entry {
+            name: name.to_string(),
+            created_at: SystemTime::now(),
+            config: config_summary(layout),
+            metrics: Mutex::new(metrics.clone()),
+        });
+        let mut registry = REGISTRY.lock().unwrap_or_else(PoisonError::into_inner);
+        registry.retain(|weak| weak.strong_count() > 0);
+        registry.push(Arc::downgrade(&entry));
+        Self(entry)
+    }
+
+    pub(crate) fn publish(&self, metrics: &AllocatorMetrics) {
+        let Self(entry) = self;
+        *entry.metrics.lock().unwrap_or_else(PoisonError::into_inner) = metrics.clone();
+    }
+}
+
+/// every registered allocator still alive, in registration order.
+pub fn live_pools() -> Vec<PoolInfo> {
+    let mut registry = REGISTRY.lock().unwrap_or_else(PoisonError::into_inner);
+    registry.retain(|weak| weak.strong_count() > 0);
+    registry
+        .iter()
+        .filter_map(Weak::upgrade)
+        .map(|entry| PoolInfo {
+            name: entry.name.clone(),
+            created_at: entry.created_at,
+            config: entry.config.clone(),
+            metrics: entry
+                .metrics
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clone(),
+        })
+        .collect()
+}
+
+/// one line per live allocator with its age, configuration and counters; meant for logging at shutdown.
+pub fn report() -> String {
+    let pools = live_pools();
+    let mut out = format!("{} live registered allocator(s)\n", pools.len());
+    for pool in pools {
+        let age = pool.created_at.elapsed().unwrap_or_default().as_secs();
+        let _ = writeln!(
+            out,
+            "  {}: {} age={}s allocations={} oblivious_allocations={} frees={} resets={}",
+            pool.name,
+            pool.config,
+            age,
+            pool.metrics.allocations,
+            pool.metrics.oblivious_allocations,
+            pool.metrics.frees,
+            pool.metrics.resets,
+        );
+    }
+    out
+}
+
+fn config_summary(layout: &Layout) -> String {
+    if layout.tiers.is_empty() {
+        return "confidential".to_string();
+    }
+    let tiers: Vec<String> = layout
+        .tiers
+        .iter()
+        .map(|tier| format!("{}x{}", tier.block_size, tier.num_blocks))
+        .collect();
+    format!("tiers={} arena={}", tiers.join(","), layout.arena_size())
+}
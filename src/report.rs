@@ -0,0 +1,230 @@
+//! report produces the trusted-side view of an allocator for incident response: plaintext layout and counters plus every slab bitmap and arena cursor decrypted with the caller's keys.
+//! Building a report only reads state, and it renders to JSON without extra dependencies; the structures also derive Serialize for any other serde format.
+//! Every decrypted field is an Option: a ciphertext that does not conform to the keys' parameters, which tfhe would panic on, is never decrypted and becomes None, rendered as "error" in JSON, instead of aborting the rest of the report.
+
+use crate::{allocator::CryptMalloc, keys::Keys, layout::Layout, metrics::AllocatorMetrics};
+use core::fmt::{self, Display, Write};
+use serde::Serialize;
+use tfhe::{
+    conformance::ParameterSetConformant, FheBool, FheBoolConformanceParams, FheUint64,
+    FheUint64ConformanceParams,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReportError {
+    /// the keys are server-only, so nothing in the allocator can be decrypted.
+    ServerOnlyKeys,
+}
+
+impl fmt::Display for ReportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ServerOnlyKeys => {
+                write!(f, "reports need a client key to decrypt allocator state")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ReportError {}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct TierReport {
+    pub block_size: Option<usize>,
+    pub num_blocks: usize,
+    pub base_offset: Option<u64>,
+    pub allocated: Vec<Option<bool>>,
+    /// cells known to be allocated; cells that failed to decrypt are not counted.
+    pub used_blocks: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ArenaReport {
+    pub start: Option<u64>,
+    pub end: Option<u64>,
+    pub cursor: Option<u64>,
+    pub used_bytes: Option<u64>,
+    /// Some when the allocator serves large requests from a RingArena, holding the decrypted ring epoch.
+    pub ring_epoch: Option<Option<u64>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct AllocatorReport {
+    pub name: String,
+    pub layout: Layout,
+    pub metrics: AllocatorMetrics,
+    pub tiers: Vec<TierReport>,
+    pub arena: ArenaReport,
+}
+
+impl AllocatorReport {
+    /// decrypts the allocator's state with `keys`, which must be the keypair the allocator was built under; fails only for server-only keys. fields whose ciphertexts do not conform to the keys' parameters are left None.
+    pub fn collect(allocator: &CryptMalloc, keys: &Keys) -> Result<Self, ReportError> {
+        if !keys.has_client_key() {
+            return Err(ReportError::ServerOnlyKeys);
+        }
+        let dec = Decryptor::new(keys);
+
+        // geometry comes from the slabs themselves so confidential tiers, whose layout is empty, are reported too.
+        let tiers = allocator
+            .slabs()
+            .iter()
+            .map(|slab| {
+                let allocated: Vec<Option<bool>> =
+                    slab.bitmap().iter().map(|cell| dec.bool(cell)).collect();
+                let block_size = match slab.enc_block_size() {
+                    Some(enc_block_size) => dec.u64(enc_block_size).map(|size| size as usize),
+                    None => Some(slab.block_size()),
+                };
+                TierReport {
+                    block_size,
+                    num_blocks: slab.num_blocks(),
+                    base_offset: dec.u64(slab.base_offset()),
+                    used_blocks: allocated.iter().filter(|&&used| used == Some(true)).count(),
+                    allocated,
+                }
+            })
+            .collect();
+
+        let (start, end, cursor, ring_epoch) = match allocator.ring_arena() {
+            Some(ring) => (
+                dec.u64(ring.start()),
+                dec.u64(ring.end()),
+                dec.u64(ring.cursor()),
+                Some(dec.u64(ring.epoch())),
+            ),
+            None => {
+                let arena = allocator.arena();
+                (
+                    dec.u64(arena.start()),
+                    dec.u64(arena.end()),
+                    dec.u64(arena.cursor()),
+                    None,
+                )
+            }
+        };
+
+        Ok(Self {
+            name: allocator.name().to_string(),
+            layout: allocator.layout().clone(),
+            metrics: allocator.metrics().clone(),
+            tiers,
+            arena: ArenaReport {
+                start,
+                end,
+                cursor,
+                used_bytes: cursor
+                    .zip(start)
+                    .map(|(cursor, start)| cursor.saturating_sub(start)),
+                ring_epoch,
+            },
+        })
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+        let metrics = &self.metrics;
+        let _ = write!(
+            out,
+            "{{\"name\":{},\"layout\":{{\"arena_start\":{},\"arena_end\":{},\"seed\":{}}}",
+            json_string(&self.name),
+            self.layout.arena_start,
+            self.layout.arena_end,
+            self.layout
+                .seed
+                .map_or_else(|| "null".to_string(), |seed| seed.to_string()),
+        );
+        let _ = write!(
+            out,
+            ",\"metrics\":{{\"allocations\":{},\"oblivious_allocations\":{},\"frees\":{},\"resets\":{},\"allocate_seconds\":{},\"free_seconds\":{}}}",
+            metrics.allocations,
+            metrics.oblivious_allocations,
+            metrics.frees,
+            metrics.resets,
+            metrics.allocate_time.as_secs_f64(),
+            metrics.free_time.as_secs_f64(),
+        );
+        out.push_str(",\"tiers\":[");
+        for (i, tier) in self.tiers.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            let allocated: Vec<String> = tier.allocated.iter().map(json_field).collect();
+            let _ = write!(
+                out,
+                "{{\"block_size\":{},\"num_blocks\":{},\"base_offset\":{},\"used_blocks\":{},\"allocated\":[{}]}}",
+                json_field(&tier.block_size),
+                tier.num_blocks,
+                json_field(&tier.base_offset),
+                tier.used_blocks,
+                allocated.join(","),
+            );
+        }
+        let arena = &self.arena;
+        let _ = write!(
+            out,
+            "],\"arena\":{{\"start\":{},\"end\":{},\"cursor\":{},\"used_bytes\":{},\"ring_epoch\":{}}}}}",
+            json_field(&arena.start),
+            json_field(&arena.end),
+            json_field(&arena.cursor),
+            json_field(&arena.used_bytes),
+            arena
+                .ring_epoch
+                .map_or_else(|| "null".to_string(), |epoch| json_field(&epoch)),
+        );
+        out
+    }
+}
+
+// decrypts only ciphertexts that conform to the keys' parameters, so a field under other parameters is None without tfhe ever panicking on it.
+struct Decryptor<'a> {
+    keys: &'a Keys,
+    bool_params: FheBoolConformanceParams,
+    uint64_params: FheUint64ConformanceParams,
+}
+
+impl<'a> Decryptor<'a> {
+    fn new(keys: &'a Keys) -> Self {
+        let server_key = keys.server_key();
+        Self {
+            keys,
+            bool_params: FheBoolConformanceParams::from(&server_key),
+            uint64_params: FheUint64ConformanceParams::from(&server_key),
+        }
+    }
+
+    fn bool(&self, ct: &FheBool) -> Option<bool> {
+        ct.is_conformant(&self.bool_params)
+            .then(|| self.keys.dec_bool(ct))
+    }
+
+    fn u64(&self, ct: &FheUint64) -> Option<u64> {
+        ct.is_conformant(&self.uint64_params)
+            .then(|| self.keys.dec_u64(ct))
+    }
+}
+
+// a decrypted field, or the "error" marker for one that failed.
+fn json_field<T: Display>(value: &Option<T>) -> String {
+    value
+        .as_ref()
+        .map_or_else(|| json_string("error"), ToString::to_string)
+}
+
+fn json_string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
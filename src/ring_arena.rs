@@ -0,0 +1,127 @@
+//! RingArena is the wrap-around sibling of Arena for streaming workloads: when a request no longer fits before the encrypted `end`, it restarts at `start` instead of failing, and the wrap decision is an encrypted comparison so it never leaks.
+//! Every wrap bumps an encrypted epoch counter; data handed out in an earlier epoch may be overwritten, so callers use the wrap flag or the epoch to invalidate old references.
+
+use crate::{encrypted_option::EncryptedOption, encrypted_ptr::EncryptedPtr};
+use std::ops::Not;
+use tfhe::{prelude::*, set_server_key, FheBool, FheUint64, ServerKey};
+
+/// result of a ring allocation; `wrapped` is true when this request restarted the ring, which also means it succeeded.
+#[derive(Clone)]
+pub struct RingAllocation {
+    pub ptr: EncryptedOption<EncryptedPtr>,
+    pub wrapped: FheBool,
+}
+
+impl core::fmt::Debug for RingAllocation {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("RingAllocation")
+            .field("ptr", &self.ptr)
+            .field("wrapped", &"<ciphertext>")
+            .finish()
+    }
+}
+
+#[derive(Clone)]
+pub struct RingArena {
+    start: FheUint64,
+    end: FheUint64,
+    cursor: FheUint64,
+    epoch: FheUint64,
+    server_key: ServerKey,
+    enc_false: FheBool,
+    enc_zero_u64: FheUint64,
+}
+
+impl core::fmt::Debug for RingArena {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("RingArena")
+            .field("start", &"<ciphertext>")
+            .field("end", &"<ciphertext>")
+            .field("cursor", &"<ciphertext>")
+            .field("epoch", &"<ciphertext>")
+            .finish()
+    }
+}
+
+impl RingArena {
+    pub fn new(
+        start: FheUint64,
+        end: FheUint64,
+        server_key: ServerKey,
+        enc_false: FheBool,
+        enc_zero_u64: FheUint64,
+    ) -> Self {
+        set_server_key(server_key.clone());
+        Self {
+            start: start.clone(),
+            end,
+            cursor: start,
+            epoch: enc_zero_u64.clone(),
+            server_key,
+            enc_false,
+            enc_zero_u64,
+        }
+    }
+
+    /// bump-allocates in place when the request fits before `end`, otherwise restarts at `start`; both candidates are always computed and the choice is an encrypted select. requests larger than the whole ring fail with `is_some = false` and leave the cursor and epoch untouched.
+    pub fn allocate(&mut self, size: FheUint64) -> RingAllocation {
+        set_server_key(self.server_key.clone());
+
+        let in_place_cursor = &self.cursor + &size;
+        let in_place_wrapped = in_place_cursor.lt(&self.cursor);
+        let fits_in_place = &in_place_cursor.le(&self.end) & &in_place_wrapped.not();
+
+        let restart_cursor = &self.start + &size;
+        let restart_wrapped = restart_cursor.lt(&self.start);
+        let ok = &restart_cursor.le(&self.end) & &restart_wrapped.not();
+
+        let wrapped = &fits_in_place.clone().not() & &ok;
+        let base = wrapped.if_then_else(&self.start, &self.cursor);
+        let next_cursor = wrapped.if_then_else(&restart_cursor, &in_place_cursor);
+
+        let ptr_val = ok.if_then_else(&base, &self.enc_zero_u64);
+        self.cursor = ok.if_then_else(&next_cursor, &self.cursor);
+        self.epoch = &self.epoch + &FheUint64::cast_from(wrapped.clone());
+
+        RingAllocation {
+            ptr: EncryptedOption {
+                value: EncryptedPtr::new(ptr_val),
+                is_some: ok,
+            },
+            wrapped,
+        }
+    }
+
+    /// rewinds the cursor to `start` and the epoch to zero.
+    pub fn reset(&mut self) {
+        set_server_key(self.server_key.clone());
+        self.cursor = self.start.clone();
+        self.epoch = self.enc_zero_u64.clone();
+    }
+
+    pub fn start(&self) -> &FheUint64 {
+        set_server_key(self.server_key.clone());
+        &self.start
+    }
+
+    pub fn end(&self) -> &FheUint64 {
+        set_server_key(self.server_key.clone());
+        &self.end
+    }
+
+    pub fn cursor(&self) -> &FheUint64 {
+        set_server_key(self.server_key.clone());
+        &self.cursor
+    }
+
+    /// encrypted number of times the ring has wrapped.
+    pub fn epoch(&self) -> &FheUint64 {
+        set_server_key(self.server_key.clone());
+        &self.epoch
+    }
+
+    pub fn enc_false(&self) -> &FheBool {
+        set_server_key(self.server_key.clone());
+        &self.enc_false
+    }
+}
@@ -0,0 +1,168 @@
+//! SimulatedHeap runs a plaintext heap whose addresses come from CryptMalloc: sizes are encrypted, routed homomorphically, and the returned offset is decrypted (simulation only) to index a host-side backing buffer.
+//! It implements GlobalAlloc so allocation-heavy code can be pointed at it through the trait, but it allocates its own bookkeeping and must not be installed as the process-wide `#[global_allocator]`.
+
+use crate::allocator::CryptMalloc;
+use crate::encrypted_ptr::EncryptedPtr;
+use core::fmt;
+use std::{
+    alloc::{GlobalAlloc, Layout},
+    collections::HashMap,
+    panic::{self, AssertUnwindSafe},
+    ptr,
+    sync::{Mutex, PoisonError},
+};
+
+/// why an allocator cannot back a SimulatedHeap.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SimulationError {
+    /// built on Keys::server_only, so granted offsets cannot be decrypted.
+    ServerOnlyKeys,
+    /// built by CryptMalloc::from_confidential_tiers, whose placeholder layout gives no arena end to size the backing buffer by.
+    ConfidentialLayout,
+}
+
+impl fmt::Display for SimulationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ServerOnlyKeys => {
+                write!(f, "simulated heap needs a client key to decrypt offsets")
+            }
+            Self::ConfidentialLayout => {
+                write!(
+                    f,
+                    "simulated heap needs a plaintext layout to size its backing buffer"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for SimulationError {}
+
+struct HeapState {
+    allocator: CryptMalloc,
+    // leaked once from a Box and only ever touched through raw pointers, so pointers handed out by alloc are never invalidated by a fresh &mut to the buffer.
+    backing: *mut [u8],
+    // aligned pointer address -> raw offset handed out by CryptMalloc, so dealloc frees what allocate returned.
+    adjustments: HashMap<usize, u64>,
+}
+
+// SAFETY: the backing buffer is owned by HeapState and only accessed while the SimulatedHeap mutex is held.
+unsafe impl Send for HeapState {}
+
+impl Drop for HeapState {
+    fn drop(&mut self) {
+        // SAFETY: backing came from Box::into_raw in with_allocator and is released exactly once, here.
+        drop(unsafe { Box::from_raw(self.backing) });
+    }
+}
+
+pub struct SimulatedHeap {
+    state: Mutex<HeapState>,
+}
+
+impl fmt::Debug for SimulatedHeap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        f.debug_struct("SimulatedHeap")
+            .field("backing_len", &state.backing.len())
+            .field("live_allocations", &state.adjustments.len())
+            .finish()
+    }
+}
+
+impl SimulatedHeap {
+    pub fn new(arena_size: u64) -> Self {
+        Self::with_allocator(CryptMalloc::new(arena_size))
+            .expect("CryptMalloc::new has a client key and a plaintext layout")
+    }
+
+    /// the backing buffer spans every offset the allocator can hand out, i.e. up to the end of its arena. the allocator must hold a client key and a plaintext layout.
+    pub fn with_allocator(allocator: CryptMalloc) -> Result<Self, SimulationError> {
+        if !allocator.keys().has_client_key() {
+            return Err(SimulationError::ServerOnlyKeys);
+        }
+        if allocator.layout().tiers.is_empty() {
+            return Err(SimulationError::ConfidentialLayout);
+        }
+        let backing =
+            Box::into_raw(vec![0u8; allocator.layout().arena_end as usize].into_boxed_slice());
+        Ok(Self {
+            state: Mutex::new(HeapState {
+                allocator,
+                backing,
+                adjustments: HashMap::new(),
+            }),
+        })
+    }
+
+    pub fn live_allocations(&self) -> usize {
+        self.state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .adjustments
+            .len()
+    }
+
+    /// runs `f` against the wrapped allocator, e.g. to decrypt slab bitmaps after a workload.
+    pub fn inspect<R>(&self, f: impl FnOnce(&CryptMalloc) -> R) -> R {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        f(&state.allocator)
+    }
+}
+
+impl HeapState {
+    fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        let Some(padded) = layout.size().checked_add(layout.align() - 1) else {
+            return ptr::null_mut();
+        };
+        let padded = padded as u64;
+        let enc_size = self.allocator.keys().enc_u64(padded);
+        let granted = self.allocator.allocate(enc_size);
+        let keys = self.allocator.keys();
+        if !keys.dec_bool(&granted.is_some) {
+            return ptr::null_mut();
+        }
+        let offset = keys.dec_u64(&granted.value.0);
+        if offset
+            .checked_add(padded)
+            .is_none_or(|end| end > self.backing.len() as u64)
+        {
+            return ptr::null_mut();
+        }
+
+        // SAFETY: offset + padded lies within the backing buffer, checked above, and the aligned pointer stays within the padding.
+        let raw = unsafe { self.backing.cast::<u8>().add(offset as usize) };
+        let aligned = unsafe { raw.add(raw.align_offset(layout.align())) };
+        self.adjustments.insert(aligned as usize, offset);
+        aligned
+    }
+
+    fn dealloc(&mut self, ptr: *mut u8) {
+        let Some(offset) = self.adjustments.remove(&(ptr as usize)) else {
+            return;
+        };
+        let enc_offset = EncryptedPtr::new(self.allocator.keys().enc_u64(offset));
+        let _ = self.allocator.free(&enc_offset);
+    }
+}
+
+// unwinding out of a GlobalAlloc method is undefined behaviour, so both run under catch_unwind and a panic anywhere in routing becomes a failed allocation or a leaked block.
+unsafe impl GlobalAlloc for SimulatedHeap {
+    /// over-allocates by `align - 1` so the pointer can be bumped to the requested alignment, returning null if that padding overflows; the adjustment is remembered for dealloc.
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        panic::catch_unwind(AssertUnwindSafe(|| {
+            let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+            state.alloc(layout)
+        }))
+        .unwrap_or(ptr::null_mut())
+    }
+
+    /// re-encrypts the original offset and frees it; arena-backed chunks stay consumed, matching CryptMalloc::free.
+    unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| {
+            let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+            state.dealloc(ptr);
+        }));
+    }
+}
@@ -0,0 +1,80 @@
+//! SizeBlinder pads request sizes up to one of a few public buckets so the allocator only ever sees bucket-sized requests, hiding the application's real size distribution behind a handful of values.
+//! `blind` rounds on the client before encryption; `blind_cipher` rounds an already-encrypted size with an oblivious ge/select chain over every bucket, so all inputs cost the same.
+
+use crate::keys::{reseat_server_key, Keys};
+use core::fmt;
+use once_cell::sync::OnceCell;
+use tfhe::{prelude::*, FheUint64};
+
+#[derive(Clone)]
+pub struct SizeBlinder {
+    buckets: Vec<u64>,
+    // trivially encrypted buckets, built on first blind_cipher once a server key is installed; bucket values are public, so nothing is lost by not encrypting them under the client key.
+    enc_buckets: OnceCell<Vec<FheUint64>>,
+}
+
+impl fmt::Debug for SizeBlinder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SizeBlinder")
+            .field("buckets", &self.buckets)
+            .finish()
+    }
+}
+
+impl SizeBlinder {
+    /// buckets may be given in any order; duplicates are dropped.
+    pub fn new(buckets: &[u64]) -> Self {
+        let mut buckets = buckets.to_vec();
+        buckets.sort_unstable();
+        buckets.dedup();
+        Self {
+            buckets,
+            enc_buckets: OnceCell::new(),
+        }
+    }
+
+    /// the buckets in ascending order.
+    pub fn buckets(&self) -> &[u64] {
+        &self.buckets
+    }
+
+    /// smallest bucket >= `actual`; sizes above the largest bucket pass through unchanged.
+    pub fn bucket_for(&self, actual: u64) -> u64 {
+        self.buckets
+            .iter()
+            .copied()
+            .find(|&bucket| bucket >= actual)
+            .unwrap_or(actual)
+    }
+
+    /// encrypts the bucket for `actual`, so the ciphertext never carries the exact size.
+    pub fn blind(&self, keys: &Keys, actual: u64) -> FheUint64 {
+        keys.enc_u64(self.bucket_for(actual))
+    }
+
+    /// oblivious bucket_for: the buckets are visited largest first and each one that still fits replaces the result, so the smallest fitting bucket wins; every bucket costs one comparison and one select whatever the size.
+    pub fn blind_cipher(&self, size: &FheUint64) -> FheUint64 {
+        reseat_server_key();
+        self.blind_installed(size)
+    }
+
+    // blind_cipher under whichever server key the caller installed, for owners such as TierRounder that hold their own.
+    pub(crate) fn blind_installed(&self, size: &FheUint64) -> FheUint64 {
+        let enc_buckets = self.enc_buckets.get_or_init(|| {
+            self.buckets
+                .iter()
+                .map(|&bucket| {
+                    FheUint64::try_encrypt_trivial(bucket)
+                        .expect("a server key is installed whenever a ciphertext exists")
+                })
+                .collect()
+        });
+
+        let mut blinded = size.clone();
+        for (&bucket, enc_bucket) in self.buckets.iter().zip(enc_buckets).rev() {
+            let fits = size.le(bucket);
+            blinded = fits.if_then_else(enc_bucket, &blinded);
+        }
+        blinded
+    }
+}
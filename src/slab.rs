@@ -1,33 +1,90 @@
 //! SlabClass models a fixed block allocator tier; `bitmap[i] = enc_true` marks an allocated block and `enc_false` marks free, so the canonical invariant stays purely encrypted.
 //! Block sizing metadata remains plaintext, but every allocation decision uses the injected server key plus pre-encrypted index/offset tables supplied by the caller.
+//! Confidential slabs (new_confidential) carry their block size only as a ciphertext; num_blocks stays plaintext because it is the bound of every constant-time scan, and the scan length is observable from timing regardless.
+//! SlabOptions trades memory for write-back cost: narrow index tables make each second-pass comparison cheaper, and retained flags skip the second pass altogether.
 
-use crate::{encrypted_option::EncryptedOption, encrypted_ptr::EncryptedPtr};
+use crate::{
+    encrypted_bitset::EncryptedBitset, encrypted_option::EncryptedOption,
+    encrypted_ptr::EncryptedPtr, keys::Keys,
+};
 use core::fmt;
 use std::ops::Not;
-use tfhe::{prelude::*, set_server_key, FheBool, FheUint32, FheUint64, ServerKey};
+use tfhe::{
+    prelude::*, set_server_key, FheBool, FheUint16, FheUint32, FheUint64, FheUint8, ServerKey,
+};
+
+/// ciphertext width of the index table that allocate_masked's write-back compares against.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IndexWidth {
+    /// the shared 32-bit table the bitmap already carries.
+    #[default]
+    U32,
+    /// the narrowest width covering num_blocks: u8 up to 256 blocks, u16 beyond.
+    Narrow,
+}
+
+/// how allocate_masked marks the chosen block once the selection scan is done.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WriteBack {
+    /// track the selected index and run a second full pass comparing it against every block index.
+    #[default]
+    IndexScan,
+    /// keep each block's per-scan select flag and OR it straight into the bitmap; no index comparisons, but num_blocks FheBools are held until write-back.
+    RetainedFlags,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SlabOptions {
+    pub index_width: IndexWidth,
+    pub write_back: WriteBack,
+}
+
+#[derive(Clone)]
+enum NarrowIndices {
+    U8(Vec<FheUint8>),
+    U16(Vec<FheUint16>),
+}
+
+// the write-back witness accumulated during the selection scan.
+enum Selection {
+    U32(FheUint32),
+    U8(FheUint8),
+    U16(FheUint16),
+    Flags(Vec<FheBool>),
+}
 
 #[derive(Clone)]
 pub struct SlabClass {
-    block_size: usize,
+    // None for confidential slabs, which hold enc_block_size instead.
+    block_size: Option<usize>,
+    enc_block_size: Option<FheUint64>,
     num_blocks: usize,
-    bitmap: Vec<FheBool>,
+    bitmap: EncryptedBitset,
     base_offset: FheUint64,
     server_key: ServerKey,
     enc_false: FheBool,
     enc_true: FheBool,
     enc_zero_u32: FheUint32,
     enc_zero_u64: FheUint64,
-    enc_indices_u32: Vec<FheUint32>,
     enc_offsets_u64: Vec<FheUint64>,
+    narrow_indices: Option<NarrowIndices>,
+    write_back: WriteBack,
+    // power of two every block offset is known to be a multiple of; 1 unless the builder vouches for more.
+    alignment: u64,
 }
 
 impl fmt::Debug for SlabClass {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let block_size: &dyn fmt::Debug = match &self.block_size {
+            Some(block_size) => block_size,
+            None => &"<ciphertext>",
+        };
         f.debug_struct("SlabClass")
-            .field("block_size", &self.block_size)
+            .field("block_size", block_size)
             .field("num_blocks", &self.num_blocks)
             .field("bitmap_len", &self.bitmap.len())
             .field("base_offset", &"<ciphertext>")
+            .field("options", &self.options())
             .finish()
     }
 }
@@ -47,13 +104,16 @@ impl SlabClass {
         enc_offsets_u64: Vec<FheUint64>,
     ) -> Self {
         set_server_key(server_key.clone());
-        let mut bitmap = Vec::with_capacity(num_blocks);
-        for _ in 0..num_blocks {
-            bitmap.push(enc_false.clone());
-        }
+        let bitmap = EncryptedBitset::from_parts(
+            server_key.clone(),
+            enc_false.clone(),
+            enc_zero_u32.clone(),
+            enc_indices_u32,
+        );
 
         Self {
-            block_size,
+            block_size: Some(block_size),
+            enc_block_size: None,
             num_blocks,
             bitmap,
             base_offset,
@@ -62,14 +122,104 @@ impl SlabClass {
             enc_true,
             enc_zero_u32,
             enc_zero_u64,
+            enc_offsets_u64,
+            narrow_indices: None,
+            write_back: WriteBack::default(),
+            alignment: 1,
+        }
+    }
+
+    /// confidential tier: the block size is supplied and kept only as a ciphertext, so neither fields nor Debug output reveal it. `enc_offsets_u64` must already hold the encrypted `i * block_size` offsets, built by whoever knows the plaintext size.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_confidential(
+        enc_block_size: FheUint64,
+        num_blocks: usize,
+        base_offset: FheUint64,
+        server_key: ServerKey,
+        enc_false: FheBool,
+        enc_true: FheBool,
+        enc_zero_u32: FheUint32,
+        enc_zero_u64: FheUint64,
+        enc_indices_u32: Vec<FheUint32>,
+        enc_offsets_u64: Vec<FheUint64>,
+    ) -> Self {
+        let mut slab = Self::new(
+            0,
+            num_blocks,
+            base_offset,
+            server_key,
+            enc_false,
+            enc_true,
+            enc_zero_u32,
+            enc_zero_u64,
             enc_indices_u32,
             enc_offsets_u64,
+        );
+        slab.block_size = None;
+        slab.enc_block_size = Some(enc_block_size);
+        slab
+    }
+
+    /// applies construction options; narrow index tables are encrypted here with `keys`, which must be the keypair the slab was built under, and only when the index-scan write-back will use them.
+    pub fn with_options(mut self, keys: &Keys, options: SlabOptions) -> Self {
+        set_server_key(self.server_key.clone());
+        let narrow = options.index_width == IndexWidth::Narrow
+            && options.write_back == WriteBack::IndexScan
+            && self.num_blocks > 0;
+        self.narrow_indices = match narrow {
+            false => None,
+            true if self.num_blocks <= 1 << 8 => Some(NarrowIndices::U8(
+                keys.build_enc_indices_u8(self.num_blocks),
+            )),
+            true => Some(NarrowIndices::U16(
+                keys.build_enc_indices_u16(self.num_blocks),
+            )),
+        };
+        self.write_back = options.write_back;
+        self
+    }
+
+    /// records the alignment every block offset is guaranteed to have, e.g. TierPlacement::alignment of the tier's placement; the base offset is encrypted, so the slab cannot derive it and takes the caller's word.
+    pub fn with_alignment(mut self, alignment: u64) -> Self {
+        assert!(
+            alignment.is_power_of_two(),
+            "alignment {alignment} is not a power of two"
+        );
+        self.alignment = alignment;
+        self
+    }
+
+    /// power of two every pointer from this slab decrypts to a multiple of; 1 when nothing was recorded.
+    pub fn alignment(&self) -> u64 {
+        self.alignment
+    }
+
+    /// the options in effect; a narrow width only sticks when the index-scan write-back uses it.
+    pub fn options(&self) -> SlabOptions {
+        SlabOptions {
+            index_width: match self.narrow_indices {
+                Some(_) => IndexWidth::Narrow,
+                None => IndexWidth::U32,
+            },
+            write_back: self.write_back,
         }
     }
 
+    /// panics for confidential slabs, which have no plaintext block size; see enc_block_size.
     pub fn block_size(&self) -> usize {
         set_server_key(self.server_key.clone());
         self.block_size
+            .expect("confidential slabs have no plaintext block size; use enc_block_size")
+    }
+
+    /// the encrypted block size of a confidential slab, None for plaintext-sized slabs.
+    pub fn enc_block_size(&self) -> Option<&FheUint64> {
+        set_server_key(self.server_key.clone());
+        self.enc_block_size.as_ref()
+    }
+
+    pub fn is_confidential(&self) -> bool {
+        self.block_size.is_none()
     }
 
     pub fn num_blocks(&self) -> usize {
@@ -78,10 +228,60 @@ impl SlabClass {
     }
 
     pub fn bitmap(&self) -> &[FheBool] {
+        set_server_key(self.server_key.clone());
+        self.bitmap.bits()
+    }
+
+    /// the bitmap as a bitset, for oblivious queries such as `test` or `first_clear`.
+    pub fn bitset(&self) -> &EncryptedBitset {
         set_server_key(self.server_key.clone());
         &self.bitmap
     }
 
+    /// encrypted number of allocated blocks.
+    pub fn used_count(&self) -> FheUint32 {
+        set_server_key(self.server_key.clone());
+        self.bitmap.popcount()
+    }
+
+    /// trusted-side: the plaintext block size, decrypted with `keys` for confidential slabs.
+    pub fn trusted_block_size(&self, keys: &Keys) -> usize {
+        match (&self.block_size, &self.enc_block_size) {
+            (Some(block_size), _) => *block_size,
+            (None, Some(enc_block_size)) => keys.dec_u64(enc_block_size) as usize,
+            (None, None) => unreachable!("every slab has a plain or encrypted block size"),
+        }
+    }
+
+    /// encrypted "at least one block is free": an OR over every negated bitmap cell, so a full tier costs the same as an empty one.
+    pub fn has_free(&self) -> FheBool {
+        set_server_key(self.server_key.clone());
+        self.bitmap
+            .bits()
+            .iter()
+            .fold(self.enc_false.clone(), |any, bit| &any | &bit.clone().not())
+    }
+
+    /// trusted-side: decrypts every bitmap cell with the client key in `keys`; test support only.
+    #[cfg(feature = "test-utils")]
+    pub fn decrypt_bitmap(&self, keys: &Keys) -> Vec<bool> {
+        self.bitmap
+            .bits()
+            .iter()
+            .map(|bit| keys.dec_bool(bit))
+            .collect()
+    }
+
+    /// trusted-side: panics listing every index where the decrypted bitmap differs from `expected`.
+    #[cfg(feature = "test-utils")]
+    pub fn assert_bitmap(&self, keys: &Keys, expected: &[bool]) {
+        let actual = self.decrypt_bitmap(keys);
+        let block_size = self.trusted_block_size(keys);
+        if let Some(report) = crate::test_utils::tier_mismatch(block_size, &actual, expected) {
+            panic!("{report}");
+        }
+    }
+
     pub fn base_offset(&self) -> &FheUint64 {
         set_server_key(self.server_key.clone());
         &self.base_offset
@@ -109,7 +309,7 @@ impl SlabClass {
 
     pub fn enc_indices_u32(&self) -> &[FheUint32] {
         set_server_key(self.server_key.clone());
-        &self.enc_indices_u32
+        self.bitmap.enc_indices_u32()
     }
 
     pub fn enc_offsets_u64(&self) -> &[FheUint64] {
@@ -117,16 +317,25 @@ impl SlabClass {
         &self.enc_offsets_u64
     }
 
-    /// Performs the constant-time masked allocation scan described in Spec 3.2; `requested_mask` is a one-hot selector from the routing layer, every block is scanned, and write-back runs a second full pass so no early exits occur.
+    /// Performs the constant-time masked allocation scan described in Spec 3.2; `requested_mask` is a one-hot selector from the routing layer, every block is scanned, and write-back runs a second full pass (or, with retained flags, one unconditional OR per block) so no early exits occur.
     pub fn allocate_masked(&mut self, requested_mask: FheBool) -> EncryptedOption<EncryptedPtr> {
         set_server_key(self.server_key.clone());
 
         let mut selected = self.enc_false.clone();
-        let mut selected_index = self.enc_zero_u32.clone();
+        let mut selection = match (self.write_back, &self.narrow_indices) {
+            (WriteBack::RetainedFlags, _) => Selection::Flags(Vec::with_capacity(self.num_blocks)),
+            (WriteBack::IndexScan, None) => Selection::U32(self.enc_zero_u32.clone()),
+            (WriteBack::IndexScan, Some(NarrowIndices::U8(table))) => {
+                Selection::U8(table[0].clone())
+            }
+            (WriteBack::IndexScan, Some(NarrowIndices::U16(table))) => {
+                Selection::U16(table[0].clone())
+            }
+        };
         let mut selected_ptrval = self.enc_zero_u64.clone();
 
         for i in 0..self.num_blocks {
-            let is_allocated = self.bitmap[i].clone();
+            let is_allocated = self.bitmap.bits()[i].clone();
             let is_free = is_allocated.not();
             let not_selected = selected.clone().not();
             let can_select = (&is_free) & (&not_selected);
@@ -134,18 +343,45 @@ impl SlabClass {
             let candidate = &self.base_offset + &self.enc_offsets_u64[i];
 
             selected_ptrval = should_sel.if_then_else(&candidate, &selected_ptrval);
-            selected_index = should_sel.if_then_else(&self.enc_indices_u32[i], &selected_index);
             selected = (&selected) | (&should_sel);
+            match (&mut selection, &self.narrow_indices) {
+                (Selection::U32(index), _) => {
+                    *index = should_sel.if_then_else(&self.bitmap.enc_indices_u32()[i], index);
+                }
+                (Selection::U8(index), Some(NarrowIndices::U8(table))) => {
+                    *index = should_sel.if_then_else(&table[i], index);
+                }
+                (Selection::U16(index), Some(NarrowIndices::U16(table))) => {
+                    *index = should_sel.if_then_else(&table[i], index);
+                }
+                (Selection::Flags(flags), _) => flags.push(should_sel),
+                _ => unreachable!("selection width follows the slab's index table"),
+            }
         }
 
         let selected_mask = (&selected) & (&requested_mask);
 
-        for j in 0..self.num_blocks {
-            let is_target = self.enc_indices_u32[j].eq(&selected_index);
-            let should_mark = (&is_target) & (&selected_mask);
-            let current = self.bitmap[j].clone();
-            let updated = should_mark.if_then_else(&self.enc_true, &current);
-            self.bitmap[j] = updated;
+        // should_sel already folds in requested_mask and is true for at most one block, so retained flags need no further gating.
+        match (selection, &self.narrow_indices) {
+            (Selection::U32(index), _) => self.bitmap.set_if(&index, &selected_mask),
+            (Selection::U8(index), Some(NarrowIndices::U8(table))) => {
+                for (i, enc_i) in table.iter().enumerate() {
+                    let hit = &enc_i.eq(&index) & &selected_mask;
+                    self.bitmap.set_at_if(i, &hit);
+                }
+            }
+            (Selection::U16(index), Some(NarrowIndices::U16(table))) => {
+                for (i, enc_i) in table.iter().enumerate() {
+                    let hit = &enc_i.eq(&index) & &selected_mask;
+                    self.bitmap.set_at_if(i, &hit);
+                }
+            }
+            (Selection::Flags(flags), _) => {
+                for (i, flag) in flags.iter().enumerate() {
+                    self.bitmap.set_at_if(i, flag);
+                }
+            }
+            _ => unreachable!("selection width follows the slab's index table"),
         }
 
         EncryptedOption {
@@ -154,16 +390,66 @@ impl SlabClass {
         }
     }
 
-    /// frees a pointer by equality only; the entire slab scans once, compares each encrypted offset, and writes `enc_false` into matching bitmap cells with no early exit, so ciphertexts that never belonged to this tier simply leave the bitmap unchanged.
-    pub fn free(&mut self, ptr: &EncryptedPtr) {
+    /// frees a pointer by equality only; the entire slab scans once, compares each encrypted offset, and writes `enc_false` into matching bitmap cells with no early exit, so ciphertexts that never belonged to this tier simply leave the bitmap unchanged. returns the encrypted "reclaimed a cell" flag, which is false for a pointer whose cell was already free.
+    pub fn free(&mut self, ptr: &EncryptedPtr) -> FheBool {
+        set_server_key(self.server_key.clone());
+        let enable = self.enc_true.clone();
+        self.free_masked(ptr, &enable)
+    }
+
+    pub fn reset(&mut self) {
+        set_server_key(self.server_key.clone());
+        self.bitmap.clear_all();
+    }
+
+    /// masked free: every cell is still compared, but bitmap writes only land where `enable` is true; the returned flag is set only when a write landed on a cell that was allocated.
+    pub fn free_masked(&mut self, ptr: &EncryptedPtr, enable: &FheBool) -> FheBool {
+        self.free_cells(ptr, enable).1
+    }
+
+    // (matched, reclaimed): whether any offset equals `ptr`, and whether that cell was set and cleared under `enable`.
+    fn free_cells(&mut self, ptr: &EncryptedPtr, enable: &FheBool) -> (FheBool, FheBool) {
         set_server_key(self.server_key.clone());
 
+        let mut matched = self.enc_false.clone();
+        let mut reclaimed = self.enc_false.clone();
         for i in 0..self.num_blocks {
             let candidate = &self.base_offset + &self.enc_offsets_u64[i];
             let is_match = candidate.eq(&ptr.0);
-            let current = self.bitmap[i].clone();
-            let updated = is_match.if_then_else(&self.enc_false, &current);
-            self.bitmap[i] = updated;
+            let should_clear = &(&is_match & enable) & &self.bitmap.bits()[i];
+            self.bitmap.clear_at_if(i, &should_clear);
+            matched = (&matched) | (&is_match);
+            reclaimed = (&reclaimed) | (&should_clear);
+        }
+        (matched, reclaimed)
+    }
+
+    /// frees across several tiers so that at most one tier is modified: each tier is scanned in full, but its writes are enabled only while no earlier tier has matched, mirroring allocate_masked's not-previously-selected chain. returns (matched, reclaimed): the OR of every tier's offset matches, and the OR of every tier's reclaimed flag.
+    pub fn free_first_match(
+        slabs: &mut [SlabClass],
+        ptr: &EncryptedPtr,
+    ) -> Option<(FheBool, FheBool)> {
+        let enable = slabs.first()?.enc_true.clone();
+        Self::free_first_match_masked(slabs, ptr, &enable)
+    }
+
+    /// free_first_match with an extra encrypted gate: every tier is still scanned, but no bitmap write lands unless `enable` holds.
+    pub fn free_first_match_masked(
+        slabs: &mut [SlabClass],
+        ptr: &EncryptedPtr,
+        enable: &FheBool,
+    ) -> Option<(FheBool, FheBool)> {
+        let first = slabs.first()?;
+        set_server_key(first.server_key.clone());
+
+        let mut any_matched = first.enc_false.clone();
+        let mut any_reclaimed = first.enc_false.clone();
+        for slab in slabs.iter_mut() {
+            let enable = &any_matched.clone().not() & enable;
+            let (matched_here, reclaimed_here) = slab.free_cells(ptr, &enable);
+            any_matched = (&any_matched) | (&matched_here);
+            any_reclaimed = (&any_reclaimed) | (&reclaimed_here);
         }
+        Some((any_matched, any_reclaimed))
     }
 }
@@ -0,0 +1,199 @@
+//! tenancy caps how many blocks of each slab tier a tenant may hold, so one tenant's burst of small allocations cannot take a tier away from everyone else; caps come from CryptMallocBuilder::reserve_tier_fraction and are enforced by CryptMalloc::allocate_for.
+//! Caps are plaintext, but which tier a request lands in is encrypted, so the held counts are FheUint32s bumped by the routing masks; a request whose tier has no room left for its tenant is diverted to the arena inside the same constant-time route.
+//! A tier's blocks not reserved by any tenant form its unreserved share, held jointly by every tenant without a reservation on that tier; a tenant whose reservation is full draws on the same share before falling back to the arena. Plain allocate and free bypass the counts entirely, so reservations only hold while every caller goes through allocate_for.
+
+use crate::{keys::Keys, layout::LayoutError};
+use core::fmt;
+use std::collections::BTreeMap;
+use tfhe::{prelude::*, set_server_key, FheBool, FheUint32, ServerKey};
+
+/// handed out by CryptMalloc::allocate_for and consumed by free_for, which uses it to return the block to the right tenant's count; it holds one encrypted flag per tier, so it reveals nothing about where the request landed. it is not Clone: each ticket refunds at most once.
+pub struct AllocationTicket {
+    tenant: u32,
+    // granted[t] is set when tier t served the request.
+    granted: Vec<FheBool>,
+    // from_reserved[t] is set when that block was charged to the tenant's reservation rather than the unreserved share; None on tiers the tenant has no reservation for.
+    from_reserved: Vec<Option<FheBool>>,
+}
+
+impl fmt::Debug for AllocationTicket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AllocationTicket")
+            .field("tenant", &self.tenant)
+            .field("granted", &"<ciphertext>")
+            .finish()
+    }
+}
+
+impl AllocationTicket {
+    // a ticket for an allocator without reservations, which keeps no counts to refund.
+    pub(crate) fn untracked(tenant: u32, granted: Vec<FheBool>) -> Self {
+        Self {
+            tenant,
+            granted,
+            from_reserved: Vec::new(),
+        }
+    }
+
+    pub fn tenant(&self) -> u32 {
+        self.tenant
+    }
+}
+
+#[derive(Clone)]
+struct Quota {
+    cap: u32,
+    held: FheUint32,
+}
+
+// per-tier quotas: each tenant's reserved tiers, plus the unreserved share every other tenant draws from.
+#[derive(Clone)]
+pub(crate) struct TenantTable {
+    reserved: BTreeMap<u32, Vec<Option<Quota>>>,
+    shared: Vec<Quota>,
+    // the count every quota starts from, kept for reset.
+    zero: FheUint32,
+    server_key: ServerKey,
+}
+
+impl TenantTable {
+    // `caps` is (tenant, tier, blocks) as returned by reservation_caps, so each tier's caps fit in its `num_blocks`; reserved blocks come out of the unreserved share.
+    pub(crate) fn new(keys: &Keys, num_blocks: &[usize], caps: &[(u32, usize, u32)]) -> Self {
+        let server_key = keys.server_key();
+        set_server_key(server_key.clone());
+        let zero = keys.enc_u32_cached(0);
+        let quota = |cap: u32| Quota {
+            cap,
+            held: zero.clone(),
+        };
+        let mut unreserved: Vec<u32> = num_blocks.iter().map(|&blocks| blocks as u32).collect();
+        let mut reserved = BTreeMap::new();
+        for &(tenant, tier, cap) in caps {
+            unreserved[tier] -= cap;
+            let tiers: &mut Vec<Option<Quota>> = reserved
+                .entry(tenant)
+                .or_insert_with(|| vec![None; num_blocks.len()]);
+            tiers[tier] = Some(quota(cap));
+        }
+        Self {
+            reserved,
+            shared: unreserved.into_iter().map(quota).collect(),
+            zero,
+            server_key,
+        }
+    }
+
+    // every quota back to zero held, for CryptMalloc::reset; tickets issued before the reset must not be refunded afterwards.
+    pub(crate) fn reset(&mut self) {
+        let quotas = self.reserved.values_mut().flatten().flatten();
+        for quota in quotas.chain(self.shared.iter_mut()) {
+            quota.held = self.zero.clone();
+        }
+    }
+
+    // encrypted "tenant may take another block of tier t", one per tier: room in the tenant's reservation, or failing that in the unreserved share.
+    pub(crate) fn gates(&self, tenant: u32) -> Vec<FheBool> {
+        set_server_key(self.server_key.clone());
+        (0..self.shared.len())
+            .map(|tier| {
+                let shared = &self.shared[tier];
+                let shared_room = shared.held.lt(shared.cap);
+                match self.reservation(tenant, tier) {
+                    Some(quota) => quota.held.lt(quota.cap) | shared_room,
+                    None => shared_room,
+                }
+            })
+            .collect()
+    }
+
+    // counts the blocks a routed request took and returns its ticket; `granted[t]` must be set only when tier t served it. a block goes to the tenant's reservation while it has room and to the unreserved share otherwise, matching gates.
+    pub(crate) fn charge(&mut self, tenant: u32, granted: Vec<FheBool>) -> AllocationTicket {
+        set_server_key(self.server_key.clone());
+        let mut from_reserved = Vec::with_capacity(granted.len());
+        for (tier, flag) in granted.iter().enumerate() {
+            let reserved = match self.reservation_mut(tenant, tier) {
+                Some(quota) => {
+                    let use_reserved = quota.held.lt(quota.cap);
+                    quota.held += FheUint32::cast_from(flag & &use_reserved);
+                    Some(use_reserved)
+                }
+                None => None,
+            };
+            let to_shared = match &reserved {
+                Some(use_reserved) => flag & &!use_reserved,
+                None => flag.clone(),
+            };
+            self.shared[tier].held += FheUint32::cast_from(to_shared);
+            from_reserved.push(reserved);
+        }
+        AllocationTicket {
+            tenant,
+            granted,
+            from_reserved,
+        }
+    }
+
+    // returns the ticket's block to the quota it was charged to when `freed` is set; `freed` must be the reclaimed flag, so a repeated or stale free refunds nothing.
+    pub(crate) fn refund(&mut self, ticket: AllocationTicket, freed: &FheBool) {
+        set_server_key(self.server_key.clone());
+        let AllocationTicket {
+            tenant,
+            granted,
+            from_reserved,
+        } = ticket;
+        for (tier, flag) in granted.iter().enumerate() {
+            let returned = flag & freed;
+            let reserved = from_reserved.get(tier).cloned().flatten();
+            let to_shared = match (reserved, self.reservation_mut(tenant, tier)) {
+                (Some(use_reserved), Some(quota)) => {
+                    quota.held -= FheUint32::cast_from(&returned & &use_reserved);
+                    &returned & &!use_reserved
+                }
+                _ => returned,
+            };
+            self.shared[tier].held -= FheUint32::cast_from(to_shared);
+        }
+    }
+
+    fn reservation(&self, tenant: u32, tier: usize) -> Option<&Quota> {
+        self.reserved
+            .get(&tenant)
+            .and_then(|tiers| tiers[tier].as_ref())
+    }
+
+    fn reservation_mut(&mut self, tenant: u32, tier: usize) -> Option<&mut Quota> {
+        self.reserved
+            .get_mut(&tenant)
+            .and_then(|tiers| tiers[tier].as_mut())
+    }
+}
+
+// turns builder reservations into per-tier block caps, rounding down; the last reservation for a tenant and tier wins, and a tier's fractions may not sum past one.
+pub(crate) fn reservation_caps(
+    num_blocks: &[usize],
+    reservations: &[(u32, usize, f32)],
+) -> Result<Vec<(u32, usize, u32)>, LayoutError> {
+    let mut latest: BTreeMap<(u32, usize), f32> = BTreeMap::new();
+    for &(tenant, tier, fraction) in reservations {
+        if tier >= num_blocks.len() || !(fraction > 0.0 && fraction <= 1.0) {
+            return Err(LayoutError::Reservation { tenant, tier });
+        }
+        latest.insert((tenant, tier), fraction);
+    }
+    let mut committed = vec![0.0f64; num_blocks.len()];
+    let mut unreserved: Vec<u32> = num_blocks.iter().map(|&blocks| blocks as u32).collect();
+    let mut caps = Vec::with_capacity(latest.len());
+    for ((tenant, tier), fraction) in latest {
+        committed[tier] += f64::from(fraction);
+        if committed[tier] > 1.0 + 1e-6 {
+            return Err(LayoutError::Reservation { tenant, tier });
+        }
+        let cap = (f64::from(fraction) * num_blocks[tier] as f64).floor() as u32;
+        // the tolerance above can let rounded caps sum past the tier, which would underflow its unreserved share.
+        unreserved[tier] = unreserved[tier]
+            .checked_sub(cap)
+            .ok_or(LayoutError::Reservation { tenant, tier })?;
+        caps.push((tenant, tier, cap));
+    }
+    Ok(caps)
+}
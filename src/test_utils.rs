@@ -0,0 +1,102 @@
+//! test_utils holds trusted-side assertion helpers for code built on CryptMalloc: occupancy is every tier's decrypted bitmap keyed by block size, written one tier per line as `tier16: 1100`.
+//! Everything here decrypts with the client key, so the module only exists behind the `test-utils` feature and stays out of production builds.
+
+use core::fmt;
+
+/// decrypted bitmaps in routing order, each paired with its tier's block size.
+pub type Occupancy = Vec<(usize, Vec<bool>)>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OccupancyParseError {
+    MissingTierPrefix { line: usize },
+    BadBlockSize { line: usize },
+    BadBit { line: usize, found: char },
+}
+
+impl fmt::Display for OccupancyParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingTierPrefix { line } => {
+                write!(f, "line {line}: expected `tier<block size>: <bits>`")
+            }
+            Self::BadBlockSize { line } => write!(f, "line {line}: block size is not a number"),
+            Self::BadBit { line, found } => {
+                write!(f, "line {line}: bits must be 0 or 1, found {found:?}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for OccupancyParseError {}
+
+/// `tier16: 1100`, the encoding used in failure messages and accepted by occupancy_from_str.
+pub fn format_tier(block_size: usize, bits: &[bool]) -> String {
+    let bits: String = bits
+        .iter()
+        .map(|&bit| if bit { '1' } else { '0' })
+        .collect();
+    format!("tier{block_size}: {bits}")
+}
+
+pub fn format_occupancy(occupancy: &[(usize, Vec<bool>)]) -> String {
+    occupancy
+        .iter()
+        .map(|(block_size, bits)| format_tier(*block_size, bits))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// parses one `tier<block size>: <bits>` line per tier; blank lines and surrounding whitespace are ignored, line numbers in errors start at 1.
+pub fn occupancy_from_str(text: &str) -> Result<Occupancy, OccupancyParseError> {
+    let mut occupancy = Vec::new();
+    for (idx, raw) in text.lines().enumerate() {
+        let line = idx + 1;
+        let raw = raw.trim();
+        if raw.is_empty() {
+            continue;
+        }
+        let (block_size, bits) = raw
+            .strip_prefix("tier")
+            .and_then(|rest| rest.split_once(':'))
+            .ok_or(OccupancyParseError::MissingTierPrefix { line })?;
+        let block_size = block_size
+            .trim()
+            .parse()
+            .map_err(|_| OccupancyParseError::BadBlockSize { line })?;
+        let bits = bits
+            .trim()
+            .chars()
+            .map(|found| match found {
+                '0' => Ok(false),
+                '1' => Ok(true),
+                found => Err(OccupancyParseError::BadBit { line, found }),
+            })
+            .collect::<Result<Vec<bool>, _>>()?;
+        occupancy.push((block_size, bits));
+    }
+    Ok(occupancy)
+}
+
+/// positions where the two bitmaps disagree; a length difference counts every position past the shorter one.
+pub fn bitmap_mismatches(actual: &[bool], expected: &[bool]) -> Vec<usize> {
+    (0..actual.len().max(expected.len()))
+        .filter(|&i| actual.get(i) != expected.get(i))
+        .collect()
+}
+
+// failure text shared by SlabClass::assert_bitmap and CryptMalloc::assert_occupancy; None when the bitmaps agree.
+pub(crate) fn tier_mismatch(
+    block_size: usize,
+    actual: &[bool],
+    expected: &[bool],
+) -> Option<String> {
+    let mismatches = bitmap_mismatches(actual, expected);
+    if mismatches.is_empty() {
+        return None;
+    }
+    Some(format!(
+        "tier{block_size} bitmap differs at indices {mismatches:?}\n  expected {}\n  actual   {}",
+        format_tier(block_size, expected),
+        format_tier(block_size, actual),
+    ))
+}
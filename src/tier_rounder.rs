@@ -0,0 +1,122 @@
+//! TierRounder answers "which tier would this size land in" as ciphertexts, using the very comparisons CryptMalloc routes with; the allocator owns one, so padding and tier predictions computed from CryptMalloc::rounder never drift from where allocate actually sends a request.
+//! A SizeBlinder installed on the builder lives in the allocator's rounder too: queries bucket the size first exactly as routing does, so predictions describe the bucketed request the tiers really see.
+//! Every query costs the same comparison and select chain over all five tiers whatever the size; index 5 stands for the arena.
+
+use crate::keys::Keys;
+use crate::size_blinder::SizeBlinder;
+use core::fmt;
+use tfhe::{prelude::*, set_server_key, FheBool, FheUint32, FheUint64, ServerKey};
+
+#[derive(Clone)]
+pub struct TierRounder {
+    // plaintext tier sizes go through tfhe's scalar comparisons so no bound is ever an encrypted operand; None for confidential tiers, which compare against enc_sizes.
+    block_sizes: Option<[u64; 5]>,
+    // tier sizes as ciphertexts, the values round_up selects between.
+    enc_sizes: Box<[FheUint64; 5]>,
+    // buckets sizes before every query; routing blinds through the same field before its own comparisons.
+    blinder: Option<SizeBlinder>,
+    server_key: ServerKey,
+}
+
+impl fmt::Debug for TierRounder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("TierRounder");
+        match &self.block_sizes {
+            Some(block_sizes) => debug.field("block_sizes", block_sizes),
+            None => debug.field("block_sizes", &"<ciphertext>"),
+        };
+        if let Some(blinder) = &self.blinder {
+            debug.field("blinder_buckets", &blinder.buckets());
+        }
+        debug.finish()
+    }
+}
+
+impl TierRounder {
+    /// plaintext tier sizes in ascending order; comparisons use tfhe's scalar ops and only the select targets are encrypted.
+    pub fn new(keys: &Keys, block_sizes: [u64; 5]) -> Self {
+        let server_key = keys.server_key();
+        set_server_key(server_key.clone());
+        let enc_sizes = Box::new(block_sizes.map(|size| keys.enc_u64_cached(size)));
+        Self {
+            block_sizes: Some(block_sizes),
+            enc_sizes,
+            blinder: None,
+            server_key,
+        }
+    }
+
+    /// encrypted tier sizes under `keys`, which must be ascending; the order cannot be checked without decrypting, so the caller vouches for it.
+    pub fn from_encrypted(keys: &Keys, block_sizes: [FheUint64; 5]) -> Self {
+        let server_key = keys.server_key();
+        set_server_key(server_key.clone());
+        Self {
+            block_sizes: None,
+            enc_sizes: Box::new(block_sizes),
+            blinder: None,
+            server_key,
+        }
+    }
+
+    pub(crate) fn set_blinder(&mut self, blinder: Option<SizeBlinder>) {
+        self.blinder = blinder;
+    }
+
+    // pads `size` to its bucket when a blinder is installed.
+    pub(crate) fn blind(&self, size: FheUint64) -> FheUint64 {
+        set_server_key(self.server_key.clone());
+        match &self.blinder {
+            Some(blinder) => blinder.blind_installed(&size),
+            None => size,
+        }
+    }
+
+    /// the tier size `size` would be padded to; sizes above the largest tier go to the arena unpadded, or padded only to their bucket with a blinder, and sizes below the smallest tier (zero included) round to it, as routing does.
+    pub fn round_up(&self, size: &FheUint64) -> FheUint64 {
+        set_server_key(self.server_key.clone());
+        let size = self.clamp_to_min(&self.blind(size.clone()));
+        let mut rounded = size.clone();
+        for tier in (0..self.enc_sizes.len()).rev() {
+            rounded = self
+                .fits(&size, tier)
+                .if_then_else(&self.enc_sizes[tier], &rounded);
+        }
+        rounded
+    }
+
+    /// index of the tier `size` routes to, or 5 for the arena; tiers are ascending, so the index is the number of tiers the size does not fit.
+    pub fn tier_index(&self, size: &FheUint64) -> FheUint32 {
+        set_server_key(self.server_key.clone());
+        let size = self.clamp_to_min(&self.blind(size.clone()));
+        let mut index = FheUint32::cast_from(!self.fits(&size, 0));
+        for tier in 1..self.enc_sizes.len() {
+            index += FheUint32::cast_from(!self.fits(&size, tier));
+        }
+        index
+    }
+
+    /// bytes of padding the tier, and the blinder's bucket if any, add on top of `size`; zero for arena requests without a blinder.
+    pub fn padding_for(&self, size: &FheUint64) -> FheUint64 {
+        let rounded = self.round_up(size);
+        &rounded - size
+    }
+
+    // raises sizes below the smallest tier to it; zero is below every bound, so one comparison covers both zero-length and undersized requests.
+    pub(crate) fn clamp_to_min(&self, size: &FheUint64) -> FheUint64 {
+        set_server_key(self.server_key.clone());
+        let below = match &self.block_sizes {
+            Some(block_sizes) => size.lt(block_sizes[0]),
+            None => size.lt(&self.enc_sizes[0]),
+        };
+        below.if_then_else(&self.enc_sizes[0], size)
+    }
+
+    // encrypted `size <= block_size[tier]`.
+    pub(crate) fn fits(&self, size: &FheUint64, tier: usize) -> FheBool {
+        set_server_key(self.server_key.clone());
+        match &self.block_sizes {
+            Some(block_sizes) => size.le(block_sizes[tier]),
+            None => size.le(&self.enc_sizes[tier]),
+        }
+    }
+}
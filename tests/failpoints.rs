@@ -0,0 +1,71 @@
+#![cfg(feature = "failpoints")]
+
+use cryptmalloc::failpoints::{FailPoint, FailPolicy, Failpoints};
+use cryptmalloc::CryptMalloc;
+
+#[test]
+fn failpoint_policies_are_deterministic() {
+    let mut points = Failpoints::default();
+    assert!(!points.should_fail(FailPoint::ArenaAllocate));
+
+    points.inject(FailPoint::SlabAllocate(2), FailPolicy::EveryNth(3));
+    let fired: Vec<bool> = (0..6)
+        .map(|_| points.should_fail(FailPoint::SlabAllocate(2)))
+        .collect();
+    assert_eq!(fired, [false, false, true, false, false, true]);
+    assert_eq!(points.triggered(FailPoint::SlabAllocate(2)), 2);
+    assert!(!points.should_fail(FailPoint::SlabAllocate(1)));
+
+    let policy = FailPolicy::Probability { p: 0.5, seed: 7 };
+    points.inject(FailPoint::ArenaAllocate, policy);
+    let first: Vec<bool> = (0..200)
+        .map(|_| points.should_fail(FailPoint::ArenaAllocate))
+        .collect();
+    points.inject(FailPoint::ArenaAllocate, policy);
+    let second: Vec<bool> = (0..200)
+        .map(|_| points.should_fail(FailPoint::ArenaAllocate))
+        .collect();
+    assert_eq!(first, second);
+    let hits = first.iter().filter(|fired| **fired).count();
+    assert!((50..150).contains(&hits));
+
+    points.clear_all();
+    assert!(!points.is_armed(FailPoint::ArenaAllocate));
+}
+
+#[test]
+fn injected_arena_failure_clears_is_some() {
+    let mut allocator = CryptMalloc::new(4096);
+    allocator.inject_failure(FailPoint::ArenaAllocate, FailPolicy::EveryNth(2));
+
+    let first = allocator.allocate(allocator.keys().enc_u64(512));
+    assert!(allocator.keys().dec_bool(&first.is_some));
+
+    let second = allocator.allocate(allocator.keys().enc_u64(512));
+    assert!(!allocator.keys().dec_bool(&second.is_some));
+    assert_eq!(allocator.failpoints().triggered(FailPoint::ArenaAllocate), 1);
+
+    allocator.clear_failure(FailPoint::ArenaAllocate);
+    let third = allocator.allocate(allocator.keys().enc_u64(512));
+    assert!(allocator.keys().dec_bool(&third.is_some));
+}
+
+#[test]
+fn failpoint_masked_requests_fail_the_one_hot_audit() {
+    let mut allocator = CryptMalloc::new(4096);
+    let (granted, audit) = allocator.allocate_audited(allocator.keys().enc_u64(512));
+    assert!(allocator.keys().dec_bool(&granted.is_some));
+    assert!(allocator.keys().dec_bool(&audit.assert_one_hot()));
+
+    // the failpoint clears the arena flag after routing picked it, so no flag is left set.
+    allocator.inject_failure(FailPoint::ArenaAllocate, FailPolicy::EveryNth(1));
+    let (granted, audit) = allocator.allocate_audited(allocator.keys().enc_u64(512));
+    assert!(!allocator.keys().dec_bool(&granted.is_some));
+    assert!(!allocator.keys().dec_bool(&audit.arena));
+    assert!(!allocator.keys().dec_bool(&audit.assert_one_hot()));
+
+    allocator.inject_failure(FailPoint::SlabAllocate(0), FailPolicy::EveryNth(1));
+    let (granted, audit) = allocator.allocate_audited(allocator.keys().enc_u64(16));
+    assert!(!allocator.keys().dec_bool(&granted.is_some));
+    assert!(!allocator.keys().dec_bool(&audit.assert_one_hot()));
+}
@@ -0,0 +1,21 @@
+//! Round-trips exported keys through the `ffi` feature's Rust-side helpers; the C ABI itself is exercised by cryptmalloc-ffi's tests.
+#![cfg(feature = "ffi")]
+
+use cryptmalloc::{
+    ffi::{export_keys, import_keys},
+    Keys,
+};
+
+#[test]
+fn exported_keys_import_back() {
+    let keys = Keys::new();
+    let imported = import_keys(&export_keys(&keys).unwrap()).unwrap();
+    assert_eq!(imported.dec_u64(&keys.enc_u64(77)), 77);
+    assert!(import_keys(b"not keys").is_err());
+
+    // a flipped bit anywhere in the blob fails the envelope checksum before anything is decoded.
+    let mut corrupted = export_keys(&keys).unwrap();
+    let last = corrupted.len() - 1;
+    corrupted[last] ^= 1;
+    assert!(import_keys(&corrupted).is_err());
+}
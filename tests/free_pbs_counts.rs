@@ -0,0 +1,80 @@
+//! Counts tfhe bootstraps around CryptMalloc::free to pin that slab and arena pointers cost the same, whichever side of the arena the tiers sit on.
+//! The counter is process-wide, so this file holds a single test and runs in its own binary.
+
+use cryptmalloc::{
+    layout::{Layout, TierPlacement},
+    CryptMalloc, EncryptedPtr, Keys,
+};
+
+const TIERS: [(usize, usize); 5] = [(16, 1), (32, 1), (64, 1), (128, 1), (256, 1)];
+
+// frees `ptr` and returns the decrypted reclamation flag with the bootstraps the free took.
+fn counted_free(alloc: &mut CryptMalloc, keys: &Keys, ptr: u64) -> (bool, u64) {
+    let ptr = EncryptedPtr(keys.enc_u64(ptr));
+    tfhe::reset_pbs_count();
+    let freed = alloc.free(&ptr);
+    let pbs = tfhe::get_pbs_count();
+    (keys.dec_bool(&freed), pbs)
+}
+
+// allocates one 16-byte slab block and one 300-byte arena chunk, then frees both and the slab block once more, and checks the three frees bootstrap equally often.
+fn check_layout(keys: &Keys, layout: Layout, slab_offset: u64, arena_offset: u64) {
+    let mut alloc = CryptMalloc::builder(0)
+        .keys(keys.clone())
+        .layout(layout)
+        .build()
+        .unwrap();
+
+    let slab_ptr = alloc.allocate(keys.enc_u64(16));
+    let arena_ptr = alloc.allocate(keys.enc_u64(300));
+    assert_eq!(keys.dec_u64(&slab_ptr.value.0), slab_offset);
+    assert_eq!(keys.dec_u64(&arena_ptr.value.0), arena_offset);
+
+    let (freed_arena, arena_pbs) = counted_free(&mut alloc, keys, arena_offset);
+    assert!(!freed_arena);
+    assert!(keys.dec_bool(&alloc.slabs()[0].bitmap()[0]));
+
+    let (freed_slab, slab_pbs) = counted_free(&mut alloc, keys, slab_offset);
+    assert!(freed_slab);
+    assert!(!keys.dec_bool(&alloc.slabs()[0].bitmap()[0]));
+
+    // a double free matches the same cell but reclaims nothing, and costs the same as the first free.
+    let (freed_again, again_pbs) = counted_free(&mut alloc, keys, slab_offset);
+    assert!(!freed_again);
+    assert!(!keys.dec_bool(&alloc.slabs()[0].bitmap()[0]));
+
+    assert!(slab_pbs > 0);
+    assert_eq!(slab_pbs, arena_pbs);
+    assert_eq!(again_pbs, slab_pbs);
+}
+
+#[test]
+fn slab_and_arena_frees_bootstrap_equally_on_either_side_of_the_arena() {
+    let keys = Keys::new();
+
+    // tiers first: [0, 496) then the arena.
+    check_layout(&keys, Layout::contiguous(&TIERS, 512), 0, 496);
+
+    // arena first: [0, 512), then the tiers back to back.
+    let mut base_offset = 512;
+    let tiers = TIERS
+        .iter()
+        .map(|&(block_size, num_blocks)| {
+            let placement = TierPlacement {
+                block_size,
+                num_blocks,
+                base_offset,
+            };
+            base_offset += placement.extent();
+            placement
+        })
+        .collect();
+    let arena_first = Layout {
+        tiers,
+        arena_start: 0,
+        arena_end: 512,
+        seed: None,
+    };
+    arena_first.validate().unwrap();
+    check_layout(&keys, arena_first, 512, 0);
+}
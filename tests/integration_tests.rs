@@ -13,4 +13,2124 @@ fn allocator_smoke_test() {
     let small = allocator.keys().enc_u64(32);
     let _small = allocator.allocate(small);
     allocator.arena().cursor();
+
+    // a 32-byte request takes the first block of the 32-byte tier and nothing else.
+    #[cfg(feature = "test-utils")]
+    {
+        let mut expected: Vec<(usize, Vec<bool>)> = allocator
+            .slabs()
+            .iter()
+            .map(|slab| (slab.block_size(), vec![false; slab.num_blocks()]))
+            .collect();
+        expected[1].1[0] = true;
+        allocator.assert_occupancy(&expected);
+    }
+}
+
+#[test]
+fn oblivious_result_combinators_propagate_poison() {
+    use cryptmalloc::{EncryptedOption, Keys, ObliviousResult};
+
+    let keys = Keys::new();
+    let good = ObliviousResult::new(keys.enc_u64(40), keys.enc_true());
+    let bad = ObliviousResult::new(keys.enc_u64(40), keys.enc_false());
+
+    let good = good.map(|v| v + 2u64);
+    let bad = bad.map(|v| v + 2u64);
+    assert_eq!(keys.dec_u64(&good.value), 42);
+    assert_eq!(keys.dec_u64(&bad.value), 42);
+    assert!(keys.dec_bool(&good.ok));
+    assert!(!keys.dec_bool(&bad.ok));
+
+    let failing_step = keys.enc_false();
+    let chained = good.and_then(|v| ObliviousResult::new(v, failing_step));
+    assert!(!keys.dec_bool(&chained.ok));
+
+    let option: EncryptedOption<_> = chained.into_option();
+    assert!(!keys.dec_bool(&option.is_some));
+    assert_eq!(keys.dec_u64(&option.value), 42);
+}
+
+#[test]
+fn allocate_oblivious_rejects_without_touching_state() {
+    use cryptmalloc::Keys;
+
+    let keys = Keys::new();
+    let mut alloc = CryptMalloc::builder(512)
+        .keys(keys.clone())
+        .tiers(&[(16, 1), (32, 1), (64, 1), (128, 1), (256, 1)])
+        .build()
+        .unwrap();
+    let snapshot = |alloc: &CryptMalloc| {
+        let bitmaps: Vec<Vec<bool>> = alloc
+            .slabs()
+            .iter()
+            .map(|slab| slab.bitmap().iter().map(|bit| keys.dec_bool(bit)).collect())
+            .collect();
+        (bitmaps, keys.dec_u64(alloc.arena().cursor()))
+    };
+
+    let granted = alloc.allocate_oblivious(keys.enc_u64(16));
+    assert!(keys.dec_bool(&granted.ok));
+    assert_eq!(keys.dec_u64(&granted.value.0), 0);
+    let before = snapshot(&alloc);
+    assert!(before.0[0][0]);
+
+    // zero-length requests are rejected rather than coerced to 16 bytes.
+    let zero = alloc.allocate_oblivious(keys.enc_u64(0));
+    assert!(!keys.dec_bool(&zero.ok));
+    // the 16-byte tier's only block is taken, and small requests never spill into the arena.
+    let exhausted = alloc.allocate_oblivious(keys.enc_u64(16));
+    assert!(!keys.dec_bool(&exhausted.ok));
+
+    assert_eq!(snapshot(&alloc), before);
+    assert_eq!(alloc.metrics().oblivious_allocations, 3);
+}
+
+#[test]
+fn evm_host_stack_seeding_and_drain() {
+    use cryptmalloc::{Keys, EVM};
+
+    let keys = Keys::new();
+    let program = vec![0x00u8];
+    let pc_values = keys.build_enc_indices_u32(program.len());
+    let mut evm = EVM::new(
+        program,
+        4,
+        keys.server_key(),
+        keys.enc_false(),
+        keys.enc_true(),
+        keys.enc_zero_u32(),
+        keys.enc_zero_u64(),
+        keys.enc_u32(1),
+        pc_values,
+    );
+
+    evm.push_input(keys.enc_u64(3));
+    evm.push_input(keys.enc_u64(4));
+    assert_eq!(keys.dec_u32(evm.stack_len()), 2);
+    assert_eq!(keys.dec_u64(&evm.stack_get(0)), 4);
+    assert_eq!(keys.dec_u64(&evm.stack_get(1)), 3);
+    assert_eq!(keys.dec_u64(&evm.stack_get(2)), 0);
+
+    let outputs = evm.drain_outputs(1);
+    assert_eq!(keys.dec_u64(&outputs[0]), 4);
+    assert_eq!(keys.dec_u32(evm.stack_len()), 1);
+
+    // the next push reuses the popped slot instead of leaving the stale 4 on top.
+    evm.push_input(keys.enc_u64(5));
+    assert_eq!(keys.dec_u32(evm.stack_len()), 2);
+    assert_eq!(keys.dec_u64(&evm.stack_get(0)), 5);
+    assert_eq!(keys.dec_u64(&evm.stack_get(1)), 3);
+    assert_eq!(keys.dec_u64(&evm.stack_get(u32::MAX as usize)), 0);
+    assert_eq!(keys.dec_u64(&evm.stack_get(usize::MAX)), 0);
+
+    evm.reset();
+    assert_eq!(keys.dec_u32(evm.stack_len()), 0);
+}
+
+#[test]
+fn keys_table_cache_shares_prefixes_within_its_bound() {
+    use cryptmalloc::Keys;
+
+    let keys = Keys::new();
+    let clone = keys.clone();
+    keys.set_table_cache_limit(6);
+
+    let short = keys.build_enc_indices_u32(3);
+    assert_eq!(keys.table_cache_len(), 3);
+    // a longer table reuses the cached prefix, and clones share it.
+    let long = clone.build_enc_indices_u32(5);
+    assert_eq!(keys.table_cache_len(), 5);
+    assert_eq!(keys.dec_u32(&short[2]), 2);
+    assert!(long
+        .iter()
+        .enumerate()
+        .all(|(i, ct)| keys.dec_u32(ct) == i as u32));
+
+    // only one more ciphertext fits, and the uncached tail is still encrypted.
+    let offsets = keys.build_enc_offsets_u64(3, 16);
+    assert_eq!(keys.table_cache_len(), 6);
+    let plain: Vec<u64> = offsets.iter().map(|ct| keys.dec_u64(ct)).collect();
+    assert_eq!(plain, [0, 16, 32]);
+    let again = keys.build_enc_offsets_u64(3, 16);
+    assert_eq!(keys.dec_u64(&again[2]), 32);
+    assert_eq!(keys.table_cache_len(), 6);
+    assert_eq!(keys.const_cache_misses(), 0);
+
+    keys.set_table_cache_limit(0);
+    assert_eq!(keys.table_cache_len(), 0);
+    let uncached = keys.build_enc_indices_u32(2);
+    assert_eq!(keys.dec_u32(&uncached[1]), 1);
+    assert_eq!(keys.table_cache_len(), 0);
+}
+
+#[test]
+fn keys_const_cache_hits_and_decrypts() {
+    use cryptmalloc::Keys;
+
+    let keys = Keys::new();
+    keys.precompute(&[0, 16], &[1]);
+    assert_eq!(keys.const_cache_misses(), 3);
+    assert_eq!(keys.const_cache_hits(), 0);
+
+    assert_eq!(keys.dec_u64(&keys.enc_u64_cached(16)), 16);
+    assert_eq!(keys.dec_u32(&keys.enc_u32_cached(1)), 1);
+    assert_eq!(keys.const_cache_hits(), 2);
+
+    // tables are cached apart from the constants and leave their counters alone.
+    let offsets = keys.build_enc_offsets_u64(3, 8);
+    assert_eq!(keys.dec_u64(&offsets[0]), 0);
+    assert_eq!(keys.dec_u64(&offsets[2]), 16);
+    assert_eq!(keys.const_cache_hits(), 2);
+    assert_eq!(keys.const_cache_misses(), 3);
+
+    keys.set_const_cache_randomize(true, 1);
+    assert!(keys.dec_bool(&keys.enc_true_cached()));
+    assert!(keys.dec_bool(&keys.enc_true_cached()));
+    assert_eq!(keys.dec_u64(&keys.enc_u64_cached(16)), 16);
+}
+
+#[test]
+fn colliding_pointer_frees_only_first_matching_tier() {
+    use cryptmalloc::{EncryptedPtr, Keys, SlabClass};
+
+    let keys = Keys::new();
+    let tiny_slab = |keys: &Keys| {
+        SlabClass::new(
+            16,
+            2,
+            keys.enc_u64(0),
+            keys.server_key(),
+            keys.enc_false(),
+            keys.enc_true(),
+            keys.enc_zero_u32(),
+            keys.enc_zero_u64(),
+            keys.build_enc_indices_u32(2),
+            keys.build_enc_offsets_u64(2, 16),
+        )
+    };
+    // both tiers share base offset 0, so offset 0 names a cell in each of them.
+    let mut slabs = vec![tiny_slab(&keys), tiny_slab(&keys)];
+    for slab in slabs.iter_mut() {
+        let granted = slab.allocate_masked(keys.enc_true());
+        assert!(keys.dec_bool(&granted.is_some));
+    }
+
+    let colliding = EncryptedPtr(keys.enc_u64(0));
+    let (matched, reclaimed) = SlabClass::free_first_match(&mut slabs, &colliding).unwrap();
+    assert!(keys.dec_bool(&matched));
+    assert!(keys.dec_bool(&reclaimed));
+    assert!(!keys.dec_bool(&slabs[0].bitmap()[0]));
+    assert!(keys.dec_bool(&slabs[1].bitmap()[0]));
+
+    // the first tier's cell is free now, so the same pointer still matches but reclaims nothing.
+    let (matched, reclaimed) = SlabClass::free_first_match(&mut slabs, &colliding).unwrap();
+    assert!(keys.dec_bool(&matched));
+    assert!(!keys.dec_bool(&reclaimed));
+    assert!(keys.dec_bool(&slabs[1].bitmap()[0]));
+
+    let stray = EncryptedPtr(keys.enc_u64(999));
+    let (matched, reclaimed) = SlabClass::free_first_match(&mut slabs, &stray).unwrap();
+    assert!(!keys.dec_bool(&matched));
+    assert!(!keys.dec_bool(&reclaimed));
+    assert!(keys.dec_bool(&slabs[1].bitmap()[0]));
+}
+
+#[test]
+fn protocol_rejects_foreign_wire_versions() {
+    use cryptmalloc::protocol::{decode, ProtocolError, Request, WIRE_VERSION};
+    use cryptmalloc::{ClientEndpoint, Keys};
+
+    let keys = Keys::new();
+    let mut client = ClientEndpoint::new(keys).unwrap();
+    let mut bytes = client.alloc_request(48).unwrap();
+    match decode::<Request>(&bytes).unwrap() {
+        Request::Alloc(req) => {
+            assert_eq!(req.request_id, 0);
+            assert_eq!(client.keys().dec_u64(&req.size), 48);
+        }
+        _ => panic!("expected an alloc request"),
+    }
+
+    bytes[0] = bytes[0].wrapping_add(1);
+    match decode::<Request>(&bytes) {
+        Err(ProtocolError::UnsupportedVersion { found, expected }) => {
+            assert_eq!(expected, WIRE_VERSION);
+            assert_ne!(found, WIRE_VERSION);
+        }
+        _ => panic!("expected a version error"),
+    }
+    assert!(matches!(
+        decode::<Request>(&[1]),
+        Err(ProtocolError::Truncated)
+    ));
+    let mut garbage = WIRE_VERSION.to_le_bytes().to_vec();
+    garbage.extend([0xff, 0xff]);
+    assert!(matches!(
+        decode::<Request>(&garbage),
+        Err(ProtocolError::Decode(_))
+    ));
+}
+
+#[test]
+fn protocol_round_trip_allocate_and_free() {
+    use cryptmalloc::protocol::{encode, AllocRequest, ClientReply, ProtocolError, Request};
+    use cryptmalloc::{ClientEndpoint, Keys, ServerEndpoint};
+    use tfhe::{prelude::*, FheUint64};
+
+    let keys = Keys::new();
+    // the server gets the server key and nothing else.
+    let mut server = ServerEndpoint::new(
+        keys.server_key(),
+        CryptMalloc::builder(512).tiers(&[(16, 1), (32, 1), (64, 1), (128, 1), (256, 1)]),
+    )
+    .unwrap();
+    assert!(!server.allocator().keys().has_client_key());
+    // a client without the client key would send every size as a trivial ciphertext.
+    let server_only = Keys::server_only(keys.server_key());
+    let encrypt = std::panic::AssertUnwindSafe(|| server_only.enc_u64(24));
+    assert!(std::panic::catch_unwind(encrypt).is_err());
+    assert_eq!(keys.dec_u64(&server_only.trivial_u64(24)), 24);
+    assert!(matches!(
+        ClientEndpoint::new(server_only),
+        Err(ProtocolError::ServerOnlyKeys)
+    ));
+    let mut client = ClientEndpoint::new(keys).unwrap();
+
+    let response = server.handle(&client.alloc_request(24).unwrap()).unwrap();
+    let reply = client.decrypt_response(&response).unwrap();
+    // 24 bytes lands in the 32-byte tier, which the planned layout puts at offset 32.
+    assert_eq!(
+        reply,
+        ClientReply::Allocated {
+            request_id: 0,
+            offset: Some(32)
+        }
+    );
+
+    let response = server.handle(&client.free_request(32).unwrap()).unwrap();
+    assert_eq!(
+        client.decrypt_response(&response).unwrap(),
+        ClientReply::Freed {
+            request_id: 1,
+            reclaimed: true
+        }
+    );
+
+    let response = server.handle(&client.reset_request().unwrap()).unwrap();
+    assert_eq!(
+        client.decrypt_response(&response).unwrap(),
+        ClientReply::Reset { request_id: 2 }
+    );
+
+    // a tampered response laid out like Response::Alloc but carrying a 32-bit offset has too few blocks for an FheUint64; the client refuses it instead of decrypting.
+    let (offset, is_some) = (client.keys().enc_u32(32), client.keys().enc_true());
+    let tampered = encode(&(0u32, 3u64, offset, is_some)).unwrap();
+    assert!(client.decrypt_response(&tampered).is_err());
+
+    // a trivial ciphertext decodes fine but does not carry a fresh encryption's parameters.
+    let forged = encode(&Request::Alloc(AllocRequest {
+        request_id: 3,
+        size: FheUint64::encrypt_trivial(24u64),
+    }))
+    .unwrap();
+    assert!(matches!(
+        server.handle(&forged),
+        Err(ProtocolError::Nonconformant)
+    ));
+    assert_eq!(server.allocator().metrics().allocations, 1);
+}
+
+#[test]
+fn randomized_layouts_are_seed_deterministic() {
+    use cryptmalloc::layout::LayoutError;
+    use cryptmalloc::CryptMallocBuilder;
+
+    let contiguous = CryptMallocBuilder::new(4096).plan_layout();
+    assert_eq!(contiguous.tiers[1].base_offset, 16 * 1024);
+    assert_eq!(contiguous.arena_start, 5 * 16 * 1024);
+
+    let first = CryptMallocBuilder::new(4096)
+        .randomize_layout(Some(7))
+        .plan_layout();
+    let again = CryptMallocBuilder::new(4096)
+        .randomize_layout(Some(7))
+        .plan_layout();
+    let other = CryptMallocBuilder::new(4096)
+        .randomize_layout(Some(8))
+        .plan_layout();
+    assert_eq!(first, again);
+    assert_ne!(first, other);
+    assert_eq!(first.seed, Some(7));
+    assert_eq!(first.arena_size(), 4096);
+    first.validate().unwrap();
+    other.validate().unwrap();
+    assert_ne!(
+        cryptmalloc::Layout::fresh_seed().unwrap(),
+        cryptmalloc::Layout::fresh_seed().unwrap()
+    );
+    let drawn = CryptMallocBuilder::new(4096)
+        .randomize_layout(None)
+        .try_plan_layout()
+        .unwrap();
+    assert!(drawn.seed.is_some());
+
+    let bytes = bincode::serialize(&first).unwrap();
+    let restored: cryptmalloc::Layout = bincode::deserialize(&bytes).unwrap();
+    assert_eq!(
+        CryptMallocBuilder::new(0).layout(restored).plan_layout(),
+        first
+    );
+
+    let mut overlapping = contiguous.clone();
+    overlapping.tiers[2].base_offset = overlapping.tiers[1].base_offset;
+    assert_eq!(
+        overlapping.validate(),
+        Err(LayoutError::Overlap {
+            first: 1,
+            second: 2
+        })
+    );
+}
+
+#[test]
+fn oversized_layouts_fail_to_build() {
+    use cryptmalloc::layout::LayoutError;
+    use cryptmalloc::{CryptMalloc, Layout};
+
+    // planning fails before any keys are generated.
+    assert!(matches!(
+        CryptMalloc::builder(u64::MAX).build(),
+        Err(LayoutError::ArenaBounds { .. })
+    ));
+    assert!(matches!(
+        CryptMalloc::builder(u64::MAX)
+            .randomize_layout(Some(7))
+            .build(),
+        Err(LayoutError::ArenaBounds { .. })
+    ));
+    let huge = [(16, 1), (32, 1), (64, 1), (128, 1), (1 << 40, 1 << 30)];
+    assert!(matches!(
+        CryptMalloc::builder(0).tiers(&huge).build(),
+        Err(LayoutError::OffsetOverflow { tier: 4 })
+    ));
+    assert_eq!(
+        Layout::try_randomized(&huge, 0, 7).unwrap_err(),
+        Layout::try_contiguous(&huge, 0).unwrap_err()
+    );
+}
+
+#[test]
+fn randomized_allocator_round_trip() {
+    use cryptmalloc::CryptMalloc;
+
+    let mut allocator = CryptMalloc::builder(4096)
+        .randomize_layout(Some(42))
+        .build()
+        .unwrap();
+    let layout = allocator.layout().clone();
+
+    let size = allocator.keys().enc_u64(20);
+    let granted = allocator.allocate(size);
+    assert!(allocator.keys().dec_bool(&granted.is_some));
+    assert_eq!(
+        allocator.keys().dec_u64(&granted.value.0),
+        layout.tiers[1].base_offset
+    );
+
+    let matched = allocator.free(&granted.value);
+    assert!(allocator.keys().dec_bool(&matched));
+    assert!(!allocator.keys().dec_bool(&allocator.slabs()[1].bitmap()[0]));
+}
+
+#[test]
+fn layout_envelope_fixture_still_decodes() {
+    use cryptmalloc::envelope::{EnvelopeError, Persistent};
+    use cryptmalloc::layout::DEFAULT_TIERS;
+    use cryptmalloc::Layout;
+
+    // generated at format version 1; if this stops decoding, bump Layout::FORMAT_VERSION and add a migration instead of regenerating it.
+    let fixture = include_bytes!("fixtures/layout_v1.bin");
+    let expected = Layout::contiguous(&DEFAULT_TIERS, 4096);
+    assert_eq!(Layout::from_bytes(fixture).unwrap(), expected);
+    assert_eq!(expected.to_bytes().unwrap(), fixture.to_vec());
+
+    let mut flipped = fixture.to_vec();
+    flipped[20] ^= 0x01;
+    assert_eq!(
+        Layout::from_bytes(&flipped),
+        Err(EnvelopeError::ChecksumMismatch)
+    );
+
+    // a well-formed blob from a newer format version: rewrite the version and re-seal the FNV-1a checksum.
+    let mut future = fixture.to_vec();
+    future[4] = 9;
+    let body_end = future.len() - 8;
+    let checksum = future[..body_end]
+        .iter()
+        .fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+            (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+        });
+    future[body_end..].copy_from_slice(&checksum.to_le_bytes());
+    assert_eq!(
+        Layout::from_bytes(&future),
+        Err(EnvelopeError::UnsupportedVersion {
+            found: 9,
+            current: Layout::FORMAT_VERSION
+        })
+    );
+
+    let mut foreign = fixture.to_vec();
+    foreign[..4].copy_from_slice(b"XXXX");
+    assert!(matches!(
+        Layout::from_bytes(&foreign),
+        Err(EnvelopeError::BadMagic { .. })
+    ));
+    assert_eq!(
+        Layout::from_bytes(&fixture[..fixture.len() - 1]),
+        Err(EnvelopeError::LengthMismatch {
+            declared: 145,
+            available: 144
+        })
+    );
+}
+
+#[test]
+fn decoded_envelopes_are_structurally_validated() {
+    use cryptmalloc::envelope::{open, seal, EnvelopeError};
+    use cryptmalloc::layout::{LayoutError, DEFAULT_TIERS};
+    use cryptmalloc::{Layout, NonceWindow};
+    use tfhe::FheBool;
+
+    // a tier whose end wraps past u64::MAX would slip under the overlap checks.
+    let mut wrapping = Layout::contiguous(&DEFAULT_TIERS, 4096);
+    wrapping.tiers[4].base_offset = u64::MAX - 8;
+    assert_eq!(
+        wrapping.validate(),
+        Err(LayoutError::OffsetOverflow { tier: 4 })
+    );
+    assert_eq!(wrapping.clone().aligned(), wrapping);
+    assert!(matches!(
+        Layout::from_bytes(&wrapping.to_bytes().unwrap()),
+        Err(EnvelopeError::Invalid(_))
+    ));
+
+    let mut inverted = Layout::contiguous(&DEFAULT_TIERS, 4096);
+    inverted.arena_start = inverted.arena_end + 1;
+    assert_eq!(inverted.arena_size(), 0);
+    assert_eq!(inverted.clone().aligned(), inverted);
+    assert!(matches!(
+        Layout::from_bytes(&inverted.to_bytes().unwrap()),
+        Err(EnvelopeError::Invalid(_))
+    ));
+
+    // a hand-built window whose recency order names a nonce it holds no entry for.
+    let mut payload = Vec::new();
+    payload.extend(4u64.to_le_bytes()); // capacity
+    payload.extend(0u64.to_le_bytes()); // entries
+    payload.extend(1u64.to_le_bytes()); // order
+    payload.extend(0u64.to_le_bytes());
+    payload.extend(7u128.to_le_bytes());
+    payload.extend(1u64.to_le_bytes()); // next_stamp
+    let mut blob = b"CMNW".to_vec();
+    blob.extend(1u16.to_le_bytes());
+    blob.extend((payload.len() as u64).to_le_bytes());
+    blob.extend(payload);
+    let checksum = blob.iter().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    });
+    blob.extend(checksum.to_le_bytes());
+    assert!(matches!(
+        open::<NonceWindow<FheBool>>(&blob),
+        Err(EnvelopeError::Invalid(_))
+    ));
+    let empty: NonceWindow<FheBool> = open(&seal(&NonceWindow::new(4)).unwrap()).unwrap();
+    assert_eq!(empty.capacity(), 4);
+}
+
+#[test]
+fn encrypted_option_gather_and_scatter() {
+    use cryptmalloc::{EncryptedOption, EncryptedPtr, Keys};
+
+    let keys = Keys::new();
+    let mut slots: Vec<EncryptedOption<EncryptedPtr>> = (0..4u64)
+        .map(|i| {
+            let ptr = EncryptedPtr(keys.enc_u64(100 + i));
+            if i == 3 {
+                EncryptedOption::none(ptr, keys.enc_false())
+            } else {
+                EncryptedOption::some(ptr, keys.enc_true())
+            }
+        })
+        .collect();
+
+    let picked = EncryptedOption::gather(&slots, &keys.enc_u32(2)).unwrap();
+    assert!(keys.dec_bool(&picked.is_some));
+    assert_eq!(keys.dec_u64(&picked.value.0), 102);
+
+    let invalid_slot = EncryptedOption::gather(&slots, &keys.enc_u32(3)).unwrap();
+    assert!(!keys.dec_bool(&invalid_slot.is_some));
+    let out_of_range = EncryptedOption::gather(&slots, &keys.enc_u32(9)).unwrap();
+    assert!(!keys.dec_bool(&out_of_range.is_some));
+
+    let replacement = EncryptedOption::some(EncryptedPtr(keys.enc_u64(777)), keys.enc_true());
+    EncryptedOption::scatter(&mut slots, &keys.enc_u32(3), &replacement);
+    assert!(keys.dec_bool(&slots[3].is_some));
+    assert_eq!(keys.dec_u64(&slots[3].value.0), 777);
+    assert_eq!(keys.dec_u64(&slots[0].value.0), 100);
+
+    let cleared = EncryptedOption::none(EncryptedPtr(keys.enc_u64(0)), keys.enc_false());
+    EncryptedOption::scatter(&mut slots, &keys.enc_u32(0), &cleared);
+    assert!(!keys.dec_bool(&slots[0].is_some));
+    assert!(keys.dec_bool(&slots[1].is_some));
+
+    // an out-of-range index leaves every slot's payload and flag as it was.
+    let decrypt = |slots: &[EncryptedOption<EncryptedPtr>]| -> Vec<(u64, bool)> {
+        slots
+            .iter()
+            .map(|slot| (keys.dec_u64(&slot.value.0), keys.dec_bool(&slot.is_some)))
+            .collect()
+    };
+    let before = decrypt(&slots);
+    EncryptedOption::scatter(&mut slots, &keys.enc_u32(9), &replacement);
+    assert_eq!(decrypt(&slots), before);
+    assert_eq!(before, [(0, false), (101, true), (102, true), (777, true)]);
+}
+
+#[test]
+fn arena_aligned_allocation_rounds_size_when_enabled() {
+    use cryptmalloc::{Arena, Keys};
+
+    let keys = Keys::new();
+    let run = |size_rounding: bool| {
+        // unaligned start so the first request already pays padding.
+        let mut arena = Arena::new(
+            keys.enc_u64(1000),
+            keys.enc_u64(4096),
+            keys.server_key(),
+            keys.enc_false(),
+            keys.enc_zero_u64(),
+        );
+        arena.set_size_rounding(size_rounding);
+        let ptrs: Vec<u64> = (0..3)
+            .map(|_| {
+                let granted = arena.allocate_aligned(keys.enc_u64(100), 64);
+                assert!(keys.dec_bool(&granted.is_some));
+                keys.dec_u64(&granted.value.0)
+            })
+            .collect();
+        (ptrs, keys.dec_u64(arena.cursor()))
+    };
+
+    let (rounded, rounded_cursor) = run(true);
+    assert_eq!(rounded, vec![1024, 1152, 1280]);
+    assert!(rounded.iter().all(|ptr| ptr % 64 == 0));
+    assert_eq!(rounded_cursor, 1408);
+
+    // without rounding the cursor trails each block, so every request re-pads.
+    let (unrounded, unrounded_cursor) = run(false);
+    assert!(unrounded.iter().all(|ptr| ptr % 64 == 0));
+    assert_eq!(unrounded, vec![1024, 1152, 1280]);
+    assert_eq!(unrounded_cursor, 1380);
+
+    let arena = Arena::new(
+        keys.enc_u64(0),
+        keys.enc_u64(64),
+        keys.server_key(),
+        keys.enc_false(),
+        keys.enc_zero_u64(),
+    );
+    assert_eq!(
+        keys.dec_u64(&arena.rounded_size(&keys.enc_u64(100), 64)),
+        128
+    );
+}
+
+#[test]
+fn encrypted_bitset_operations() {
+    use cryptmalloc::{BitsetOp, EncryptedBitset, Keys};
+
+    let keys = Keys::new();
+    let decrypt = |set: &EncryptedBitset| -> Vec<bool> {
+        set.bits().iter().map(|bit| keys.dec_bool(bit)).collect()
+    };
+
+    let mut bits = EncryptedBitset::new(&keys, 4);
+    bits.set_if(&keys.enc_u32(1), &keys.enc_true());
+    bits.set_if(&keys.enc_u32(2), &keys.enc_false());
+    bits.set_if(&keys.enc_u32(0), &keys.enc_true());
+    bits.set_if(&keys.enc_u32(7), &keys.enc_true());
+    assert_eq!(decrypt(&bits), vec![true, true, false, false]);
+    assert_eq!(keys.dec_u32(&bits.popcount()), 2);
+    assert!(keys.dec_bool(&bits.test(&keys.enc_u32(1))));
+    assert!(!keys.dec_bool(&bits.test(&keys.enc_u32(3))));
+
+    let (index, found) = bits.first_clear();
+    assert!(keys.dec_bool(&found));
+    assert_eq!(keys.dec_u32(&index), 2);
+
+    bits.clear_if(&keys.enc_u32(0), &keys.enc_true());
+    assert_eq!(decrypt(&bits), vec![false, true, false, false]);
+
+    let mut mask = EncryptedBitset::new(&keys, 4);
+    mask.set_if(&keys.enc_u32(1), &keys.enc_true());
+    mask.set_if(&keys.enc_u32(3), &keys.enc_true());
+    let mut combined = bits.clone();
+    combined.apply_mask(&mask, BitsetOp::Or);
+    assert_eq!(decrypt(&combined), vec![false, true, false, true]);
+    combined.apply_mask(&bits, BitsetOp::Xor);
+    assert_eq!(decrypt(&combined), vec![false, false, false, true]);
+    combined.apply_mask(&mask, BitsetOp::And);
+    assert_eq!(decrypt(&combined), vec![false, false, false, true]);
+    combined.apply_mask(&mask, BitsetOp::AndNot);
+    assert_eq!(decrypt(&combined), vec![false; 4]);
+
+    mask.apply_mask(&mask.clone(), BitsetOp::Xor);
+    for i in 0..4 {
+        mask.set_if(&keys.enc_u32(i), &keys.enc_true());
+    }
+    let (_, found) = mask.first_clear();
+    assert!(!keys.dec_bool(&found));
+    mask.clear_all();
+    assert_eq!(keys.dec_u32(&mask.popcount()), 0);
+}
+
+#[test]
+fn slab_bitmap_tracks_used_count() {
+    use cryptmalloc::{EncryptedPtr, Keys, SlabClass};
+
+    let keys = Keys::new();
+    let mut slab = SlabClass::new(
+        16,
+        3,
+        keys.enc_u64(64),
+        keys.server_key(),
+        keys.enc_false(),
+        keys.enc_true(),
+        keys.enc_zero_u32(),
+        keys.enc_zero_u64(),
+        keys.build_enc_indices_u32(3),
+        keys.build_enc_offsets_u64(3, 16),
+    );
+
+    let first = slab.allocate_masked(keys.enc_true());
+    let second = slab.allocate_masked(keys.enc_true());
+    assert_eq!(keys.dec_u64(&first.value.0), 64);
+    assert_eq!(keys.dec_u64(&second.value.0), 80);
+    assert_eq!(keys.dec_u32(&slab.used_count()), 2);
+
+    assert!(keys.dec_bool(&slab.free(&EncryptedPtr(keys.enc_u64(64)))));
+    let bitmap: Vec<bool> = slab.bitmap().iter().map(|b| keys.dec_bool(b)).collect();
+    assert_eq!(bitmap, vec![false, true, false]);
+    let (index, found) = slab.bitset().first_clear();
+    assert!(keys.dec_bool(&found));
+    assert_eq!(keys.dec_u32(&index), 0);
+
+    slab.reset();
+    assert_eq!(keys.dec_u32(&slab.used_count()), 0);
+}
+
+#[test]
+fn unified_free_routes_slab_and_arena_pointers() {
+    use cryptmalloc::{layout::Layout, EncryptedPtr, Keys};
+
+    // one block per tier keeps the full routing path cheap enough to run.
+    let tiers = [(16, 1), (32, 1), (64, 1), (128, 1), (256, 1)];
+    let keys = Keys::new();
+    let mut alloc = CryptMalloc::builder(1024)
+        .keys(keys.clone())
+        .layout(Layout::contiguous(&tiers, 1024))
+        .build()
+        .unwrap();
+
+    let slab_ptr = alloc.allocate(keys.enc_u64(16));
+    let arena_ptr = alloc.allocate(keys.enc_u64(300));
+    assert!(keys.dec_bool(&slab_ptr.is_some));
+    assert!(keys.dec_bool(&arena_ptr.is_some));
+    assert_eq!(keys.dec_u64(&slab_ptr.value.0), 0);
+    assert_eq!(keys.dec_u64(&arena_ptr.value.0), 496);
+
+    let freed_arena = alloc.free(&EncryptedPtr(arena_ptr.value.0.clone()));
+    assert!(!keys.dec_bool(&freed_arena));
+    assert!(keys.dec_bool(&alloc.slabs()[0].bitmap()[0]));
+
+    let freed_slab = alloc.free(&EncryptedPtr(slab_ptr.value.0.clone()));
+    assert!(keys.dec_bool(&freed_slab));
+    assert!(!keys.dec_bool(&alloc.slabs()[0].bitmap()[0]));
+}
+
+#[test]
+fn metrics_text_is_well_formed_and_counts_operations() {
+    use cryptmalloc::{layout::Layout, EncryptedPtr, Keys, MetricsRegistry};
+
+    fn check_exposition(text: &str) -> Vec<String> {
+        let valid_name = |name: &str| {
+            !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+                && !name.starts_with(|c: char| c.is_ascii_digit())
+        };
+        let mut samples = Vec::new();
+        for line in text.lines() {
+            if let Some(rest) = line.strip_prefix("# HELP ") {
+                assert!(valid_name(rest.split(' ').next().unwrap()), "{line}");
+            } else if let Some(rest) = line.strip_prefix("# TYPE ") {
+                let (name, kind) = rest.split_once(' ').unwrap();
+                assert!(valid_name(name), "{line}");
+                assert!(kind == "counter" || kind == "gauge", "{line}");
+            } else {
+                let (series, value) = line.rsplit_once(' ').unwrap();
+                assert!(value.parse::<f64>().is_ok(), "{line}");
+                let (name, labels) = series.split_once('{').unwrap();
+                assert!(valid_name(name), "{line}");
+                let labels = labels.strip_suffix('}').unwrap();
+                for pair in labels.split(',') {
+                    let (key, quoted) = pair.split_once('=').unwrap();
+                    assert!(valid_name(key), "{line}");
+                    assert!(quoted.starts_with('"') && quoted.ends_with('"'), "{line}");
+                }
+                samples.push(series.to_string());
+            }
+        }
+        samples
+    }
+    fn sample(text: &str, series: &str) -> f64 {
+        text.lines()
+            .find_map(|line| line.strip_prefix(series)?.strip_prefix(' '))
+            .unwrap()
+            .parse()
+            .unwrap()
+    }
+
+    let tiers = [(16, 1), (32, 1), (64, 1), (128, 1), (256, 1)];
+    let keys = Keys::new();
+    let build = |name: &str| {
+        CryptMalloc::builder(512)
+            .keys(keys.clone())
+            .layout(Layout::contiguous(&tiers, 512))
+            .name(name)
+            .build()
+            .unwrap()
+    };
+    let mut primary = build("primary");
+    let replica = build("replica");
+
+    let before = primary.metrics_text();
+    let series_before = check_exposition(&before);
+    assert_eq!(
+        sample(&before, "cryptmalloc_frees_total{allocator=\"primary\"}"),
+        0.0
+    );
+    assert_eq!(
+        sample(&before, "cryptmalloc_arena_bytes{allocator=\"primary\"}"),
+        512.0
+    );
+
+    primary.reset();
+    primary.reset();
+    let _ = primary.free(&EncryptedPtr(keys.enc_u64(0)));
+
+    let after = primary.metrics_text();
+    assert_eq!(check_exposition(&after), series_before);
+    assert_eq!(
+        sample(&after, "cryptmalloc_resets_total{allocator=\"primary\"}"),
+        2.0
+    );
+    assert_eq!(
+        sample(&after, "cryptmalloc_frees_total{allocator=\"primary\"}"),
+        1.0
+    );
+    assert!(
+        sample(
+            &after,
+            "cryptmalloc_free_seconds_total{allocator=\"primary\"}"
+        ) > 0.0
+    );
+
+    let mut registry = MetricsRegistry::new();
+    registry.register(&primary).register(&replica);
+    let combined = registry.render();
+    let series = check_exposition(&combined);
+    assert_eq!(series.len(), 2 * series_before.len());
+    assert_eq!(
+        combined
+            .matches("# TYPE cryptmalloc_frees_total counter")
+            .count(),
+        1
+    );
+    assert_eq!(
+        sample(&combined, "cryptmalloc_frees_total{allocator=\"replica\"}"),
+        0.0
+    );
+}
+
+#[test]
+fn ring_arena_wraps_and_counts_epochs() {
+    use cryptmalloc::{layout::Layout, Keys, RingArena};
+
+    let keys = Keys::new();
+    let mut ring = RingArena::new(
+        keys.enc_u64(100),
+        keys.enc_u64(400),
+        keys.server_key(),
+        keys.enc_false(),
+        keys.enc_zero_u64(),
+    );
+    let mut step = |size: u64| {
+        let granted = ring.allocate(keys.enc_u64(size));
+        (
+            keys.dec_bool(&granted.ptr.is_some)
+                .then(|| keys.dec_u64(&granted.ptr.value.0)),
+            keys.dec_bool(&granted.wrapped),
+        )
+    };
+    assert_eq!(step(120), (Some(100), false));
+    assert_eq!(step(120), (Some(220), false));
+    assert_eq!(step(120), (Some(100), true));
+    // larger than the whole ring: fails without wrapping.
+    assert_eq!(step(500), (None, false));
+    assert_eq!(keys.dec_u64(ring.epoch()), 1);
+    assert_eq!(keys.dec_u64(ring.cursor()), 220);
+    assert!(keys.dec_u64(ring.cursor()) <= keys.dec_u64(ring.end()));
+
+    let tiers = [(16, 1), (32, 1), (64, 1), (128, 1), (256, 1)];
+    let mut alloc = CryptMalloc::builder(512)
+        .keys(keys.clone())
+        .layout(Layout::contiguous(&tiers, 512))
+        .ring_arena(true)
+        .build()
+        .unwrap();
+    let first = alloc.allocate(keys.enc_u64(300));
+    let second = alloc.allocate(keys.enc_u64(300));
+    assert!(keys.dec_bool(&second.is_some));
+    assert_eq!(keys.dec_u64(&first.value.0), 496);
+    assert_eq!(keys.dec_u64(&second.value.0), 496);
+    assert_eq!(keys.dec_u64(alloc.ring_arena().unwrap().epoch()), 1);
+}
+
+#[test]
+fn allocate_audited_witnesses_the_routing_decision() {
+    use cryptmalloc::{layout::Layout, Keys};
+
+    let tiers = [(16, 1), (32, 1), (64, 1), (128, 1), (256, 1)];
+    let keys = Keys::new();
+    let mut alloc = CryptMalloc::builder(2048)
+        .keys(keys.clone())
+        .layout(Layout::contiguous(&tiers, 2048))
+        .build()
+        .unwrap();
+
+    let cases: [(u64, Option<usize>); 3] = [(10, Some(0)), (100, Some(3)), (1000, None)];
+    for (size, expected_tier) in cases {
+        let (granted, audit) = alloc.allocate_audited(keys.enc_u64(size));
+        assert!(keys.dec_bool(&granted.is_some));
+        let tier_flags: Vec<bool> = audit.tiers.iter().map(|f| keys.dec_bool(f)).collect();
+        let expected: Vec<bool> = (0..tiers.len()).map(|t| Some(t) == expected_tier).collect();
+        assert_eq!(tier_flags, expected, "size {size}");
+        assert_eq!(keys.dec_bool(&audit.arena), expected_tier.is_none());
+        assert!(keys.dec_bool(&audit.assert_one_hot()));
+    }
+}
+
+#[test]
+fn prepared_allocator_builds_tiers_lazily_once() {
+    use cryptmalloc::{layout::Layout, Keys};
+
+    let tiers = [(16, 2), (32, 2), (64, 1), (128, 1), (256, 1)];
+    let keys = Keys::new();
+    let prepared = CryptMalloc::builder(512)
+        .keys(keys.clone())
+        .layout(Layout::contiguous(&tiers, 512))
+        .prepare()
+        .unwrap();
+    // nothing has been encrypted yet beyond key generation.
+    assert_eq!(prepared.construction_progress(), vec![false; 5]);
+    assert_eq!(prepared.keys().const_cache_misses(), 0);
+
+    let addresses: Vec<usize> = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..4)
+            .map(|_| scope.spawn(|| prepared.ensure_tier(1) as *const _ as usize))
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+    assert!(addresses.windows(2).all(|pair| pair[0] == pair[1]));
+    assert_eq!(
+        prepared.construction_progress(),
+        vec![false, true, false, false, false]
+    );
+
+    let mut alloc = prepared.finish();
+    let granted = alloc.allocate(keys.enc_u64(20));
+    assert!(keys.dec_bool(&granted.is_some));
+    assert_eq!(keys.dec_u64(&granted.value.0), 32);
+}
+
+#[test]
+fn routing_boundaries_with_plaintext_bounds() {
+    use cryptmalloc::{layout::Layout, Keys};
+
+    let tiers = [(16, 2), (32, 1), (64, 1), (128, 1), (256, 1)];
+    let keys = Keys::new();
+    let mut alloc = CryptMalloc::builder(1024)
+        .keys(keys.clone())
+        .layout(Layout::contiguous(&tiers, 1024))
+        .build()
+        .unwrap();
+
+    // (size, serving tier or None for the arena)
+    let cases: [(u64, Option<usize>); 5] = [
+        (0, Some(0)),
+        (16, Some(0)),
+        (17, Some(1)),
+        (256, Some(4)),
+        (257, None),
+    ];
+    for (size, expected_tier) in cases {
+        let (granted, audit) = alloc.allocate_audited(keys.enc_u64(size));
+        assert!(keys.dec_bool(&granted.is_some), "size {size}");
+        let served = audit.tiers.iter().position(|flag| keys.dec_bool(flag));
+        assert_eq!(served, expected_tier, "size {size}");
+        assert_eq!(keys.dec_bool(&audit.arena), expected_tier.is_none());
+    }
+}
+
+#[test]
+fn encrypted_heap_extracts_in_priority_order() {
+    use cryptmalloc::{EncryptedHeap, Keys};
+
+    let keys = Keys::new();
+    let mut heap = EncryptedHeap::new(&keys, 4);
+    for (priority, payload) in [(30u32, 300u64), (10, 100), (20, 200)] {
+        let placed = heap.insert(
+            &keys.enc_u32(priority),
+            &keys.enc_u64(payload),
+            &keys.enc_true(),
+        );
+        assert!(keys.dec_bool(&placed));
+    }
+    let skipped = heap.insert(&keys.enc_u32(1), &keys.enc_u64(1), &keys.enc_false());
+    assert!(!keys.dec_bool(&skipped));
+    assert_eq!(keys.dec_u32(&heap.len()), 3);
+
+    for (priority, payload) in [(10u32, 100u64), (20, 200)] {
+        let min = heap.extract_min(&keys.enc_true());
+        assert!(keys.dec_bool(&min.is_some));
+        assert_eq!(keys.dec_u32(&min.value.0), priority);
+        assert_eq!(keys.dec_u64(&min.value.1), payload);
+    }
+
+    let held = heap.extract_min(&keys.enc_false());
+    assert!(!keys.dec_bool(&held.is_some));
+    let peeked = heap.peek_min();
+    assert!(keys.dec_bool(&peeked.is_some));
+    assert_eq!(keys.dec_u32(&peeked.value.0), 30);
+    assert_eq!(keys.dec_u32(&heap.len()), 1);
+
+    let last = heap.extract_min(&keys.enc_true());
+    assert!(keys.dec_bool(&last.is_some));
+    assert_eq!(keys.dec_u64(&last.value.1), 300);
+
+    // an empty heap yields nothing, whether extracted from or peeked at.
+    let drained = heap.extract_min(&keys.enc_true());
+    assert!(!keys.dec_bool(&drained.is_some));
+    assert!(!keys.dec_bool(&heap.peek_min().is_some));
+    assert_eq!(keys.dec_u32(&heap.len()), 0);
+}
+
+#[test]
+fn bulk_encryption_matches_single_encryption() {
+    use cryptmalloc::Keys;
+
+    let keys = Keys::new();
+    let values: Vec<u32> = (0..64).map(|i| i * 7).collect();
+    let bulk = keys.enc_u32_many(&values);
+    let single: Vec<_> = values.iter().map(|&v| keys.enc_u32(v)).collect();
+    let bulk_plain: Vec<u32> = bulk.iter().map(|ct| keys.dec_u32(ct)).collect();
+    let single_plain: Vec<u32> = single.iter().map(|ct| keys.dec_u32(ct)).collect();
+    assert_eq!(bulk_plain, values);
+    assert_eq!(bulk_plain, single_plain);
+
+    let wide = keys.enc_u64_many(&[0, u64::MAX, 1 << 40]);
+    assert_eq!(keys.dec_u64(&wide[1]), u64::MAX);
+    assert_eq!(keys.dec_u64(&wide[2]), 1 << 40);
+    let flags = keys.enc_bool_many(&[true, false]);
+    assert!(keys.dec_bool(&flags[0]) && !keys.dec_bool(&flags[1]));
+
+    let indices = keys.build_enc_indices_u32(256);
+    assert!(indices
+        .iter()
+        .enumerate()
+        .all(|(i, ct)| keys.dec_u32(ct) == i as u32));
+    let again = keys.build_enc_indices_u32(256);
+    assert_eq!(keys.dec_u32(&again[255]), 255);
+    assert_eq!(keys.const_cache_misses(), 0);
+    assert_eq!(keys.const_cache_hits(), 0);
+}
+
+#[test]
+fn trusted_report_decrypts_allocator_state() {
+    use cryptmalloc::{layout::Layout, report::ReportError, Keys};
+
+    let tiers = [(16, 2), (32, 1), (64, 1), (128, 1), (256, 1)];
+    let keys = Keys::new();
+    let mut alloc = CryptMalloc::builder(1024)
+        .keys(keys.clone())
+        .layout(Layout::contiguous(&tiers, 1024))
+        .name("incident")
+        .build()
+        .unwrap();
+    let granted = alloc.allocate(keys.enc_u64(300));
+    assert!(keys.dec_bool(&granted.is_some));
+
+    let report = alloc.report(&keys).unwrap();
+    assert_eq!(report.name, "incident");
+    assert_eq!(report.metrics.allocations, 1);
+    assert_eq!(report.tiers.len(), 5);
+    assert_eq!(report.tiers[0].allocated, vec![Some(false), Some(false)]);
+    assert_eq!(report.tiers[1].block_size, Some(32));
+    assert!(report.tiers.iter().all(|tier| tier.used_blocks == 0));
+    assert_eq!(report.arena.start, Some(512));
+    assert_eq!(report.arena.cursor, Some(812));
+    assert_eq!(report.arena.used_bytes, Some(300));
+    assert_eq!(report.arena.ring_epoch, None);
+
+    let json = report.to_json();
+    assert!(json.starts_with("{\"name\":\"incident\",\"layout\":{"));
+    assert!(json.contains("\"allocated\":[false,false]"));
+    assert!(json.contains(
+        "\"arena\":{\"start\":512,\"end\":1536,\"cursor\":812,\"used_bytes\":300,\"ring_epoch\":null}"
+    ));
+    assert_eq!(json.matches('{').count(), json.matches('}').count());
+    assert_eq!(json.matches('[').count(), json.matches(']').count());
+    // collecting a report never mutates the allocator.
+    assert_eq!(alloc.report(&keys).unwrap(), report);
+
+    // server-only keys cannot decrypt anything, which is reported once instead of panicking.
+    let server_only = Keys::server_only(keys.server_key());
+    assert_eq!(alloc.report(&server_only), Err(ReportError::ServerOnlyKeys));
+
+    // a field that failed to decrypt is marked rather than dropping the report.
+    let mut partial = report.clone();
+    partial.tiers[0].allocated[1] = None;
+    partial.arena.cursor = None;
+    partial.arena.used_bytes = None;
+    let json = partial.to_json();
+    assert!(json.contains("\"allocated\":[false,\"error\"]"));
+    assert!(json.contains("\"cursor\":\"error\",\"used_bytes\":\"error\""));
+}
+
+#[test]
+fn slab_write_back_variants_agree() {
+    use cryptmalloc::{IndexWidth, Keys, SlabClass, SlabOptions, WriteBack};
+
+    let keys = Keys::new();
+    let variants = [
+        SlabOptions::default(),
+        SlabOptions {
+            index_width: IndexWidth::Narrow,
+            write_back: WriteBack::IndexScan,
+        },
+        SlabOptions {
+            index_width: IndexWidth::U32,
+            write_back: WriteBack::RetainedFlags,
+        },
+    ];
+
+    // every variant must produce the default two-pass write-back's grants and bitmap.
+    for options in variants {
+        let mut slab = SlabClass::new(
+            16,
+            3,
+            keys.enc_u64(64),
+            keys.server_key(),
+            keys.enc_false(),
+            keys.enc_true(),
+            keys.enc_zero_u32(),
+            keys.enc_zero_u64(),
+            keys.build_enc_indices_u32(3),
+            keys.build_enc_offsets_u64(3, 16),
+        )
+        .with_options(&keys, options);
+        assert_eq!(slab.options(), options);
+
+        let mut grants = Vec::new();
+        for mask in [keys.enc_true(), keys.enc_false(), keys.enc_true()] {
+            let granted = slab.allocate_masked(mask);
+            grants.push((
+                keys.dec_bool(&granted.is_some),
+                keys.dec_u64(&granted.value.0),
+            ));
+        }
+        let bitmap: Vec<bool> = slab.bitmap().iter().map(|b| keys.dec_bool(b)).collect();
+
+        assert_eq!(
+            grants,
+            vec![(true, 64), (false, 0), (true, 80)],
+            "{options:?}"
+        );
+        assert_eq!(bitmap, vec![true, true, false], "{options:?}");
+    }
+}
+
+#[test]
+fn size_blinder_pads_to_buckets() {
+    use cryptmalloc::{layout::Layout, Keys, SizeBlinder};
+
+    let keys = Keys::new();
+    let blinder = SizeBlinder::new(&[512, 32, 128, 32]);
+    assert_eq!(blinder.buckets(), &[32, 128, 512]);
+
+    // (actual, expected bucket): zero, exactly at a bucket, one over, and past the largest bucket.
+    let cases = [
+        (0, 32),
+        (32, 32),
+        (33, 128),
+        (128, 128),
+        (129, 512),
+        (600, 600),
+    ];
+    for (actual, bucket) in cases {
+        assert_eq!(blinder.bucket_for(actual), bucket);
+        assert_eq!(keys.dec_u64(&blinder.blind(&keys, actual)), bucket);
+        let blinded = blinder.blind_cipher(&keys.enc_u64(actual));
+        assert_eq!(keys.dec_u64(&blinded), bucket, "size {actual}");
+    }
+
+    // a 20-byte request padded to 64 lands in the 64-byte tier instead of the 32-byte one.
+    let tiers = [(16, 1), (32, 1), (64, 1), (128, 1), (256, 1)];
+    let mut alloc = CryptMalloc::builder(512)
+        .keys(keys.clone())
+        .layout(Layout::contiguous(&tiers, 512))
+        .size_blinder(SizeBlinder::new(&[64, 256]))
+        .build()
+        .unwrap();
+    // the rounder buckets first too, so its prediction matches where the request goes.
+    let enc_20 = keys.enc_u64(20);
+    assert_eq!(keys.dec_u32(&alloc.rounder().tier_index(&enc_20)), 2);
+    assert_eq!(keys.dec_u64(&alloc.rounder().padding_for(&enc_20)), 44);
+    let granted = alloc.allocate(keys.enc_u64(20));
+    assert!(keys.dec_bool(&granted.is_some));
+    assert_eq!(keys.dec_u64(&granted.value.0), 48);
+}
+
+#[test]
+fn capacity_proofs_answer_threshold_and_free_block_queries() {
+    use cryptmalloc::{layout::Layout, Arena, Keys, SlabClass};
+
+    let keys = Keys::new();
+    let mut arena = Arena::new(
+        keys.enc_u64(100),
+        keys.enc_u64(200),
+        keys.server_key(),
+        keys.enc_false(),
+        keys.enc_zero_u64(),
+    );
+    let _ = arena.allocate(keys.enc_u64(30));
+    assert_eq!(keys.dec_u64(&arena.remaining()), 70);
+    let answers: Vec<bool> = [69, 70, 71]
+        .into_iter()
+        .map(|threshold| keys.dec_bool(&arena.capacity_proof(keys.enc_u64(threshold))))
+        .collect();
+    assert_eq!(answers, [true, true, false]);
+
+    let mut slab = SlabClass::new(
+        16,
+        1,
+        keys.enc_u64(0),
+        keys.server_key(),
+        keys.enc_false(),
+        keys.enc_true(),
+        keys.enc_zero_u32(),
+        keys.enc_zero_u64(),
+        keys.build_enc_indices_u32(1),
+        keys.build_enc_offsets_u64(1, 16),
+    );
+    assert!(keys.dec_bool(&slab.has_free()));
+    let _ = slab.allocate_masked(keys.enc_true());
+    assert!(!keys.dec_bool(&slab.has_free()));
+
+    let tiers = [(16, 1), (32, 1), (64, 1), (128, 1), (256, 1)];
+    let alloc = CryptMalloc::builder(512)
+        .keys(keys.clone())
+        .layout(Layout::contiguous(&tiers, 512))
+        .build()
+        .unwrap();
+    let proofs = alloc.capacity_proofs(&[keys.enc_u64(512), keys.enc_u64(513)]);
+    let proofs: Vec<bool> = proofs.iter().map(|proof| keys.dec_bool(proof)).collect();
+    assert_eq!(proofs, [true, false, true, true, true, true, true]);
+}
+
+#[test]
+fn encrypted_option_fold_matches_combine_with_chain() {
+    use cryptmalloc::{EncryptedOption, Keys};
+
+    let keys = Keys::new();
+    // every some/none pattern over three options carrying distinct payloads.
+    for pattern in 0u32..8 {
+        let options: Vec<EncryptedOption<_>> = (0..3u32)
+            .map(|i| EncryptedOption {
+                value: keys.enc_u32(i + 1),
+                is_some: if pattern & (1 << i) != 0 {
+                    keys.enc_true()
+                } else {
+                    keys.enc_false()
+                },
+            })
+            .collect();
+        let init = EncryptedOption::none(keys.enc_zero_u32(), keys.enc_false());
+
+        let mut chained = init.clone();
+        for option in &options {
+            chained = chained.combine_with(option);
+        }
+        let folded = EncryptedOption::fold(&options, init);
+
+        let decrypt = |option: &EncryptedOption<_>| {
+            (keys.dec_bool(&option.is_some), keys.dec_u32(&option.value))
+        };
+        let last_some = (0..3u32).rev().find(|i| pattern & (1 << i) != 0);
+        let expected = (last_some.is_some(), last_some.map_or(0, |i| i + 1));
+        assert_eq!(decrypt(&chained), expected, "pattern {pattern:03b}");
+        assert_eq!(decrypt(&folded), expected, "pattern {pattern:03b}");
+    }
+}
+
+#[test]
+fn evm_try_new_rejects_invalid_configurations() {
+    use cryptmalloc::evm::{EvmError, MAX_MEMORY_SLOTS};
+    use cryptmalloc::{Keys, EVM};
+
+    let keys = Keys::new();
+    let build = |program: Vec<u8>, memory_size: usize, pc_values: usize| {
+        EVM::try_new(
+            program,
+            memory_size,
+            keys.server_key(),
+            keys.enc_false(),
+            keys.enc_true(),
+            keys.enc_zero_u32(),
+            keys.enc_zero_u64(),
+            keys.enc_u32(1),
+            keys.build_enc_indices_u32(pc_values),
+        )
+    };
+
+    assert_eq!(
+        build(vec![0x00, 0x00], 4, 1).unwrap_err(),
+        EvmError::PcTableTooShort {
+            program_len: 2,
+            pc_values: 1
+        }
+    );
+    assert_eq!(
+        build(vec![0x00], MAX_MEMORY_SLOTS + 1, 1).unwrap_err(),
+        EvmError::MemoryTooLarge {
+            requested: MAX_MEMORY_SLOTS + 1,
+            max: MAX_MEMORY_SLOTS
+        }
+    );
+
+    // a thread that never installed a server key can still run it: every EVM method reinstalls its own key, and bounds are scalar comparisons rather than encrypted constants.
+    let mut evm = build(vec![0x00], 4, 1).unwrap();
+    let seeded = keys.enc_u64(7);
+    let stack_len = std::thread::spawn(move || {
+        evm.push_input(seeded);
+        evm.stack_len().clone()
+    })
+    .join()
+    .unwrap();
+    assert_eq!(keys.dec_u32(&stack_len), 1);
+}
+
+#[test]
+fn confidential_tiers_route_on_encrypted_bounds() {
+    use cryptmalloc::{layout::LayoutError, Keys, SlabClass};
+
+    let keys = Keys::new();
+    let tiers = [(16, 1), (32, 1), (64, 1), (128, 1), (256, 1)];
+    let confidential_slabs = || {
+        let mut base = 0u64;
+        tiers
+            .iter()
+            .map(|&(block_size, num_blocks)| {
+                let slab = SlabClass::new_confidential(
+                    keys.enc_u64(block_size as u64),
+                    num_blocks,
+                    keys.enc_u64(base),
+                    keys.server_key(),
+                    keys.enc_false(),
+                    keys.enc_true(),
+                    keys.enc_zero_u32(),
+                    keys.enc_zero_u64(),
+                    keys.build_enc_indices_u32(num_blocks),
+                    keys.build_enc_offsets_u64(num_blocks, block_size),
+                );
+                base += (block_size * num_blocks) as u64;
+                slab
+            })
+            .collect::<Vec<_>>()
+    };
+    let (arena_start, arena_end) = (496, 1008);
+
+    let mut slabs = confidential_slabs();
+    assert!(slabs.iter().all(SlabClass::is_confidential));
+    let debug = format!("{:?}", slabs[1]);
+    assert!(debug.contains("block_size: \"<ciphertext>\""), "{debug}");
+    assert_eq!(keys.dec_u64(slabs[1].enc_block_size().unwrap()), 32);
+
+    slabs[2] = SlabClass::new(
+        64,
+        1,
+        keys.enc_u64(48),
+        keys.server_key(),
+        keys.enc_false(),
+        keys.enc_true(),
+        keys.enc_zero_u32(),
+        keys.enc_zero_u64(),
+        keys.build_enc_indices_u32(1),
+        keys.build_enc_offsets_u64(1, 64),
+    );
+    let err = CryptMalloc::from_confidential_tiers(
+        keys.clone(),
+        slabs,
+        keys.enc_u64(arena_start),
+        keys.enc_u64(arena_end),
+    )
+    .unwrap_err();
+    assert_eq!(err, LayoutError::PlaintextTier { tier: 2 });
+
+    let mut alloc = CryptMalloc::from_confidential_tiers(
+        keys.clone(),
+        confidential_slabs(),
+        keys.enc_u64(arena_start),
+        keys.enc_u64(arena_end),
+    )
+    .unwrap();
+    assert!(alloc.layout().tiers.is_empty());
+    assert!(!alloc.metrics_text().contains("block_size="));
+
+    let small = alloc.allocate(keys.enc_u64(20));
+    assert!(keys.dec_bool(&small.is_some));
+    assert_eq!(keys.dec_u64(&small.value.0), 16);
+    let large = alloc.allocate(keys.enc_u64(300));
+    assert!(keys.dec_bool(&large.is_some));
+    assert_eq!(keys.dec_u64(&large.value.0), arena_start);
+
+    let report = alloc.report(&keys).unwrap();
+    let sizes: Vec<usize> = report
+        .tiers
+        .iter()
+        .filter_map(|tier| tier.block_size)
+        .collect();
+    assert_eq!(sizes, [16, 32, 64, 128, 256]);
+    assert_eq!(report.tiers[1].allocated, vec![Some(true)]);
+}
+
+#[test]
+fn crate_error_converts_and_categorizes_subsystem_errors() {
+    use cryptmalloc::envelope::EnvelopeError;
+    use cryptmalloc::evm::EvmError;
+    use cryptmalloc::layout::{Layout, LayoutError};
+    use cryptmalloc::protocol::{decode, ProtocolError, Request};
+    use cryptmalloc::report::ReportError;
+    use cryptmalloc::{Category, CryptmallocError};
+    use std::error::Error;
+
+    fn lift<E>(result: Result<(), E>) -> Result<(), CryptmallocError>
+    where
+        CryptmallocError: From<E>,
+    {
+        Ok(result?)
+    }
+
+    let cases: Vec<(CryptmallocError, Category)> = vec![
+        (
+            LayoutError::TierCount {
+                found: 4,
+                expected: 5,
+            }
+            .into(),
+            Category::Config,
+        ),
+        (
+            LayoutError::PlaintextTier { tier: 0 }.into(),
+            Category::Usage,
+        ),
+        (EnvelopeError::ChecksumMismatch.into(), Category::Integrity),
+        (
+            EnvelopeError::UnsupportedVersion {
+                found: 9,
+                current: 1,
+            }
+            .into(),
+            Category::Config,
+        ),
+        (ProtocolError::Truncated.into(), Category::Integrity),
+        (
+            ProtocolError::Encode("size limit".into()).into(),
+            Category::Capacity,
+        ),
+        (
+            EvmError::MemoryTooLarge {
+                requested: 5000,
+                max: 4096,
+            }
+            .into(),
+            Category::Capacity,
+        ),
+        (
+            EvmError::PcTableTooShort {
+                program_len: 2,
+                pc_values: 1,
+            }
+            .into(),
+            Category::Usage,
+        ),
+        (ReportError::ServerOnlyKeys.into(), Category::Usage),
+    ];
+    for (err, category) in &cases {
+        assert_eq!(err.category(), *category, "{err}");
+    }
+
+    // the subsystem error stays reachable through source() for callers that need the detail.
+    let err = lift(Layout::from_bytes(b"junk").map(drop)).unwrap_err();
+    assert_eq!(err.category(), Category::Integrity);
+    assert_eq!(
+        err.source().unwrap().downcast_ref::<EnvelopeError>(),
+        Some(&EnvelopeError::Truncated)
+    );
+    assert_eq!(
+        err.to_string(),
+        format!("envelope: {}", EnvelopeError::Truncated)
+    );
+
+    let err = lift(decode::<Request>(&[1]).map(drop)).unwrap_err();
+    assert!(matches!(
+        err.source().unwrap().downcast_ref::<ProtocolError>(),
+        Some(ProtocolError::Truncated)
+    ));
+
+    let err = lift(
+        CryptMalloc::builder(64)
+            .layout(Layout::contiguous(&[(16, 1)], 64))
+            .build()
+            .map(drop),
+    )
+    .unwrap_err();
+    assert!(matches!(
+        err,
+        CryptmallocError::Layout(LayoutError::TierCount { found: 1, .. })
+    ));
+    assert_eq!(err.category(), Category::Config);
+}
+
+#[test]
+fn encrypted_delta_moves_pointers_both_ways_with_wraparound() {
+    use cryptmalloc::{EncryptedDelta, EncryptedPtr, Keys};
+
+    let keys = Keys::new();
+    // (address, delta): forward, backward, backward past zero, forward past u64::MAX.
+    let cases = [(4096u64, 48i64), (4096, -48), (5, -10), (u64::MAX - 1, 5)];
+    for (addr, delta) in cases {
+        let ptr = EncryptedPtr::new(keys.enc_u64(addr));
+        let enc_delta = EncryptedDelta::encrypt(&keys, delta);
+        assert_eq!(enc_delta.decrypt(&keys), delta);
+
+        let moved = enc_delta.apply_to_ptr(&ptr);
+        assert_eq!(keys.dec_u64(&moved.0), addr.wrapping_add_signed(delta));
+        let back = enc_delta.negated().apply_to_ptr(&moved);
+        assert_eq!(keys.dec_u64(&back.0), addr);
+    }
+}
+
+#[test]
+fn batched_select_matches_looped_select() {
+    use cryptmalloc::encrypted_option::{select_many, select_pairs, CipherSelectable};
+    use cryptmalloc::{EncryptedOption, EncryptedPtr, Keys};
+    use tfhe::FheUint32;
+
+    let keys = Keys::new();
+    let when_true = keys.enc_u32_many(&[1, 2, 3, 4]);
+    let when_false = keys.enc_u32_many(&[10, 20, 30, 40]);
+    for (cond, expected) in [(true, [1, 2, 3, 4]), (false, [10, 20, 30, 40])] {
+        let cond = if cond {
+            keys.enc_true()
+        } else {
+            keys.enc_false()
+        };
+        let looped: Vec<u32> = when_true
+            .iter()
+            .zip(&when_false)
+            .map(|(t, f)| keys.dec_u32(&<FheUint32 as CipherSelectable>::select(&cond, t, f)))
+            .collect();
+        let batched: Vec<u32> = select_many(&cond, &when_true, &when_false)
+            .unwrap()
+            .iter()
+            .map(|ct| keys.dec_u32(ct))
+            .collect();
+        assert_eq!(batched, looped);
+        assert_eq!(batched, expected);
+
+        let pairs: Vec<_> = when_true
+            .iter()
+            .cloned()
+            .zip(when_false.iter().cloned())
+            .collect();
+        let paired: Vec<u32> = select_pairs(&cond, &pairs)
+            .iter()
+            .map(|ct| keys.dec_u32(ct))
+            .collect();
+        assert_eq!(paired, expected);
+    }
+    assert!(select_many(&keys.enc_true(), &when_true, &when_false[..3]).is_none());
+
+    let options: Vec<EncryptedOption<EncryptedPtr>> = [(16, true), (32, false), (48, true)]
+        .into_iter()
+        .map(|(offset, some)| EncryptedOption {
+            value: EncryptedPtr::new(keys.enc_u64(offset)),
+            is_some: if some {
+                keys.enc_true()
+            } else {
+                keys.enc_false()
+            },
+        })
+        .collect();
+    let combined = EncryptedOption::combine_many(&options).unwrap();
+    let chained = options[0]
+        .combine_with(&options[1])
+        .combine_with(&options[2]);
+    assert_eq!(
+        keys.dec_u64(&combined.value.0),
+        keys.dec_u64(&chained.value.0)
+    );
+    assert_eq!(keys.dec_u64(&combined.value.0), 48);
+    assert!(keys.dec_bool(&combined.is_some));
+    assert!(EncryptedOption::<EncryptedPtr>::combine_many(&[]).is_none());
+}
+
+#[test]
+fn tier_rounder_predicts_routing_at_boundaries() {
+    use cryptmalloc::{Keys, TierRounder};
+
+    let keys = Keys::new();
+    let rounder = TierRounder::new(&keys, [16, 32, 64, 128, 256]);
+    assert_eq!(
+        format!("{rounder:?}"),
+        "TierRounder { block_sizes: [16, 32, 64, 128, 256] }"
+    );
+    // (size, rounded, tier index, padding); 0 and sub-minimum sizes round to the smallest tier like routing does.
+    let cases = [
+        (0u64, 16u64, 0u32, 16u64),
+        (16, 16, 0, 0),
+        (17, 32, 1, 15),
+        (256, 256, 4, 0),
+        (257, 257, 5, 0),
+    ];
+    for (size, rounded, index, padding) in cases {
+        let enc_size = keys.enc_u64(size);
+        assert_eq!(
+            keys.dec_u64(&rounder.round_up(&enc_size)),
+            rounded,
+            "{size}"
+        );
+        assert_eq!(
+            keys.dec_u32(&rounder.tier_index(&enc_size)),
+            index,
+            "{size}"
+        );
+        assert_eq!(
+            keys.dec_u64(&rounder.padding_for(&enc_size)),
+            padding,
+            "{size}"
+        );
+    }
+
+    let confidential =
+        TierRounder::from_encrypted(&keys, [16, 32, 64, 128, 256].map(|s| keys.enc_u64(s)));
+    assert_eq!(
+        format!("{confidential:?}"),
+        "TierRounder { block_sizes: \"<ciphertext>\" }"
+    );
+    assert_eq!(
+        keys.dec_u64(&confidential.round_up(&keys.enc_u64(100))),
+        128
+    );
+
+    // the allocator's own rounder agrees with where allocate sends the request.
+    let tiers = [(16, 1), (32, 1), (64, 1), (128, 1), (256, 1)];
+    let mut alloc = CryptMalloc::builder(512)
+        .keys(keys.clone())
+        .layout(cryptmalloc::Layout::contiguous(&tiers, 512))
+        .build()
+        .unwrap();
+    let enc_size = keys.enc_u64(40);
+    assert_eq!(keys.dec_u32(&alloc.rounder().tier_index(&enc_size)), 2);
+    let granted = alloc.allocate(enc_size);
+    assert_eq!(keys.dec_u64(&granted.value.0), 48);
+}
+
+#[test]
+fn read_only_view_tracks_allocator_state() {
+    use cryptmalloc::Keys;
+
+    let keys = Keys::new();
+    let tiers = [(16, 1), (32, 1), (64, 1), (128, 1), (256, 1)];
+    let mut alloc = CryptMalloc::builder(512)
+        .keys(keys.clone())
+        .layout(cryptmalloc::Layout::contiguous(&tiers, 512))
+        .name("observed")
+        .build()
+        .unwrap();
+
+    let reader = alloc.read_only();
+    assert_eq!(reader.name(), "observed");
+    assert_eq!(reader.metrics().allocations, 0);
+    assert_eq!(reader.slabs().len(), 5);
+    let free_before: Vec<bool> = reader
+        .capacity_proofs(&[])
+        .iter()
+        .map(|flag| keys.dec_bool(flag))
+        .collect();
+    assert_eq!(free_before, [true; 5]);
+
+    let granted = alloc.allocate(keys.enc_u64(16));
+    assert!(keys.dec_bool(&granted.is_some));
+
+    // a fresh view sees the allocation made through the owning handle.
+    let reader = alloc.read_only();
+    assert_eq!(reader.metrics().allocations, 1);
+    assert!(reader.metrics_text().contains("allocations"));
+    assert_eq!(
+        reader.report(&keys).unwrap().tiers[0].allocated,
+        vec![Some(true)]
+    );
+    assert!(!keys.dec_bool(&reader.slabs()[0].has_free()));
+}
+
+#[test]
+fn encrypted_prng_is_deterministic_and_roughly_balanced() {
+    use cryptmalloc::{EncryptedPrng, Keys};
+
+    let keys = Keys::new();
+    let seed = 0x9e37_79b9_7f4a_7c15u64;
+    let mut first = EncryptedPrng::new(keys.enc_u64(seed));
+    let mut second = EncryptedPrng::new(keys.enc_u64(seed));
+
+    // plaintext xorshift64 reference for the same seed.
+    let mut reference = seed;
+    let mut step = move || {
+        reference ^= reference << 13;
+        reference ^= reference >> 7;
+        reference ^= reference << 17;
+        reference
+    };
+    for _ in 0..3 {
+        let a = keys.dec_u64(&first.next_bits(64));
+        assert_eq!(a, keys.dec_u64(&second.next_bits(64)));
+        assert_eq!(a, step());
+    }
+    assert_eq!(keys.dec_u64(&first.next_bits(8)), step() >> 56);
+    let below = keys.dec_u64(&first.next_below(16));
+    assert_eq!(below, step() >> 60);
+    assert!(below < 16);
+    assert_eq!(keys.dec_u64(&first.next_below(1)), 0);
+    step();
+    assert_eq!(first.draws(), 6);
+
+    let ones = (0..128)
+        .filter(|_| keys.dec_bool(&first.next_bool()))
+        .count();
+    assert!((40..=88).contains(&ones), "{ones} of 128 draws were true");
+}
+
+#[test]
+fn builder_installs_the_oblivious_rng() {
+    use cryptmalloc::{EncryptedPrng, Keys};
+
+    let keys = Keys::new();
+    let seed = 0x9e37_79b9_7f4a_7c15u64;
+    let tiers = [(16, 1), (32, 1), (64, 1), (128, 1), (256, 1)];
+    let mut plain = CryptMalloc::builder(512)
+        .keys(keys.clone())
+        .tiers(&tiers)
+        .build()
+        .unwrap();
+    assert!(plain.oblivious_rng().is_none());
+
+    let mut alloc = CryptMalloc::builder(512)
+        .keys(keys.clone())
+        .tiers(&tiers)
+        .oblivious_rng(keys.enc_u64(seed))
+        .build()
+        .unwrap();
+    let mut standalone = EncryptedPrng::new(keys.enc_u64(seed));
+    let rng = alloc.oblivious_rng().unwrap();
+    assert_eq!(
+        keys.dec_u64(&rng.next_bits(64)),
+        keys.dec_u64(&standalone.next_bits(64))
+    );
+    assert_eq!(rng.draws(), 1);
+}
+
+#[test]
+fn planned_layouts_keep_tiers_self_aligned() {
+    use cryptmalloc::layout::{ARENA_ALIGNMENT, DEFAULT_TIERS};
+    use cryptmalloc::{CryptMallocBuilder, Keys, Layout};
+
+    // 16 x 1000 leaves tier 4 at an odd multiple of 128 unless padding is inserted.
+    let awkward = [(16, 1000), (32, 512), (64, 256), (128, 128), (256, 64)];
+    assert_eq!(Layout::contiguous(&awkward, 4096).misaligned_tiers(), [4]);
+    let planned = CryptMallocBuilder::new(4096).tiers(&awkward).plan_layout();
+    planned.validate().unwrap();
+    assert!(planned.misaligned_tiers().is_empty());
+    let offsets: Vec<u64> = planned.tiers.iter().map(|t| t.base_offset).collect();
+    assert_eq!(offsets, [0, 16000, 32384, 48768, 65280]);
+    assert_eq!(planned.arena_start % ARENA_ALIGNMENT, 0);
+    assert_eq!(planned.arena_start, 81920);
+    assert_eq!(planned.arena_size(), 4096);
+
+    let randomized = CryptMallocBuilder::new(4096)
+        .tiers(&awkward)
+        .randomize_layout(Some(3))
+        .plan_layout();
+    randomized.validate().unwrap();
+    assert!(randomized.misaligned_tiers().is_empty());
+    assert_eq!(randomized.arena_start % ARENA_ALIGNMENT, 0);
+
+    // the default tiers were already aligned, so planning them is unchanged.
+    assert_eq!(
+        CryptMallocBuilder::new(4096).plan_layout(),
+        Layout::contiguous(&DEFAULT_TIERS, 4096)
+    );
+
+    let keys = Keys::new();
+    let mut alloc = CryptMalloc::builder(512)
+        .keys(keys.clone())
+        .tiers(&[(16, 1), (32, 1), (64, 1), (128, 1), (256, 1)])
+        .build()
+        .unwrap();
+    let alignments: Vec<u64> = alloc.slabs().iter().map(|s| s.alignment()).collect();
+    assert_eq!(alignments, [16, 32, 64, 128, 256]);
+    let granted = alloc.allocate(keys.enc_u64(40));
+    let offset = keys.dec_u64(&granted.value.0);
+    assert_eq!(offset, 64);
+    assert_eq!(offset % alloc.slabs()[2].alignment(), 0);
+}
+
+#[test]
+fn tier_reservations_keep_a_tenants_share_free() {
+    use cryptmalloc::layout::LayoutError;
+    use cryptmalloc::Keys;
+
+    let tiers = [(16, 4), (32, 1), (64, 1), (128, 1), (256, 1)];
+    let over = CryptMalloc::builder(512)
+        .tiers(&tiers)
+        .reserve_tier_fraction(1, 0, 0.6)
+        .reserve_tier_fraction(2, 0, 0.6)
+        .prepare();
+    assert_eq!(
+        over.err(),
+        Some(LayoutError::Reservation { tenant: 2, tier: 0 })
+    );
+    let out_of_range = CryptMalloc::builder(512)
+        .reserve_tier_fraction(1, 5, 0.5)
+        .prepare();
+    assert_eq!(
+        out_of_range.err(),
+        Some(LayoutError::Reservation { tenant: 1, tier: 5 })
+    );
+
+    let keys = Keys::new();
+    let mut alloc = CryptMalloc::builder(512)
+        .keys(keys.clone())
+        .tiers(&tiers)
+        .reserve_tier_fraction(1, 0, 0.25)
+        .reserve_tier_fraction(2, 0, 0.25)
+        .build()
+        .unwrap();
+    let arena_start = alloc.layout().arena_start;
+
+    // tenant 1 fills its one reserved block, then the two unreserved ones, and only then spills to the arena.
+    let mut tickets = Vec::new();
+    let mut offsets = Vec::new();
+    for _ in 0..4 {
+        let (granted, ticket) = alloc.allocate_for(1, keys.enc_u64(16));
+        assert!(keys.dec_bool(&granted.is_some));
+        offsets.push(keys.dec_u64(&granted.value.0));
+        tickets.push((ticket, granted.value));
+    }
+    assert_eq!(offsets, [0, 16, 32, arena_start]);
+
+    // tenant 2 still gets its reserved block; tenant 3 has no reservation and the unreserved share is gone.
+    let (granted, ticket) = alloc.allocate_for(2, keys.enc_u64(16));
+    assert_eq!(keys.dec_u64(&granted.value.0), 48);
+    assert_eq!(ticket.tenant(), 2);
+    let (granted, _) = alloc.allocate_for(3, keys.enc_u64(16));
+    assert!(keys.dec_u64(&granted.value.0) >= arena_start);
+
+    // a block tenant 1 took from the unreserved share goes back to that share.
+    let (ticket, ptr) = tickets.remove(1);
+    assert!(keys.dec_bool(&alloc.free_for(ticket, &ptr)));
+    let (granted, _) = alloc.allocate_for(3, keys.enc_u64(16));
+    assert_eq!(keys.dec_u64(&granted.value.0), 16);
+
+    // and its reserved block goes back to its reservation.
+    let (ticket, ptr) = tickets.remove(0);
+    assert!(keys.dec_bool(&alloc.free_for(ticket, &ptr)));
+    let (granted, _) = alloc.allocate_for(1, keys.enc_u64(16));
+    assert_eq!(keys.dec_u64(&granted.value.0), 0);
+}
+
+#[test]
+fn repeated_free_for_refunds_nothing() {
+    use cryptmalloc::Keys;
+
+    let tiers = [(16, 2), (32, 1), (64, 1), (128, 1), (256, 1)];
+    let keys = Keys::new();
+    let mut alloc = CryptMalloc::builder(512)
+        .keys(keys.clone())
+        .tiers(&tiers)
+        .reserve_tier_fraction(1, 0, 0.5)
+        .build()
+        .unwrap();
+
+    let (first, first_ticket) = alloc.allocate_for(1, keys.enc_u64(16));
+    let (second, second_ticket) = alloc.allocate_for(1, keys.enc_u64(16));
+    assert_eq!(keys.dec_u64(&first.value.0), 0);
+    assert_eq!(keys.dec_u64(&second.value.0), 16);
+
+    // the second free of the same pointer reclaims nothing, so the stale ticket refunds nothing either.
+    assert!(keys.dec_bool(&alloc.free_for(first_ticket, &first.value)));
+    assert!(!keys.dec_bool(&alloc.free_for(second_ticket, &first.value)));
+
+    let (granted, _) = alloc.allocate_for(1, keys.enc_u64(16));
+    assert!(keys.dec_bool(&granted.is_some));
+    assert_eq!(keys.dec_u64(&granted.value.0), 0);
+
+    // tenant 1 now holds its reserved half and the unreserved half; a reset hands both back without any free_for.
+    alloc.reset();
+    let offsets: Vec<u64> = (0..2)
+        .map(|_| keys.dec_u64(&alloc.allocate_for(1, keys.enc_u64(16)).0.value.0))
+        .collect();
+    assert_eq!(offsets, [0, 16]);
+    let (spilled, _) = alloc.allocate_for(1, keys.enc_u64(16));
+    assert!(keys.dec_u64(&spilled.value.0) >= alloc.layout().arena_start);
+}
+
+#[test]
+fn evm_program_builder_resolves_labels_and_sizes_the_evm() {
+    use cryptmalloc::evm::EvmError;
+    use cryptmalloc::evm_program::{JUMP, JUMPDEST, PUSH1, STOP};
+    use cryptmalloc::{Category, CryptmallocError, EvmProgram, EvmProgramBuilder, Keys, EVM};
+
+    let mut builder = EvmProgramBuilder::new();
+    let end = builder.label();
+    builder
+        .push1(2)
+        .push1(3)
+        .add()
+        .jump(end)
+        .push1(99)
+        .jumpdest(end)
+        .push1(0)
+        .mstore()
+        .stop();
+    let program = builder.build().unwrap();
+    assert_eq!(
+        program.bytecode(),
+        [0x60, 2, 0x60, 3, 0x01, 0x60, 10, 0x56, 0x60, 99, 0x5b, 0x60, 0, 0x52, 0x00]
+    );
+    assert_eq!(program.pc_table_len(), 15);
+    assert_eq!(program.memory_slots(), 1);
+
+    let mut dangling = EvmProgramBuilder::new();
+    let nowhere = dangling.label();
+    dangling.jump(nowhere).stop();
+    let err = dangling.build().unwrap_err();
+    assert_eq!(err, EvmError::DanglingLabel { label: 0 });
+    assert_eq!(CryptmallocError::from(err).category(), Category::Usage);
+
+    let mut rebound = EvmProgramBuilder::new();
+    let twice = rebound.label();
+    rebound.jumpdest(twice).jumpdest(twice);
+    assert_eq!(
+        rebound.build().unwrap_err(),
+        EvmError::LabelRebound { label: 0 }
+    );
+
+    // labels from another builder are dangling whether jumped to or bound.
+    let mut other = EvmProgramBuilder::new();
+    other.label();
+    let foreign = other.label();
+    let mut jumps_away = EvmProgramBuilder::new();
+    jumps_away.jump(foreign).stop();
+    assert_eq!(
+        jumps_away.build().unwrap_err(),
+        EvmError::DanglingLabel { label: 1 }
+    );
+    let mut binds_away = EvmProgramBuilder::new();
+    binds_away.jumpdest(foreign).stop();
+    assert_eq!(
+        binds_away.build().unwrap_err(),
+        EvmError::DanglingLabel { label: 1 }
+    );
+
+    // ...even when this builder has a label with the same index.
+    let mut theirs = EvmProgramBuilder::new();
+    let their_start = theirs.label();
+    let mut jumps_across = EvmProgramBuilder::new();
+    let start = jumps_across.label();
+    jumps_across.jumpdest(start).jump(their_start).stop();
+    assert_eq!(
+        jumps_across.build().unwrap_err(),
+        EvmError::DanglingLabel { label: 0 }
+    );
+    let mut binds_across = EvmProgramBuilder::new();
+    let start = binds_across.label();
+    binds_across.jumpdest(start).jumpdest(their_start).stop();
+    assert_eq!(
+        binds_across.build().unwrap_err(),
+        EvmError::DanglingLabel { label: 0 }
+    );
+
+    // PUSH1 can only reach the first 256 bytes.
+    let mut far = EvmProgramBuilder::new();
+    let past = far.label();
+    far.jump(past);
+    for _ in 0..300 {
+        far.stop();
+    }
+    far.jumpdest(past);
+    assert_eq!(
+        far.build().unwrap_err(),
+        EvmError::BadJumpTarget { pc: 2, target: 303 }
+    );
+
+    assert_eq!(
+        EvmProgram::from_bytecode(vec![STOP, PUSH1]).unwrap_err(),
+        EvmError::ImmediateMissing { pc: 1 }
+    );
+    assert_eq!(
+        EvmProgram::from_bytecode(vec![PUSH1, 4, JUMP, STOP, STOP]).unwrap_err(),
+        EvmError::BadJumpTarget { pc: 2, target: 4 }
+    );
+    assert!(EvmProgram::from_bytecode(vec![PUSH1, 3, JUMP, JUMPDEST]).is_ok());
+
+    let keys = Keys::new();
+    let mut evm = EVM::from_program(program, &keys).unwrap();
+    let debug = format!("{evm:?}");
+    assert!(debug.contains("program_len: 15, memory_len: 1"), "{debug}");
+    evm.push_input(keys.enc_u64(5));
+    assert_eq!(keys.dec_u32(evm.stack_len()), 1);
+}
+
+#[test]
+fn nonce_window_evicts_least_recently_seen() {
+    use cryptmalloc::NonceWindow;
+
+    let mut window = NonceWindow::new(2);
+    window.insert(1, "a");
+    window.insert(2, "b");
+    assert_eq!(window.get(1), Some("a"));
+    window.insert(3, "c");
+    assert!(window.contains(1) && window.contains(3));
+    assert!(!window.contains(2));
+    assert_eq!(window.len(), 2);
+
+    // a miss, the usual first delivery, leaves the window byte-for-byte as it was.
+    let untouched = window.clone();
+    assert_eq!(window.get(9), None);
+    assert_eq!(
+        bincode::serialize(&window).unwrap(),
+        bincode::serialize(&untouched).unwrap()
+    );
+
+    let mut disabled = NonceWindow::new(0);
+    disabled.insert(1, "a");
+    assert!(disabled.is_empty());
+}
+
+#[test]
+fn duplicate_free_requests_apply_once() {
+    use cryptmalloc::envelope::{open, seal};
+    use cryptmalloc::protocol::{decode, ClientReply, Request};
+    use cryptmalloc::{ClientEndpoint, Keys, NonceWindow, ServerEndpoint};
+
+    let keys = Keys::new();
+    let mut server = ServerEndpoint::new(
+        keys.server_key(),
+        CryptMalloc::builder(512)
+            .tiers(&[(16, 1), (32, 1), (64, 1), (128, 1), (256, 1)])
+            .nonce_window(8),
+    )
+    .unwrap();
+    let mut client = ClientEndpoint::new(keys).unwrap();
+    let allocated = |reply| match reply {
+        ClientReply::Allocated { offset, .. } => offset,
+        other => panic!("expected an allocation, got {other:?}"),
+    };
+
+    let response = server.handle(&client.alloc_request(16).unwrap()).unwrap();
+    assert_eq!(
+        allocated(client.decrypt_response(&response).unwrap()),
+        Some(0)
+    );
+
+    // at-least-once delivery: the same bytes arrive twice.
+    let free = client.free_request(0).unwrap();
+    let first = server.handle(&free).unwrap();
+    let second = server.handle(&free).unwrap();
+    assert_eq!(first, second);
+    assert!(matches!(
+        client.decrypt_response(&first).unwrap(),
+        ClientReply::Freed {
+            reclaimed: true,
+            ..
+        }
+    ));
+    assert_eq!(server.allocator().metrics().frees, 1);
+
+    // the same offset freed again later under a fresh nonce is applied.
+    let response = server.handle(&client.alloc_request(16).unwrap()).unwrap();
+    assert_eq!(
+        allocated(client.decrypt_response(&response).unwrap()),
+        Some(0)
+    );
+    let again = client.free_request(0).unwrap();
+    let response = server.handle(&again).unwrap();
+    assert!(matches!(
+        client.decrypt_response(&response).unwrap(),
+        ClientReply::Freed {
+            reclaimed: true,
+            ..
+        }
+    ));
+    assert_eq!(server.allocator().metrics().frees, 2);
+
+    // the window survives a seal/open round trip with both nonces.
+    let nonce = |bytes: &[u8]| match decode::<Request>(bytes).unwrap() {
+        Request::Free(req) => req.nonce,
+        _ => panic!("expected a free request"),
+    };
+    assert_ne!(nonce(&free), nonce(&again));
+    let restored: NonceWindow<_> = open(&seal(server.allocator().nonce_window()).unwrap()).unwrap();
+    assert_eq!(restored.capacity(), 8);
+    assert!(restored.contains(nonce(&free)) && restored.contains(nonce(&again)));
+
+    // a restarted server with a smaller window keeps only the most recent nonce and still answers its replay.
+    let mut restarted = ServerEndpoint::new(
+        client.keys().server_key(),
+        CryptMalloc::builder(512)
+            .tiers(&[(16, 1), (32, 1), (64, 1), (128, 1), (256, 1)])
+            .nonce_window(1),
+    )
+    .unwrap();
+    restarted.restore_nonce_window(restored).unwrap();
+    let window = restarted.allocator().nonce_window();
+    assert_eq!(window.capacity(), 1);
+    assert!(window.contains(nonce(&again)) && !window.contains(nonce(&free)));
+    assert_eq!(restarted.handle(&again).unwrap(), response);
+    assert_eq!(restarted.allocator().metrics().frees, 0);
 }
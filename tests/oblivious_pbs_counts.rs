@@ -0,0 +1,35 @@
+//! Counts tfhe bootstraps around CryptMalloc::allocate_oblivious to pin that a granted, a zero-size and an exhausted request do the same ciphertext work.
+//! The counter is process-wide, so this file holds a single test and runs in its own binary.
+
+use cryptmalloc::{CryptMalloc, Keys, ObliviousResult};
+
+// allocates `size` and returns the decrypted ok flag with the bootstraps the request took.
+fn counted_allocate(alloc: &mut CryptMalloc, keys: &Keys, size: u64) -> (bool, u64) {
+    let size = keys.enc_u64(size);
+    tfhe::reset_pbs_count();
+    let ObliviousResult { ok, .. } = alloc.allocate_oblivious(size);
+    let pbs = tfhe::get_pbs_count();
+    (keys.dec_bool(&ok), pbs)
+}
+
+#[test]
+fn granted_zero_and_exhausted_oblivious_allocations_bootstrap_equally() {
+    let keys = Keys::new();
+    let mut alloc = CryptMalloc::builder(512)
+        .keys(keys.clone())
+        .tiers(&[(16, 1), (32, 1), (64, 1), (128, 1), (256, 1)])
+        .build()
+        .unwrap();
+
+    let (granted, granted_pbs) = counted_allocate(&mut alloc, &keys, 16);
+    assert!(granted);
+    let (zero, zero_pbs) = counted_allocate(&mut alloc, &keys, 0);
+    assert!(!zero);
+    // the 16-byte tier's only block is taken, and small requests never spill into the arena.
+    let (exhausted, exhausted_pbs) = counted_allocate(&mut alloc, &keys, 16);
+    assert!(!exhausted);
+
+    assert!(granted_pbs > 0);
+    assert_eq!(zero_pbs, granted_pbs);
+    assert_eq!(exhausted_pbs, granted_pbs);
+}
@@ -0,0 +1,53 @@
+#![cfg(feature = "pool-registry")]
+
+use cryptmalloc::{registry, CryptMalloc, Keys};
+
+#[test]
+fn registry_tracks_live_allocators_without_owning_them() {
+    let keys = Keys::new();
+    let tiny = [(16, 1), (32, 1), (64, 1), (128, 1), (256, 1)];
+    let build = |name: &str, arena_size: u64| {
+        CryptMalloc::builder(arena_size)
+            .keys(keys.clone())
+            .tiers(&tiny)
+            .register(name)
+            .build()
+            .unwrap()
+    };
+    let registered = |name: &str| {
+        registry::live_pools()
+            .into_iter()
+            .filter(|pool| pool.name == name)
+            .collect::<Vec<_>>()
+    };
+
+    let mut first = build("registry-first", 512);
+    let second = build("registry-second", 1024);
+
+    let first_info = registered("registry-first");
+    assert_eq!(first_info.len(), 1);
+    assert_eq!(
+        first_info[0].config,
+        "tiers=16x1,32x1,64x1,128x1,256x1 arena=512"
+    );
+    assert_eq!(
+        registered("registry-second")[0].config,
+        "tiers=16x1,32x1,64x1,128x1,256x1 arena=1024"
+    );
+    assert!(first_info[0].created_at <= registered("registry-second")[0].created_at);
+
+    first.reset();
+    assert_eq!(registered("registry-first")[0].metrics.resets, 1);
+    assert!(registry::report().contains("registry-first: tiers="));
+
+    drop(second);
+    assert!(registered("registry-second").is_empty());
+    assert_eq!(registered("registry-first").len(), 1);
+
+    // re-registering replaces the entry rather than adding a second one.
+    first.register("registry-renamed");
+    assert!(registered("registry-first").is_empty());
+    assert_eq!(registered("registry-renamed").len(), 1);
+    drop(first);
+    assert!(registered("registry-renamed").is_empty());
+}
@@ -0,0 +1,111 @@
+//! Pins the thread-safety of every public type: each one is expected to be Send + Sync, so a field change that silently drops an auto trait fails to compile here.
+
+use cryptmalloc::{
+    allocator::{AllocatorReader, CryptMalloc, CryptMallocBuilder, PreparedAllocator, TierAudit},
+    arena::Arena,
+    encrypted_bitset::{BitsetOp, EncryptedBitset},
+    encrypted_delta::EncryptedDelta,
+    encrypted_heap::EncryptedHeap,
+    encrypted_option::EncryptedOption,
+    encrypted_prng::EncryptedPrng,
+    encrypted_ptr::EncryptedPtr,
+    envelope::EnvelopeError,
+    error::{Category, CryptmallocError},
+    evm::{EvmError, EVM},
+    evm_program::{EvmProgram, EvmProgramBuilder, Label},
+    keys::Keys,
+    layout::{Layout, LayoutError, TierPlacement},
+    metrics::{AllocatorMetrics, MetricsRegistry},
+    nonce_window::NonceWindow,
+    oblivious_result::ObliviousResult,
+    protocol::{ClientEndpoint, ClientReply, ProtocolError, Request, Response, ServerEndpoint},
+    report::{AllocatorReport, ArenaReport, ReportError, TierReport},
+    ring_arena::{RingAllocation, RingArena},
+    size_blinder::SizeBlinder,
+    slab::{IndexWidth, SlabClass, SlabOptions, WriteBack},
+    tenancy::AllocationTicket,
+    tier_rounder::TierRounder,
+};
+
+fn assert_send_sync<T: Send + Sync>() {}
+
+#[test]
+fn public_types_are_send_and_sync() {
+    assert_send_sync::<CryptMalloc>();
+    assert_send_sync::<CryptMallocBuilder>();
+    assert_send_sync::<AllocatorReader<'static>>();
+    assert_send_sync::<PreparedAllocator>();
+    assert_send_sync::<TierAudit>();
+    assert_send_sync::<Arena>();
+    assert_send_sync::<RingArena>();
+    assert_send_sync::<RingAllocation>();
+    assert_send_sync::<SizeBlinder>();
+    assert_send_sync::<SlabClass>();
+    assert_send_sync::<TierRounder>();
+    assert_send_sync::<NonceWindow<tfhe::FheBool>>();
+    assert_send_sync::<AllocationTicket>();
+    assert_send_sync::<SlabOptions>();
+    assert_send_sync::<IndexWidth>();
+    assert_send_sync::<WriteBack>();
+    assert_send_sync::<EncryptedBitset>();
+    assert_send_sync::<EncryptedDelta>();
+    assert_send_sync::<BitsetOp>();
+    assert_send_sync::<EncryptedHeap>();
+    assert_send_sync::<EncryptedPtr>();
+    assert_send_sync::<EncryptedPrng>();
+    assert_send_sync::<EncryptedOption<EncryptedPtr>>();
+    assert_send_sync::<ObliviousResult<EncryptedPtr>>();
+    assert_send_sync::<EVM>();
+    assert_send_sync::<EvmError>();
+    assert_send_sync::<EvmProgram>();
+    assert_send_sync::<EvmProgramBuilder>();
+    assert_send_sync::<Label>();
+    assert_send_sync::<Keys>();
+    assert_send_sync::<Layout>();
+    assert_send_sync::<TierPlacement>();
+    assert_send_sync::<LayoutError>();
+    assert_send_sync::<EnvelopeError>();
+    assert_send_sync::<CryptmallocError>();
+    assert_send_sync::<Category>();
+    assert_send_sync::<AllocatorMetrics>();
+    assert_send_sync::<AllocatorReport>();
+    assert_send_sync::<TierReport>();
+    assert_send_sync::<ArenaReport>();
+    assert_send_sync::<ReportError>();
+    assert_send_sync::<MetricsRegistry<'static>>();
+    assert_send_sync::<Request>();
+    assert_send_sync::<Response>();
+    assert_send_sync::<ClientReply>();
+    assert_send_sync::<ProtocolError>();
+    assert_send_sync::<ClientEndpoint>();
+    assert_send_sync::<ServerEndpoint>();
+}
+
+#[cfg(feature = "failpoints")]
+#[test]
+fn failpoint_types_are_send_and_sync() {
+    use cryptmalloc::failpoints::{FailPoint, FailPolicy, Failpoints};
+
+    assert_send_sync::<FailPoint>();
+    assert_send_sync::<FailPolicy>();
+    assert_send_sync::<Failpoints>();
+}
+
+#[cfg(feature = "pool-registry")]
+#[test]
+fn registry_types_are_send_and_sync() {
+    assert_send_sync::<cryptmalloc::registry::PoolInfo>();
+}
+
+#[cfg(feature = "ffi")]
+#[test]
+fn ffi_handles_are_send_and_sync() {
+    assert_send_sync::<cryptmalloc::ffi::CmKeys>();
+}
+
+#[cfg(feature = "simulation")]
+#[test]
+fn simulated_heap_is_send_and_sync() {
+    assert_send_sync::<cryptmalloc::simulation::SimulatedHeap>();
+    assert_send_sync::<cryptmalloc::simulation::SimulationError>();
+}
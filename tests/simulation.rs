@@ -0,0 +1,214 @@
+#![cfg(feature = "simulation")]
+
+use cryptmalloc::simulation::SimulatedHeap;
+use std::alloc::{GlobalAlloc, Layout};
+
+#[test]
+fn simulated_heap_round_trips_through_the_allocator() {
+    let heap = SimulatedHeap::new(4096);
+    let layouts = [
+        Layout::from_size_align(8, 8).unwrap(),
+        Layout::from_size_align(24, 16).unwrap(),
+        Layout::from_size_align(100, 64).unwrap(),
+        Layout::from_size_align(600, 8).unwrap(),
+    ];
+
+    let mut live = Vec::new();
+    for (fill, layout) in layouts.iter().enumerate() {
+        let ptr = unsafe { heap.alloc(*layout) };
+        assert!(!ptr.is_null());
+        assert_eq!(ptr as usize % layout.align(), 0);
+        unsafe { ptr.write_bytes(fill as u8 + 1, layout.size()) };
+        live.push((ptr, *layout, fill as u8 + 1));
+    }
+    assert_eq!(heap.live_allocations(), layouts.len());
+
+    for (ptr, layout, fill) in live.iter() {
+        let bytes = unsafe { std::slice::from_raw_parts(*ptr, layout.size()) };
+        assert!(bytes.iter().all(|byte| byte == fill));
+    }
+    for (ptr, layout, _) in live {
+        unsafe { heap.dealloc(ptr, layout) };
+    }
+    assert_eq!(heap.live_allocations(), 0);
+
+    // every slab cell is free again; the 600-byte value consumed arena space, which is never reclaimed.
+    heap.inspect(|allocator| {
+        for slab in allocator.slabs() {
+            for cell in slab.bitmap() {
+                assert!(!allocator.keys().dec_bool(cell));
+            }
+        }
+        assert!(allocator.keys().dec_u64(allocator.arena().cursor()) > allocator.layout().arena_start);
+    });
+}
+
+#[test]
+fn simulated_heap_reuses_tiny_tiers_and_refuses_oversized_layouts() {
+    use cryptmalloc::CryptMalloc;
+
+    let heap = SimulatedHeap::with_allocator(
+        CryptMalloc::builder(512)
+            .tiers(&[(16, 1), (32, 1), (64, 1), (128, 1), (256, 1)])
+            .build()
+            .unwrap(),
+    )
+    .unwrap();
+    let small = Layout::from_size_align(8, 8).unwrap();
+
+    // the one 16-byte block is handed out, written through and freed again on every round.
+    let first = unsafe { heap.alloc(small) };
+    assert!(!first.is_null());
+    unsafe { heap.dealloc(first, small) };
+    for fill in 1..=3u8 {
+        let ptr = unsafe { heap.alloc(small) };
+        assert_eq!(ptr, first);
+        unsafe { ptr.write_bytes(fill, small.size()) };
+        assert!(unsafe { std::slice::from_raw_parts(ptr, small.size()) }
+            .iter()
+            .all(|byte| *byte == fill));
+        unsafe { heap.dealloc(ptr, small) };
+    }
+
+    let oversized = Layout::from_size_align(isize::MAX as usize - 15, 16).unwrap();
+    assert!(unsafe { heap.alloc(oversized) }.is_null());
+    assert_eq!(heap.live_allocations(), 0);
+}
+
+#[test]
+fn simulated_heap_rejects_allocators_it_cannot_decrypt_or_size() {
+    use cryptmalloc::simulation::SimulationError;
+    use cryptmalloc::{CryptMalloc, Keys, SlabClass};
+
+    const TIERS: [(usize, usize); 5] = [(16, 1), (32, 1), (64, 1), (128, 1), (256, 1)];
+    let keys = Keys::new();
+
+    let server_only = CryptMalloc::builder(512)
+        .keys(Keys::server_only(keys.server_key()))
+        .tiers(&TIERS)
+        .build()
+        .unwrap();
+    assert_eq!(
+        SimulatedHeap::with_allocator(server_only).unwrap_err(),
+        SimulationError::ServerOnlyKeys
+    );
+
+    let mut base = 0;
+    let slabs = TIERS
+        .iter()
+        .map(|&(block_size, num_blocks)| {
+            let slab = SlabClass::new_confidential(
+                keys.enc_u64(block_size as u64),
+                num_blocks,
+                keys.enc_u64(base),
+                keys.server_key(),
+                keys.enc_false(),
+                keys.enc_true(),
+                keys.enc_zero_u32(),
+                keys.enc_zero_u64(),
+                keys.build_enc_indices_u32(num_blocks),
+                keys.build_enc_offsets_u64(num_blocks, block_size),
+            );
+            base += (block_size * num_blocks) as u64;
+            slab
+        })
+        .collect();
+    let confidential = CryptMalloc::from_confidential_tiers(
+        keys.clone(),
+        slabs,
+        keys.enc_u64(base),
+        keys.enc_u64(base + 512),
+    )
+    .unwrap();
+    assert_eq!(
+        SimulatedHeap::with_allocator(confidential).unwrap_err(),
+        SimulationError::ConfidentialLayout
+    );
+}
+
+// random allocate/free steps of mixed sizes and alignments through the shim, keeping at most `max_live` values alive; afterwards every slab cell must be free and the arena must have advanced by exactly the large requests it served.
+fn churn(heap: &SimulatedHeap, steps: usize, max_live: usize) {
+    let arena_start = heap.inspect(|allocator| allocator.layout().arena_start);
+
+    // plaintext LCG, so the workload is the same on every run.
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    let mut next = move |bound: usize| {
+        state = state
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        ((state >> 33) as usize) % bound
+    };
+
+    let mut live: Vec<(*mut u8, Layout, u8)> = Vec::new();
+    let mut arena_bytes = 0u64;
+    for step in 0..steps {
+        if live.is_empty() || (live.len() < max_live && next(3) != 0) {
+            let align = 1 << next(5);
+            // one request in fifty is too large for the slabs and lands in the arena.
+            let size = if next(50) == 0 {
+                300 + next(300)
+            } else {
+                1 + next(257 - align)
+            };
+            let layout = Layout::from_size_align(size, align).unwrap();
+            let ptr = unsafe { heap.alloc(layout) };
+            assert!(!ptr.is_null(), "step {step}: {layout:?} was refused");
+            assert_eq!(ptr as usize % align, 0);
+            let padded = (size + align - 1) as u64;
+            if padded > 256 {
+                arena_bytes += padded;
+            }
+            let fill = step as u8;
+            unsafe { ptr.write_bytes(fill, size) };
+            live.push((ptr, layout, fill));
+        } else {
+            let (ptr, layout, fill) = live.swap_remove(next(live.len()));
+            let bytes = unsafe { std::slice::from_raw_parts(ptr, layout.size()) };
+            assert!(
+                bytes.iter().all(|byte| *byte == fill),
+                "step {step}: value was overwritten"
+            );
+            unsafe { heap.dealloc(ptr, layout) };
+        }
+        assert_eq!(heap.live_allocations(), live.len());
+    }
+    for (ptr, layout, _) in live {
+        unsafe { heap.dealloc(ptr, layout) };
+    }
+    assert_eq!(heap.live_allocations(), 0);
+
+    heap.inspect(|allocator| {
+        for slab in allocator.slabs() {
+            for cell in slab.bitmap() {
+                assert!(!allocator.keys().dec_bool(cell));
+            }
+        }
+        assert_eq!(
+            allocator.keys().dec_u64(allocator.arena().cursor()),
+            arena_start + arena_bytes
+        );
+    });
+}
+
+// steps reach GlobalAlloc::alloc and dealloc directly rather than through Box: a SimulatedHeap needs keys at construction, so it cannot be the #[global_allocator], and Box::new_in is still unstable.
+#[test]
+fn simulated_heap_keeps_its_accounting_through_a_churn() {
+    use cryptmalloc::CryptMalloc;
+
+    // two blocks per tier cover the two live values whichever tiers they land in, so the roughly 150 slab requests keep reusing the same ten cells, while the arena is sized for the few large ones.
+    let heap = SimulatedHeap::with_allocator(
+        CryptMalloc::builder(1 << 14)
+            .tiers(&[(16, 2), (32, 2), (64, 2), (128, 2), (256, 2)])
+            .build()
+            .unwrap(),
+    )
+    .unwrap();
+    churn(&heap, 300, 2);
+}
+
+// the same workload at a few thousand steps through the full default layout, which takes far longer than the default run should, so it only runs on request: `cargo test --features simulation -- --ignored`.
+#[test]
+#[ignore]
+fn simulated_heap_survives_a_churning_workload() {
+    churn(&SimulatedHeap::new(1 << 16), 3000, 64);
+}
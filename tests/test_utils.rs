@@ -0,0 +1,87 @@
+#![cfg(feature = "test-utils")]
+
+use cryptmalloc::test_utils::{
+    bitmap_mismatches, format_occupancy, occupancy_from_str, OccupancyParseError,
+};
+use cryptmalloc::{layout::Layout, Category, CryptMalloc, CryptmallocError, Keys};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+#[test]
+fn occupancy_text_round_trips_and_rejects_garbage() {
+    let text = "tier16: 10\n\n  tier32: 0  \ntier64:";
+    let occupancy = occupancy_from_str(text).unwrap();
+    assert_eq!(
+        occupancy,
+        vec![(16, vec![true, false]), (32, vec![false]), (64, vec![])]
+    );
+    assert_eq!(
+        format_occupancy(&occupancy),
+        "tier16: 10\ntier32: 0\ntier64: "
+    );
+
+    assert_eq!(
+        occupancy_from_str("tier16: 10\nslab32: 0"),
+        Err(OccupancyParseError::MissingTierPrefix { line: 2 })
+    );
+    assert_eq!(
+        occupancy_from_str("tierX: 0"),
+        Err(OccupancyParseError::BadBlockSize { line: 1 })
+    );
+    let err = CryptmallocError::from(occupancy_from_str("tierX: 0").unwrap_err());
+    assert_eq!(err.category(), Category::Usage);
+    assert_eq!(
+        occupancy_from_str("tier16: 1x"),
+        Err(OccupancyParseError::BadBit {
+            line: 1,
+            found: 'x'
+        })
+    );
+
+    assert_eq!(
+        bitmap_mismatches(&[true, false, true], &[true, true]),
+        vec![1, 2]
+    );
+}
+
+#[test]
+fn occupancy_assertions_track_allocations() {
+    let tiers = [(16, 2), (32, 2), (64, 1), (128, 1), (256, 1)];
+    let keys = Keys::new();
+    let mut alloc = CryptMalloc::builder(512)
+        .keys(keys.clone())
+        .layout(Layout::contiguous(&tiers, 512))
+        .build()
+        .unwrap();
+
+    let empty = occupancy_from_str(
+        "tier16: 00
+         tier32: 00
+         tier64: 0
+         tier128: 0
+         tier256: 0",
+    )
+    .unwrap();
+    alloc.assert_occupancy(&empty);
+
+    let granted = alloc.allocate(keys.enc_u64(20));
+    assert!(keys.dec_bool(&granted.is_some));
+    let expected = occupancy_from_str(
+        "tier16: 00
+         tier32: 10
+         tier64: 0
+         tier128: 0
+         tier256: 0",
+    )
+    .unwrap();
+    alloc.assert_occupancy(&expected);
+    assert_eq!(alloc.decrypt_occupancy(), expected);
+    alloc.slabs()[1].assert_bitmap(&keys, &[true, false]);
+
+    // a stale expectation names the tier, the differing index and both states.
+    let failure = catch_unwind(AssertUnwindSafe(|| alloc.assert_occupancy(&empty))).unwrap_err();
+    let message = failure.downcast_ref::<String>().unwrap();
+    assert_eq!(
+        message,
+        "tier32 bitmap differs at indices [0]\n  expected tier32: 00\n  actual   tier32: 10"
+    );
+}